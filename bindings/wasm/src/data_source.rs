@@ -12,6 +12,8 @@ impl DataSource for InMemoryDataSource {
         Ok(LoadResult {
             directives: directive,
             visited_files: vec![],
+            file_hashes: vec![],
+            file_appends: vec![],
         })
     }
 }
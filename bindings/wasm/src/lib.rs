@@ -83,6 +83,7 @@ pub fn parse(content: &str) -> PlayGroundParse {
                 directives: data,
                 entry: (PathBuf::from("/"), "".to_owned()),
                 visited_files: vec![],
+                file_hashes: vec![],
                 data_source: source.clone(),
             })
             .unwrap();
@@ -99,6 +100,7 @@ pub fn parse(content: &str) -> PlayGroundParse {
                 directives: data,
                 entry: (PathBuf::from("/"), "".to_owned()),
                 visited_files: vec![],
+                file_hashes: vec![],
                 data_source: source.clone(),
             })
             .unwrap();
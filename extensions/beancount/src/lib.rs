@@ -282,6 +282,7 @@ fn convert_datetime_to_date(directive: Spanned<Directive>) -> Spanned<Directive>
         Directive::Price(mut directive) => Directive::Price(convert_to_datetime!(directive)),
         Directive::Event(mut directive) => Directive::Event(convert_to_datetime!(directive)),
         Directive::Custom(mut directive) => Directive::Custom(convert_to_datetime!(directive)),
+        Directive::Query(mut directive) => Directive::Query(convert_to_datetime!(directive)),
         _ => data,
     };
     Spanned::new(data, span)
@@ -311,6 +312,7 @@ impl Beancount {
                 Directive::Price(directive) => extract_time!(directive),
                 Directive::Event(directive) => extract_time!(directive),
                 Directive::Custom(directive) => extract_time!(directive),
+                Directive::Query(directive) => extract_time!(directive),
                 _ => {}
             },
             Either::Right(beancount_onyly_directive) => match beancount_onyly_directive {
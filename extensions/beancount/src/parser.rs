@@ -553,6 +553,22 @@ fn event_body(date: Date, i: &str) -> IResult<&str, BeancountDirective> {
     ))
 }
 
+fn query_body(date: Date, i: &str) -> IResult<&str, BeancountDirective> {
+    let (i, _) = space1(i)?;
+    let (i, name) = string(i)?;
+    let (i, _) = space1(i)?;
+    let (i, query_string) = string(i)?;
+    Ok((
+        i,
+        Either::Left(Directive::Query(Query {
+            date,
+            name,
+            query_string,
+            meta: Meta::default(),
+        })),
+    ))
+}
+
 fn commodity_body(date: Date, i: &str) -> IResult<&str, BeancountDirective> {
     let (i, _) = space1(i)?;
     let (i, currency) = commodity_name(i)?;
@@ -681,6 +697,7 @@ fn dated_directive(original: &str) -> IResult<&str, BeancountDirective> {
         "event" => event_body(date, rest),
         "commodity" => commodity_body(date, rest),
         "custom" => custom_body(date, rest),
+        "query" => query_body(date, rest),
         _ => Err(nom::Err::Error(nom::error::Error::new(original, nom::error::ErrorKind::Tag))),
     }
 }
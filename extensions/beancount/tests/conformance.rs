@@ -8,13 +8,10 @@
 //! 2. `supported_language_constructs_parse` — every construct of the beancount
 //!    language surface we currently support must parse.
 //!
-//! The only remaining unsupported construct is the `query` directive
-//! (`2014-01-01 query "name" "SELECT account"`); representing it needs a
-//! first-class `Directive::Query` variant threaded through the whole codebase.
-//! Everything else on the beancount language surface — including balance
-//! tolerance `~`, the `txn` keyword, cost lot labels, total cost `{{ }}`, and
-//! `pushmeta`/`popmeta` — is supported (see `beancount_compat.rs` for the
-//! behavioural checks).
+//! The whole beancount language surface — including the `query` directive,
+//! balance tolerance `~`, the `txn` keyword, cost lot labels, total cost
+//! `{{ }}`, and `pushmeta`/`popmeta` — is supported (see `beancount_compat.rs`
+//! for the behavioural checks).
 
 use std::path::PathBuf;
 
@@ -47,6 +44,7 @@ fn supported_language_constructs_parse() {
         ("price", "2014-01-01 price USD 1.1 CAD\n"),
         ("event", "2014-01-01 event \"location\" \"Paris\"\n"),
         ("custom", "2014-01-01 custom \"budget\" Assets:Cash \"q\" 20.0 TRUE\n"),
+        ("query", "2014-01-01 query \"cash\" \"SELECT account\"\n"),
         ("transaction flag *", "2014-01-01 * \"payee\" \"narr\"\n  Assets:Cash 1 USD\n  Equity:X\n"),
         ("transaction flag !", "2014-01-01 ! \"narr\"\n  Assets:Cash 1 USD\n  Equity:X\n"),
         ("posting cost", "2014-01-01 * \"x\"\n  Assets:Cash 1 HOOL {100 USD}\n  Equity:X\n"),
@@ -152,6 +152,9 @@ export interface operations {
                   detail: {
                     [key: string]: string;
                   };
+                  unconverted: {
+                    [key: string]: string;
+                  };
                 };
                 name: string;
                 /** @enum {string} */
@@ -215,6 +218,9 @@ export interface operations {
                 detail: {
                   [key: string]: string;
                 };
+                unconverted: {
+                  [key: string]: string;
+                };
               };
               /** Format: date-time */
               date: string;
@@ -616,7 +622,7 @@ export interface operations {
               page_size: number;
               records: ({
                   /** @enum {string} */
-                  error_type: "UnbalancedTransaction" | "TransactionCannotInferTradeAmount" | "TransactionHasMultipleImplicitPosting" | "TransactionExplicitPostingHaveMultipleCommodity" | "AccountBalanceCheckError" | "AccountDoesNotExist" | "AccountClosed" | "CommodityDoesNotDefine" | "NoEnoughCommodityLot" | "CloseNonZeroAccount" | "BudgetDoesNotExist" | "DefineDuplicatedBudget" | "MultipleOperatingCurrencyDetect" | "ParseInvalidMeta";
+                  error_type: "UnbalancedTransaction" | "TransactionCannotInferTradeAmount" | "TransactionHasMultipleImplicitPosting" | "TransactionExplicitPostingHaveMultipleCommodity" | "AccountBalanceCheckError" | "AccountDoesNotExist" | "AccountClosed" | "CommodityDoesNotDefine" | "NoEnoughCommodityLot" | "CloseNonZeroAccount" | "BudgetDoesNotExist" | "DefineDuplicatedBudget" | "DefineDuplicatedQuery" | "MultipleOperatingCurrencyDetect" | "ParseInvalidMeta";
                   id: string;
                   metas: {
                     [key: string]: string;
@@ -663,6 +669,7 @@ export interface operations {
           "application/json": {
             data: {
               content: string;
+              hash: string;
               path: string;
             };
           };
@@ -681,6 +688,7 @@ export interface operations {
       content: {
         "application/json": {
           content: string;
+          hash: string;
         };
       };
     };
@@ -750,6 +758,7 @@ export interface operations {
                   /** Format: date-time */
                   datetime: string;
                   flag: string;
+                  hash: string;
                   /** Format: uuid */
                   id: string;
                   is_balanced: boolean;
@@ -963,6 +972,9 @@ export interface operations {
                   detail: {
                     [key: string]: string;
                   };
+                  unconverted: {
+                    [key: string]: string;
+                  };
                 };
               };
               changes: {
@@ -975,6 +987,9 @@ export interface operations {
                     detail: {
                       [key: string]: string;
                     };
+                    unconverted: {
+                      [key: string]: string;
+                    };
                   };
                 };
               };
@@ -1010,6 +1025,9 @@ export interface operations {
                 detail: {
                   [key: string]: string;
                 };
+                unconverted: {
+                  [key: string]: string;
+                };
               };
               expense: {
                 calculated: {
@@ -1019,6 +1037,9 @@ export interface operations {
                 detail: {
                   [key: string]: string;
                 };
+                unconverted: {
+                  [key: string]: string;
+                };
               };
               from: string;
               income: {
@@ -1029,6 +1050,9 @@ export interface operations {
                 detail: {
                   [key: string]: string;
                 };
+                unconverted: {
+                  [key: string]: string;
+                };
               };
               liability: {
                 calculated: {
@@ -1038,6 +1062,9 @@ export interface operations {
                 detail: {
                   [key: string]: string;
                 };
+                unconverted: {
+                  [key: string]: string;
+                };
               };
               to: string;
               transaction_number: number;
@@ -1074,6 +1101,9 @@ export interface operations {
                     detail: {
                       [key: string]: string;
                     };
+                    unconverted: {
+                      [key: string]: string;
+                    };
                   };
                 }[];
               /** Format: date-time */
@@ -1108,6 +1138,7 @@ export interface operations {
     requestBody: {
       content: {
         "application/json": {
+          allow_duplicate?: boolean;
           datetime: string;
           flag?: string | null;
           links: string[];
@@ -1142,6 +1173,9 @@ export interface operations {
   /** Update Single Transaction */
   update_single_transaction: {
     parameters: {
+      query: {
+        hash: string;
+      };
       path: {
         transaction_id: string;
       };
@@ -1183,6 +1217,9 @@ export interface operations {
   /** Upload Transaction Document */
   upload_transaction_document: {
     parameters: {
+      query: {
+        hash: string;
+      };
       path: {
         transaction_id: string;
       };
@@ -5,6 +5,8 @@ import { FileWithPath, useDropzone } from 'react-dropzone';
 interface Props {
   type: 'transaction' | 'account';
   id: string;
+  /** the hash of transaction, required when uploading documents of transaction */
+  hash?: string;
 }
 
 export default function AccountDocumentUpload(props: Props) {
@@ -21,6 +23,7 @@ export default function AccountDocumentUpload(props: Props) {
       await uploadTransactionDocument({
         //@ts-ignore
         transaction_id: id,
+        hash: props.hash ?? '',
         //@ts-ignore
         file: formData,
       });
@@ -15,12 +15,23 @@ export default function NewTransactionButton() {
   const [data, setData] = useState<any>({});
   const [isValid, setIsValid] = useState<boolean>(false);
 
-  const onCreate = async () => {
+  const onCreate = async (allowDuplicate: boolean = false) => {
     try {
-      await createNewTransaction(data);
+      await createNewTransaction({ ...data, allow_duplicate: allowDuplicate });
       isOpenHandler.close();
       toast.success('New transaction is created');
     } catch (error) {
+      if (error instanceof createNewTransaction.Error) {
+        const actual = error.getActualType() as { status: number; data: any };
+        const candidates: any[] = actual.data?.candidates ?? [];
+        if (actual.status === 409 && candidates.length > 0) {
+          const described = candidates.map((it) => `${it.datetime} ${it.payee ?? ''} ${it.narration ?? ''}`.trim()).join('\n');
+          if (window.confirm(`Similar transactions already exist:\n${described}\n\nCreate it anyway?`)) {
+            await onCreate(true);
+          }
+          return;
+        }
+      }
       toast.error('Fail to create new Transaction', {
         description: String(error),
       });
@@ -49,7 +60,7 @@ export default function NewTransactionButton() {
           <Button variant="outline" onClick={isOpenHandler.close}>
             {t('NEW_TRANSACTION_CANCEL')}
           </Button>
-          <Button onClick={onCreate} disabled={!isValid}>
+          <Button onClick={() => onCreate()} disabled={!isValid}>
             {t('NEW_TRANSACTION_SAVE')}
           </Button>
         </DialogFooter>
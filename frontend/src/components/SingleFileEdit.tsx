@@ -20,13 +20,20 @@ export default function SingleFileEdit({ path }: Props) {
   });
 
   const onUpdate = async () => {
-    await updateFile({
-      file_path: encodedPath,
-      content: content,
-    });
-    toast.success('File updated', {
-      description: 'Ledger will be refreshed in a moment',
-    });
+    try {
+      await updateFile({
+        file_path: encodedPath,
+        content: content,
+        hash: data!.hash,
+      });
+      toast.success('File updated', {
+        description: 'Ledger will be refreshed in a moment',
+      });
+    } catch (error) {
+      toast.error('Fail to update file', {
+        description: 'File has been changed by others, please reload it before editing',
+      });
+    }
   };
 
   const [content, setContent] = useState('');
@@ -110,7 +110,7 @@ export default function TransactionPreview(props: Props) {
               .map((meta, idx) => (
                 <DocumentPreview onClick={() => setLightboxSrc(meta.value)} key={idx} uri={meta.value} filename={meta.value} />
               ))}
-            <AccountDocumentUpload id={props.data.id} type="transaction" />
+            <AccountDocumentUpload id={props.data.id} hash={props.data.hash} type="transaction" />
           </div>
         </Section>
       </div>
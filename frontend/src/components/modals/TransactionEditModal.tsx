@@ -17,7 +17,7 @@ export const TransactionEditModal = () => {
   const [isValid, setIsValid] = useState<boolean>(false);
   const onUpdate = async () => {
     try {
-      await updateTransaction(data);
+      await updateTransaction({ ...data, transaction_id: editTransaction!.id, hash: editTransaction!.hash });
       toast.success('transaction is updated');
       setEditTransaction(undefined);
       isOpenHandler.close();
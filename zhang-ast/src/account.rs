@@ -49,11 +49,15 @@ impl Account {
     /// use std::str::FromStr;
     /// use zhang_ast::Account;
     /// assert_eq!(Account::from_str("Assets:A:B").unwrap().parent().name(), "Assets:A");
+    /// assert_eq!(Account::from_str("Assets:A:B").unwrap().parent().components(), vec!["A"]);
+    /// assert_eq!(Account::from_str("Assets:A").unwrap().parent().name(), "Assets");
     /// ```
     pub fn parent(&self) -> Account {
-        let mut parent_components: Vec<String> = self.components[0..self.components.len() - 1].to_vec();
-        parent_components.insert(0, self.account_type.to_string());
-        let content = parent_components.join(":");
+        let parent_components: Vec<String> = self.components[0..self.components.len().saturating_sub(1)].to_vec();
+        let content = std::iter::once(self.account_type.to_string())
+            .chain(parent_components.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(":");
         Account {
             account_type: self.account_type,
             content,
@@ -11,6 +11,8 @@ use serde::{Deserialize, Serialize};
 pub struct CalculatedAmount {
     pub calculated: Amount,
     pub detail: HashMap<String, BigDecimal>,
+    /// amounts of the commodities without any price to the calculated commodity, which are not counted in `calculated`
+    pub unconverted: HashMap<String, BigDecimal>,
 }
 
 impl CalculatedAmount {
@@ -20,6 +22,7 @@ impl CalculatedAmount {
         CalculatedAmount {
             calculated: Amount::new(BigDecimal::zero(), commodity.to_owned()),
             detail,
+            unconverted: HashMap::new(),
         }
     }
     pub fn persist_commodity(mut self, commodity: &str) -> Self {
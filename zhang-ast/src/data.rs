@@ -155,7 +155,7 @@ pub struct Event {
     pub meta: Meta,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct Query {
     pub date: Date,
 
@@ -1,9 +1,9 @@
 #[cfg(feature = "openapi")]
 use gotcha_core::Schematic;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use strum::Display;
 
-#[derive(Debug, Display, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Display, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[cfg_attr(feature = "openapi", derive(Schematic))]
 pub enum ErrorKind {
     UnbalancedTransaction,
@@ -22,6 +22,8 @@ pub enum ErrorKind {
     BudgetDoesNotExist,
     DefineDuplicatedBudget,
 
+    DefineDuplicatedQuery,
+
     MultipleOperatingCurrencyDetect,
 
     ParseInvalidMeta,
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use beancount::Beancount;
+use zhang_ast::{Directive, Include, Spanned, ZhangString};
+use zhang_core::data_type::text::ZhangDataType;
+use zhang_core::data_type::DataType;
+use zhang_core::error::IoErrorIntoZhangError;
+use zhang_core::ledger::Ledger;
+use zhang_core::{ZhangError, ZhangResult};
+
+use crate::commands::local_data_source;
+use crate::{ExportOpts, Exporter};
+
+impl Exporter {
+    fn data_type(&self) -> Box<dyn DataType<Carrier = String>> {
+        match self {
+            Exporter::Text => Box::new(ZhangDataType {}),
+            Exporter::Beancount => Box::new(Beancount {}),
+        }
+    }
+
+    /// the file extension used by the target format
+    fn extension(&self) -> &'static str {
+        match self {
+            Exporter::Text => "zhang",
+            Exporter::Beancount => "bean",
+        }
+    }
+}
+
+pub async fn run(opts: ExportOpts) -> ExitCode {
+    let result = tokio::task::spawn_blocking(move || export(&opts))
+        .await
+        .expect("cannot spawn ledger exporting task");
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("fail to export ledger: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+pub fn export(opts: &ExportOpts) -> ZhangResult<()> {
+    let data_source = local_data_source(&opts.endpoint)?;
+    let ledger = Ledger::load_with_data_source(opts.path.clone(), opts.endpoint.clone(), Arc::new(data_source))?;
+    let data_type = opts.exporter.data_type();
+
+    if opts.preserve_structure {
+        let Some(output) = opts.output.as_ref() else {
+            return Err(ZhangError::CustomError("output folder must be given when preserving file structure".to_owned()));
+        };
+        for (file, content) in export_files(&ledger, data_type.as_ref(), opts.exporter.extension())? {
+            let target = output.join(file);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent).with_path(parent)?;
+            }
+            std::fs::write(&target, content).with_path(&target)?;
+        }
+    } else {
+        let content = export_single(&ledger, data_type.as_ref());
+        match opts.output.as_ref() {
+            Some(output) => std::fs::write(output, content).with_path(output)?,
+            None => print!("{}", content),
+        }
+    }
+    Ok(())
+}
+
+/// group the directives by their source file in visiting order, directives inside a file are kept in source order.
+/// built-in default options are skipped since they are not written by users.
+fn directives_by_file(ledger: &Ledger) -> Vec<(&PathBuf, Vec<&Spanned<Directive>>)> {
+    let mut files: HashMap<&Path, Vec<&Spanned<Directive>>> = HashMap::new();
+    for directive in ledger.metas.iter().chain(ledger.directives.iter()) {
+        if let Some(filename) = directive.span.filename.as_ref() {
+            files.entry(filename.as_path()).or_default().push(directive);
+        }
+    }
+    ledger
+        .visited_files
+        .iter()
+        .map(|file| {
+            let mut directives = files.remove(file.as_path()).unwrap_or_default();
+            directives.sort_by_key(|it| it.span.start);
+            (file, directives)
+        })
+        .collect()
+}
+
+fn render(directives: impl Iterator<Item = Spanned<Directive>>, data_type: &dyn DataType<Carrier = String>) -> String {
+    directives
+        .map(|directive| format!("{}\n", data_type.export(directive)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// merge all files into one single content, include directives are dropped
+pub fn export_single(ledger: &Ledger, data_type: &dyn DataType<Carrier = String>) -> String {
+    let directives = directives_by_file(ledger)
+        .into_iter()
+        .flat_map(|(_, directives)| directives)
+        .filter(|it| !matches!(it.data, Directive::Include(_)))
+        .cloned();
+    render(directives, data_type)
+}
+
+/// export the ledger file by file with the relative path to the ledger entry,
+/// every file and include directive is renamed with the extension of target format.
+/// files outside the ledger entry are rejected, since they cannot be placed inside the output folder.
+pub fn export_files(ledger: &Ledger, data_type: &dyn DataType<Carrier = String>, extension: &str) -> ZhangResult<Vec<(PathBuf, String)>> {
+    let entry = &ledger.entry.0;
+    directives_by_file(ledger)
+        .into_iter()
+        .map(|(file, directives)| {
+            let directives = directives.into_iter().cloned().map(|mut directive| {
+                if let Directive::Include(include) = &directive.data {
+                    let file = with_extension(Path::new(include.file.as_str()), extension);
+                    directive.data = Directive::Include(Include {
+                        file: ZhangString::QuoteString(file.to_string_lossy().to_string()),
+                    });
+                }
+                directive
+            });
+            // included paths are joined as they are written, so `..` may lead outside even after the entry is stripped
+            let relative_path = file
+                .strip_prefix(entry)
+                .ok()
+                .filter(|it| it.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)))
+                .ok_or_else(|| ZhangError::CustomError(format!("cannot export file outside of the ledger folder: {}", file.display())))?;
+            Ok((with_extension(relative_path, extension), render(directives, data_type)))
+        })
+        .collect()
+}
+
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    match path.extension().and_then(|it| it.to_str()) {
+        Some("zhang" | "bc" | "bean" | "beancount") => path.with_extension(extension),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use tempfile::tempdir;
+    use zhang_core::data_source::LocalFileSystemDataSource;
+    use zhang_core::data_type::text::ZhangDataType;
+    use zhang_core::ledger::Ledger;
+
+    use crate::commands::export::{export_files, with_extension};
+
+    #[test]
+    fn should_replace_ledger_extension() {
+        assert_eq!(PathBuf::from("data/2024.bean"), with_extension(Path::new("data/2024.zhang"), "bean"));
+        assert_eq!(PathBuf::from("main.zhang"), with_extension(Path::new("main.beancount"), "zhang"));
+        assert_eq!(PathBuf::from("data/*"), with_extension(Path::new("data/*"), "zhang"));
+    }
+
+    #[test]
+    fn should_reject_file_outside_of_ledger_folder() {
+        let dir = tempdir().unwrap();
+        let entry = dir.path().join("ledger");
+        std::fs::create_dir(&entry).unwrap();
+        std::fs::write(entry.join("main.zhang"), "include \"../shared.zhang\"\n").unwrap();
+        std::fs::write(dir.path().join("shared.zhang"), "option \"title\" \"shared\"\n").unwrap();
+        let ledger = Ledger::load_with_data_source(entry, "main.zhang".to_string(), Arc::new(LocalFileSystemDataSource::new(ZhangDataType {}))).unwrap();
+
+        let result = export_files(&ledger, &ZhangDataType {}, "zhang");
+
+        assert!(result.is_err());
+        assert_eq!(
+            "option \"title\" \"shared\"\n",
+            std::fs::read_to_string(dir.path().join("shared.zhang")).unwrap()
+        );
+    }
+}
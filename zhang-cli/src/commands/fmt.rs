@@ -0,0 +1,67 @@
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use zhang_core::data_source::DataSource;
+use zhang_core::data_type::text::exporter::{format_content, FormatOptions};
+use zhang_core::error::IoErrorIntoZhangError;
+use zhang_core::{ZhangError, ZhangResult};
+
+use crate::commands::local_data_source;
+use crate::FmtOpts;
+
+pub async fn run(opts: FmtOpts) -> ExitCode {
+    let result = tokio::task::spawn_blocking(move || format_ledger(&opts))
+        .await
+        .expect("cannot spawn ledger formatting task");
+    match result {
+        Ok(changed_files) if changed_files.is_empty() => ExitCode::SUCCESS,
+        Ok(changed_files) => {
+            for file in changed_files {
+                println!("{}", file.display());
+            }
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("fail to format ledger: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// format every file of the ledger in place.
+/// with `--check` files are left untouched, and those which are not formatted are returned.
+pub fn format_ledger(opts: &FmtOpts) -> ZhangResult<Vec<PathBuf>> {
+    if !opts.endpoint.ends_with(".zhang") {
+        return Err(ZhangError::CustomError(format!("only zhang files can be formatted: {}", opts.endpoint)));
+    }
+    let data_source = local_data_source(&opts.endpoint)?;
+    let load_result = data_source.load(opts.path.to_string_lossy().to_string(), opts.endpoint.clone())?;
+    let options = FormatOptions {
+        sort_by_date: opts.sort_by_date,
+    };
+
+    let mut unformatted_files = vec![];
+    for file in load_result.visited_files {
+        let content = std::fs::read_to_string(&file).with_path(&file)?;
+        let formatted = format_content(&content, &options).map_err(|e| with_file(e, &file))?;
+        if formatted == content {
+            continue;
+        }
+        if opts.check {
+            unformatted_files.push(file);
+        } else {
+            std::fs::write(&file, formatted).with_path(&file)?;
+        }
+    }
+    Ok(unformatted_files)
+}
+
+fn with_file(error: ZhangError, file: &Path) -> ZhangError {
+    match error {
+        ZhangError::PestError { msg, .. } => ZhangError::PestError {
+            path: file.to_string_lossy().to_string(),
+            msg,
+        },
+        other => other,
+    }
+}
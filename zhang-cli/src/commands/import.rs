@@ -0,0 +1,92 @@
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use zhang_ast::{Directive, SpanInfo, Spanned};
+use zhang_core::data_type::text::ZhangDataType;
+use zhang_core::data_type::DataType;
+use zhang_core::error::IoErrorIntoZhangError;
+use zhang_core::importer::csv::{CsvImporter, CsvMapping};
+use zhang_core::importer::duplicate::DuplicateRule;
+use zhang_core::importer::ofx::{OfxImporter, OfxMapping};
+use zhang_core::importer::rule::{CategorizeRule, Categorizer};
+use zhang_core::importer::Importer;
+use zhang_core::ledger::Ledger;
+use zhang_core::{ZhangError, ZhangResult};
+
+use crate::commands::local_data_source;
+use crate::ImportOpts;
+
+pub async fn run(opts: ImportOpts) -> ExitCode {
+    let result = tokio::task::spawn_blocking(move || import(&opts))
+        .await
+        .expect("cannot spawn statement importing task");
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("fail to import statement: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+pub fn import(opts: &ImportOpts) -> ZhangResult<()> {
+    let extension = opts.file.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+    let (importer, account): (Box<dyn Importer>, String) = match extension.as_str() {
+        "ofx" | "qfx" => {
+            let mapping: OfxMapping = mapping(&opts.config)?;
+            let account = mapping.account.clone();
+            (Box::new(OfxImporter::new(mapping)), account)
+        }
+        _ => {
+            let mapping: CsvMapping = mapping(&opts.config)?;
+            let account = mapping.account.clone();
+            (Box::new(CsvImporter::new(mapping)), account)
+        }
+    };
+    let content = String::from_utf8(std::fs::read(&opts.file).with_path(&opts.file)?)?;
+    let directives = importer.import(&content)?;
+
+    let data_source = local_data_source(&opts.endpoint)?;
+    let ledger = Ledger::load_with_data_source(opts.path.clone(), opts.endpoint.clone(), Arc::new(data_source))?;
+
+    let mut rules: Vec<CategorizeRule> = match opts.rules.as_ref() {
+        Some(path) => mapping(path)?,
+        None => vec![],
+    };
+    if opts.learn {
+        rules.extend(ledger.operations().learned_rules(&account)?);
+    }
+    let directives = Categorizer::new(rules)?.categorize_all(directives);
+
+    let rule = DuplicateRule {
+        date_window: opts.duplicate_window,
+        external_id_key: importer.external_id_key().map(|it| it.to_owned()),
+        ..Default::default()
+    };
+    let directives = ledger.operations().handle_duplicates(directives, &rule, opts.duplicate)?;
+
+    if opts.write {
+        let count = directives.len();
+        ledger.data_source.append(&ledger, directives)?;
+        eprintln!("{} directives are imported", count);
+    } else {
+        print!("{}", render(directives));
+    }
+    Ok(())
+}
+
+pub(crate) fn mapping<T: DeserializeOwned>(path: &Path) -> ZhangResult<T> {
+    let content = std::fs::read(path).with_path(path)?;
+    serde_json::from_slice(&content).map_err(|e| ZhangError::CustomError(format!("invalid import mapping: {}", e)))
+}
+
+fn render(directives: Vec<Directive>) -> String {
+    let data_type = ZhangDataType {};
+    directives
+        .into_iter()
+        .map(|directive| format!("{}\n", data_type.export(Spanned::new(directive, SpanInfo::default()))))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
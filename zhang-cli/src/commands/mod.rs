@@ -0,0 +1,22 @@
+use std::path::PathBuf;
+
+use beancount::Beancount;
+use zhang_core::data_source::LocalFileSystemDataSource;
+use zhang_core::data_type::text::ZhangDataType;
+use zhang_core::{ZhangError, ZhangResult};
+
+pub mod export;
+pub mod fmt;
+pub mod import;
+pub mod parse;
+pub mod sql;
+
+/// create the local file system data source for the given endpoint, the data type is detected by the endpoint's extension.
+pub(crate) fn local_data_source(endpoint: &str) -> ZhangResult<LocalFileSystemDataSource> {
+    let extension = PathBuf::from(endpoint).extension().unwrap_or_default().to_string_lossy().to_string();
+    match extension.as_str() {
+        "bc" | "bean" | "beancount" => Ok(LocalFileSystemDataSource::new(Beancount {})),
+        "zhang" => Ok(LocalFileSystemDataSource::new(ZhangDataType {})),
+        _ => Err(ZhangError::CustomError(format!("not supported data format: {}", endpoint))),
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use serde::Serialize;
+use zhang_ast::error::ErrorKind;
+use zhang_core::ledger::Ledger;
+use zhang_core::ZhangResult;
+
+use crate::commands::local_data_source;
+use crate::{OutputFormat, ParseOpts};
+
+/// a single ledger error with its location resolved into file and line
+#[derive(Debug, Serialize)]
+pub struct ErrorReport {
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub error_type: ErrorKind,
+    pub content: Option<String>,
+    pub metas: HashMap<String, String>,
+}
+
+pub async fn run(opts: ParseOpts) -> ExitCode {
+    let format = opts.format.clone();
+    let result = tokio::task::spawn_blocking(move || check(&opts.path, &opts.endpoint, opts.database.as_deref()))
+        .await
+        .expect("cannot spawn ledger checking task");
+
+    match result {
+        Ok(reports) => {
+            print_reports(&reports, format);
+            if reports.is_empty() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => {
+            eprintln!("fail to load ledger: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// load the ledger and collect all errors emitted while processing it, the ledger is restored from the cache file if given and fresh
+pub fn check(path: &Path, endpoint: &str, cache: Option<&Path>) -> ZhangResult<Vec<ErrorReport>> {
+    let data_source = Arc::new(local_data_source(endpoint)?);
+    let ledger = match cache {
+        Some(cache) => Ledger::load_with_cache(path.to_path_buf(), endpoint.to_owned(), data_source, cache)?,
+        None => Ledger::load_with_data_source(path.to_path_buf(), endpoint.to_owned(), data_source)?,
+    };
+    let entry = ledger.entry.0.clone();
+    let errors = ledger.operations().errors()?;
+
+    let mut file_contents: HashMap<PathBuf, Option<String>> = HashMap::new();
+    let mut reports = Vec::with_capacity(errors.len());
+    for error in errors {
+        let (file, line, content) = match error.span {
+            Some(span) => {
+                let line = span.filename.as_ref().and_then(|filename| {
+                    file_contents
+                        .entry(filename.clone())
+                        .or_insert_with(|| std::fs::read_to_string(filename).ok())
+                        .as_deref()
+                        .map(|file_content| line_of_offset(file_content, span.start))
+                });
+                let file = span
+                    .filename
+                    .map(|filename| filename.strip_prefix(&entry).map(|it| it.to_path_buf()).unwrap_or(filename))
+                    .map(|filename| filename.to_string_lossy().to_string());
+                let content = Some(span.content).filter(|it| !it.is_empty());
+                (file, line, content)
+            }
+            None => (None, None, None),
+        };
+        reports.push(ErrorReport {
+            file,
+            line,
+            error_type: error.error_type,
+            content,
+            metas: error.metas,
+        });
+    }
+    Ok(reports)
+}
+
+/// 1-based line number of the given byte offset
+fn line_of_offset(content: &str, offset: usize) -> usize {
+    let offset = offset.min(content.len());
+    content.as_bytes()[..offset].iter().filter(|byte| **byte == b'\n').count() + 1
+}
+
+fn print_reports(reports: &[ErrorReport], format: OutputFormat) {
+    match format {
+        OutputFormat::Human => {
+            for report in reports {
+                let location = match (&report.file, report.line) {
+                    (Some(file), Some(line)) => format!("{}:{}", file, line),
+                    (Some(file), None) => file.to_owned(),
+                    _ => "<unknown>".to_owned(),
+                };
+                let metas = report
+                    .metas
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                if metas.is_empty() {
+                    println!("{}: {}", location, report.error_type);
+                } else {
+                    println!("{}: {} ({})", location, report.error_type, metas);
+                }
+                if let Some(content) = &report.content {
+                    for line in content.trim_end().lines() {
+                        println!("    {}", line);
+                    }
+                }
+            }
+            if reports.is_empty() {
+                println!("no error found");
+            } else {
+                println!("found {} error(s)", reports.len());
+            }
+        }
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(reports).expect("cannot serialize error reports"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::parse::line_of_offset;
+
+    #[test]
+    fn should_get_line_of_offset() {
+        let content = "option \"title\" \"a\"\n\n1970-01-01 open Assets:A\n";
+        assert_eq!(1, line_of_offset(content, 0));
+        assert_eq!(3, line_of_offset(content, 20));
+        assert_eq!(4, line_of_offset(content, content.len()));
+    }
+}
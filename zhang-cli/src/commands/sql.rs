@@ -0,0 +1,226 @@
+use std::io::{BufRead, Write};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use serde_json::Value;
+use zhang_core::ledger::Ledger;
+use zhang_core::ZhangResult;
+use zhang_sql::{AsExecutor, ExecutionResult, Executor};
+
+use crate::commands::local_data_source;
+use crate::{SqlOpts, SqlOutputFormat};
+
+pub async fn run(opts: SqlOpts) -> ExitCode {
+    let path = opts.path.clone();
+    let endpoint = opts.endpoint.clone();
+    let ledger = tokio::task::spawn_blocking(move || load(path, endpoint))
+        .await
+        .expect("cannot spawn ledger loading task");
+    let ledger = match ledger {
+        Ok(ledger) => ledger,
+        Err(e) => {
+            eprintln!("fail to load ledger: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let executor = match ledger.as_executor() {
+        Ok(executor) => executor,
+        Err(e) => {
+            eprintln!("fail to load ledger into sql executor: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match opts.query {
+        Some(query) => match executor.execute(&query) {
+            Ok(result) => {
+                print!("{}", render(&result, opts.format));
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("fail to execute query: {}", e);
+                ExitCode::FAILURE
+            }
+        },
+        None => {
+            // reading stdin blocks and cannot be cancelled, so the repl runs on its own thread instead of the runtime,
+            // which lets ctrl+c handled by the main task end the process while waiting for input
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            std::thread::spawn(move || {
+                repl(&executor, opts.format);
+                tx.send(()).ok();
+            });
+            rx.await.ok();
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+fn load(path: std::path::PathBuf, endpoint: String) -> ZhangResult<Ledger> {
+    let data_source = local_data_source(&endpoint)?;
+    Ledger::load_with_data_source(path, endpoint, Arc::new(data_source))
+}
+
+/// read queries line by line from stdin with the same executor, until `exit`, `quit` or EOF.
+/// `.tables` lists all tables and views.
+fn repl(executor: &Executor, format: SqlOutputFormat) {
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("zhang> ");
+        std::io::stdout().flush().ok();
+        let Some(Ok(line)) = lines.next() else {
+            println!();
+            break;
+        };
+        let query = line.trim().trim_end_matches(';').trim();
+        match query {
+            "" => continue,
+            "exit" | "quit" => break,
+            ".tables" => print_result(executor, "SHOW TABLES", format),
+            query => print_result(executor, query, format),
+        }
+    }
+}
+
+fn print_result(executor: &Executor, query: &str, format: SqlOutputFormat) {
+    match executor.execute(query) {
+        Ok(result) => print!("{}", render(&result, format)),
+        Err(e) => eprintln!("error: {}", e),
+    }
+}
+
+fn render(result: &ExecutionResult, format: SqlOutputFormat) -> String {
+    match format {
+        SqlOutputFormat::Table => render_table(result),
+        SqlOutputFormat::Csv => render_csv(result),
+        SqlOutputFormat::Json => render_json_lines(result),
+    }
+}
+
+/// null is rendered as empty, strings are rendered without quotes
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(value) => value.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn cells(result: &ExecutionResult) -> Vec<Vec<String>> {
+    result
+        .rows
+        .iter()
+        .map(|row| row.columns.iter().map(|column| cell(&column.value)).collect())
+        .collect()
+}
+
+fn render_table(result: &ExecutionResult) -> String {
+    let rows = cells(result);
+    let mut widths: Vec<usize> = result.columns.iter().map(|it| it.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: &[String]| {
+        let line = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        format!("{}\n", line.trim_end())
+    };
+
+    let mut ret = line(&result.columns);
+    ret.push_str(&format!("{}\n", widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<_>>().join("-+-")));
+    for row in &rows {
+        ret.push_str(&line(row));
+    }
+    ret.push_str(&format!("({} rows)\n", rows.len()));
+    ret
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+fn render_csv(result: &ExecutionResult) -> String {
+    std::iter::once(result.columns.clone())
+        .chain(cells(result))
+        .map(|row| format!("{}\n", row.iter().map(|it| csv_field(it)).collect::<Vec<_>>().join(",")))
+        .collect()
+}
+
+/// one json object per row, fields are written in column order
+fn render_json_lines(result: &ExecutionResult) -> String {
+    result
+        .rows
+        .iter()
+        .map(|row| {
+            let fields = row
+                .columns
+                .iter()
+                .map(|it| format!("{}:{}", Value::String(it.name.clone()), it.value))
+                .collect::<Vec<_>>();
+            format!("{{{}}}\n", fields.join(","))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{json, Value};
+    use zhang_sql::{Column, ExecutionResult, Row};
+
+    use crate::commands::sql::{render_csv, render_json_lines, render_table};
+
+    fn result() -> ExecutionResult {
+        let row = |name: &str, balance: Value| Row {
+            columns: vec![
+                Column {
+                    name: "name".to_owned(),
+                    value: json!(name),
+                },
+                Column {
+                    name: "balance".to_owned(),
+                    value: balance,
+                },
+            ],
+        };
+        ExecutionResult {
+            columns: vec!["name".to_owned(), "balance".to_owned()],
+            rows: vec![row("Assets:Cash", json!("100.000")), row("Expenses:Food, \"Lunch\"", Value::Null)],
+        }
+    }
+
+    #[test]
+    fn should_render_aligned_table() {
+        assert_eq!(
+            "name                   | balance\n\
+             -----------------------+--------\n\
+             Assets:Cash            | 100.000\n\
+             Expenses:Food, \"Lunch\" |\n\
+             (2 rows)\n",
+            render_table(&result())
+        );
+    }
+
+    #[test]
+    fn should_render_csv_with_quoted_fields() {
+        assert_eq!("name,balance\nAssets:Cash,100.000\n\"Expenses:Food, \"\"Lunch\"\"\",\n", render_csv(&result()));
+    }
+
+    #[test]
+    fn should_render_one_json_object_per_line() {
+        assert_eq!(
+            "{\"name\":\"Assets:Cash\",\"balance\":\"100.000\"}\n{\"name\":\"Expenses:Food, \\\"Lunch\\\"\",\"balance\":null}\n",
+            render_json_lines(&result())
+        );
+    }
+}
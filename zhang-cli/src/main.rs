@@ -1,5 +1,6 @@
 use std::fmt::Debug;
 use std::path::PathBuf;
+use std::process::ExitCode;
 use std::sync::Arc;
 
 use clap::{Args, Parser};
@@ -7,10 +8,13 @@ use env_logger::Env;
 use log::{error, info};
 use self_update::Status;
 use tokio::task::spawn_blocking;
+use zhang_core::importer::duplicate::DuplicateAction;
+use zhang_server::git::GitRepository;
 use zhang_server::ServeConfig;
 
 use crate::opendal::OpendalDataSource;
 
+pub mod commands;
 pub mod opendal;
 
 #[derive(Parser, Debug)]
@@ -22,6 +26,15 @@ pub enum Opts {
     /// export to target file
     Export(ExportOpts),
 
+    /// format zhang files in canonical form
+    Fmt(FmtOpts),
+
+    /// import bank statement as transactions
+    Import(ImportOpts),
+
+    /// run sql query against the ledger, start an interactive shell if query is not present
+    Sql(SqlOpts),
+
     /// start an internal server with frontend ui
     Serve(ServerOpts),
 
@@ -41,9 +54,19 @@ pub struct ParseOpts {
     #[clap(short, long, default_value = "main.zhang")]
     pub endpoint: String,
 
-    /// indicate cache database file path, using tempfile if not present
+    /// the cache file of processed ledger, parsing and processing are skipped if none of the ledger files is changed
     #[clap(long)]
     pub database: Option<PathBuf>,
+
+    /// the output format of the found errors
+    #[clap(short, long, default_value = "human")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+pub enum OutputFormat {
+    Human,
+    Json,
 }
 #[derive(Args, Debug)]
 pub struct ExportOpts {
@@ -54,9 +77,96 @@ pub struct ExportOpts {
     #[clap(short, long, default_value = "main.zhang")]
     pub endpoint: String,
 
-    /// the endpoint of main zhang file.
-    #[clap(short, long, default_value = "Text")]
+    /// the target format of exported ledger
+    #[clap(short = 'x', long, default_value = "text")]
     pub exporter: Exporter,
+
+    /// the output file, or the output folder if `--preserve-structure` is enabled. print to stdout if not present
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// export ledger file by file with the original include structure instead of merging into one file
+    #[clap(long)]
+    pub preserve_structure: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct FmtOpts {
+    /// base path of zhang project
+    pub path: PathBuf,
+
+    /// the endpoint of main zhang file.
+    #[clap(short, long, default_value = "main.zhang")]
+    pub endpoint: String,
+
+    /// list the files which are not formatted instead of rewriting them
+    #[clap(long)]
+    pub check: bool,
+
+    /// reorder directives by date
+    #[clap(long)]
+    pub sort_by_date: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ImportOpts {
+    /// base path of zhang project
+    pub path: PathBuf,
+
+    /// the statement file to import, either CSV or OFX/QFX
+    pub file: PathBuf,
+
+    /// the endpoint of main zhang file.
+    #[clap(short, long, default_value = "main.zhang")]
+    pub endpoint: String,
+
+    /// the json file describing how the statement is mapped into transactions, statements ending with `.ofx` or `.qfx` are read as OFX, others as CSV
+    #[clap(short, long)]
+    pub config: PathBuf,
+
+    /// append the imported transactions into ledger instead of printing them to stdout
+    #[clap(long)]
+    pub write: bool,
+
+    /// what to do with transactions which already exist in ledger, one of `keep`, `mark` and `skip`
+    #[clap(long, default_value = "mark")]
+    pub duplicate: DuplicateAction,
+
+    /// existing transactions within the days before and after the imported one are compared for duplicate detection
+    #[clap(long, default_value_t = 3)]
+    pub duplicate_window: u32,
+
+    /// the json file of categorize rules assigning counter accounts, tags, links and metas to imported transactions
+    #[clap(long)]
+    pub rules: Option<PathBuf>,
+
+    /// assign the most frequent counter account of the payee in existing transactions, after rules given by `--rules`
+    #[clap(long)]
+    pub learn: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct SqlOpts {
+    /// base path of zhang project
+    pub path: PathBuf,
+
+    /// the sql query to execute
+    pub query: Option<String>,
+
+    /// the endpoint of main zhang file.
+    #[clap(short, long, default_value = "main.zhang")]
+    pub endpoint: String,
+
+    /// the output format of query result
+    #[clap(short, long, default_value = "table")]
+    pub format: SqlOutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum SqlOutputFormat {
+    Table,
+    Csv,
+    Json,
 }
 
 #[derive(Debug, Clone, clap::ValueEnum)]
@@ -70,6 +180,8 @@ pub enum FileSystem {
     // S3,
     WebDav,
     Github,
+    /// local file system, and every change is committed into the git repository
+    Git,
 }
 
 impl FileSystem {
@@ -78,6 +190,7 @@ impl FileSystem {
             Ok("fs") => Some(FileSystem::Fs),
             Ok("web-dav") => Some(FileSystem::WebDav),
             Ok("github") => Some(FileSystem::Github),
+            Ok("git") => Some(FileSystem::Git),
             _ => None,
         }
     }
@@ -111,21 +224,46 @@ pub struct ServerOpts {
     /// whether the server report version info for anonymous statistics
     #[clap(long)]
     pub no_report: bool,
+
+    /// the cache file of processed ledger, parsing and processing are skipped on startup if none of the ledger files is changed
+    #[clap(long)]
+    pub database: Option<PathBuf>,
+
+    /// the json file of categorize rules applied to transactions created or imported through web
+    #[clap(long)]
+    pub rules: Option<PathBuf>,
 }
 
 impl Opts {
-    pub async fn run(self) {
+    pub async fn run(self) -> ExitCode {
         match self {
-            Opts::Parse(_parse_opts) => {
-                // let format = SupportedFormat::from_path(&parse_opts.endpoint).expect("unsupported file type");
-                // todo: fix parse
-                // Ledger::load_with_database(parse_opts.path, parse_opts.endpoint, format.transformer()).expect("Cannot load ledger");
-            }
-            Opts::Export(_) => todo!(),
+            Opts::Parse(parse_opts) => return commands::parse::run(parse_opts).await,
+            Opts::Export(export_opts) => return commands::export::run(export_opts).await,
+            Opts::Fmt(fmt_opts) => return commands::fmt::run(fmt_opts).await,
+            Opts::Import(import_opts) => return commands::import::run(import_opts).await,
+            Opts::Sql(sql_opts) => return commands::sql::run(sql_opts).await,
             Opts::Serve(mut opts) => {
                 let file_system = opts.source.clone().or(FileSystem::from_env()).unwrap_or(FileSystem::Fs);
                 info!("active file system is {:?}", &file_system);
                 let data_source = OpendalDataSource::from_env(file_system.clone(), &mut opts).await;
+                let git_repository = if file_system == FileSystem::Git {
+                    match GitRepository::open(&opts.path) {
+                        Ok(repository) => Some(Arc::new(repository)),
+                        Err(e) => {
+                            error!("cannot open git repository: {}", e);
+                            return ExitCode::FAILURE;
+                        }
+                    }
+                } else {
+                    None
+                };
+                let rules = match opts.rules.as_deref().map(commands::import::mapping).transpose() {
+                    Ok(rules) => rules.unwrap_or_default(),
+                    Err(e) => {
+                        error!("cannot read categorize rules: {}", e);
+                        return ExitCode::FAILURE;
+                    }
+                };
                 let auth_credential = opts.auth.or(std::env::var("ZHANG_AUTH").ok()).filter(|it| it.contains(':'));
                 let result = zhang_server::serve(ServeConfig {
                     path: opts.path,
@@ -133,9 +271,12 @@ impl Opts {
                     addr: opts.addr,
                     port: opts.port,
                     auth_credential,
-                    is_local_fs: file_system == FileSystem::Fs,
+                    is_local_fs: matches!(file_system, FileSystem::Fs | FileSystem::Git),
                     no_report: opts.no_report,
                     data_source: Arc::new(data_source),
+                    git_repository,
+                    database: opts.database,
+                    rules,
                 })
                 .await;
                 match result {
@@ -169,11 +310,12 @@ impl Opts {
                 }
             }
         }
+        ExitCode::SUCCESS
     }
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     // console_subscriber::init();
     let env = Env::new().filter("ZHANG_LOG").default_filter_or("RUST_LOG");
     env_logger::Builder::default().parse_env(env).init();
@@ -182,9 +324,11 @@ async fn main() {
     tokio::select! {
         _ = tokio::signal::ctrl_c() => {
             info!("receive ctrl+c, exit");
+            ExitCode::SUCCESS
         }
-        _ = opts.run() => {
-            println!("operation completed");
+        exit_code = opts.run() => {
+            info!("operation completed");
+            exit_code
         }
     }
 }
@@ -207,6 +351,8 @@ mod test {
     use tower::util::ServiceExt;
     use zhang_core::ledger::Ledger;
     use zhang_server::broadcast::Broadcaster;
+    use zhang_server::history::{History, HISTORY_CAPACITY};
+    use zhang_server::state::{SharedHistory, SharedRules, SharedSqlExecutor};
     use zhang_server::{create_server_app, ReloadSender, ServeConfig};
 
     use crate::opendal::OpendalDataSource;
@@ -283,6 +429,8 @@ mod test {
                             auth: None,
                             source: None,
                             no_report: false,
+                            database: None,
+                            rules: None,
                         },
                     )
                     .await;
@@ -293,7 +441,8 @@ mod test {
                     let ledger_data = Arc::new(RwLock::new(ledger));
                     let broadcaster = Broadcaster::create();
                     let (tx, _) = mpsc::channel(1);
-                    let reload_sender = Arc::new(ReloadSender(tx));
+                    let sql_executor = SharedSqlExecutor::default();
+                    let reload_sender = Arc::new(ReloadSender(tx, sql_executor.clone()));
                     let app = create_server_app(
                         ServeConfig {
                             path: test_temp_folder.to_path_buf(),
@@ -302,12 +451,18 @@ mod test {
                             port: 0,
                             auth_credential: None,
                             is_local_fs: true,
+                            git_repository: None,
                             no_report: false,
                             data_source: data_source.clone(),
+                            database: None,
+                            rules: vec![],
                         },
                         ledger_data,
                         broadcaster,
                         reload_sender,
+                        sql_executor,
+                        SharedHistory(Arc::new(History::new(HISTORY_CAPACITY))),
+                        SharedRules::default(),
                     );
 
                     let config = app.config().await.unwrap();
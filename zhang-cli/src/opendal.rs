@@ -5,12 +5,13 @@ use std::str::FromStr;
 use async_recursion::async_recursion;
 use beancount::Beancount;
 use chrono::Datelike;
+use futures::future::join_all;
 use log::{debug, info, warn};
 use minijinja::{context, Environment};
 use opendal::services::{Fs, Github, Webdav};
 use opendal::{ErrorKind, Operator};
 use zhang_ast::{Directive, Include, SpanInfo, Spanned, ZhangString};
-use zhang_core::data_source::{DataSource, LoadResult};
+use zhang_core::data_source::{DataSource, LoadResult, ParseCache, ParsedFile};
 use zhang_core::data_type::text::parser::parse as zhang_parse;
 use zhang_core::data_type::text::ZhangDataType;
 use zhang_core::data_type::DataType;
@@ -23,6 +24,7 @@ pub struct OpendalDataSource {
     operator: Operator,
     data_type: Box<dyn DataType<Carrier = String> + 'static + Send + Sync>,
     is_beancount: bool,
+    parse_cache: ParseCache,
 }
 
 async fn is_wildcard_pathbuf(pathbuf: &Path) -> bool {
@@ -49,135 +51,80 @@ impl DataSource for OpendalDataSource {
             .map_err(|e| ZhangError::CustomError(format!("fail to get file content [{}] : {}", path, e)))
     }
 
+    fn visited_file_path(&self, entry: &Path, visited_file: &Path) -> String {
+        visited_file.strip_prefix(entry).unwrap_or(visited_file).to_string_lossy().to_string()
+    }
+
     async fn async_load(&self, entry: String, endpoint: String) -> ZhangResult<LoadResult> {
         let entry = PathBuf::from(entry);
         let main_endpoint = entry.join(endpoint);
 
-        let mut load_queue: VecDeque<PathBuf> = VecDeque::new();
-        load_queue.push_back(main_endpoint);
-
         let mut visited: Vec<PathBuf> = Vec::new();
+        let mut file_hashes = vec![];
+        let mut file_appends = vec![];
         let mut directives = vec![];
-        while let Some(pathbuf) = load_queue.pop_front() {
-            let striped_pathbuf = &pathbuf.strip_prefix(&entry).expect("Cannot strip entry").to_path_buf();
-            if is_wildcard_pathbuf(striped_pathbuf).await {
-                // Split path into components and find wildcard level
-                let mut path_components: Vec<String> = striped_pathbuf.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
-                let first_component = path_components.remove(0);
-                let wildcard_component = WildcardPathComponent {
-                    path: first_component,
-                    remaining: path_components,
+        // files included at the same depth are fetched and parsed concurrently, and their directives are kept in the including order
+        let mut current_level = vec![main_endpoint];
+        while !current_level.is_empty() {
+            let mut files: Vec<PathBuf> = Vec::with_capacity(current_level.len());
+            for pathbuf in current_level {
+                let striped_pathbuf = pathbuf.strip_prefix(&entry).expect("Cannot strip entry").to_path_buf();
+                let candidates = if is_wildcard_pathbuf(&striped_pathbuf).await {
+                    self.expand_wildcard(&entry, &striped_pathbuf).await?
+                } else {
+                    vec![pathbuf]
                 };
-                let mut queue: VecDeque<WildcardPathComponent> = VecDeque::new();
-                queue.push_back(wildcard_component);
-
-                let mut final_file_paths: Vec<PathBuf> = vec![];
-                while let Some(mut current_component) = queue.pop_front() {
-                    let mut current_path = PathBuf::new();
-                    current_path.push(current_component.path);
-
-                    let next_component = current_component.remaining.remove(0);
-
-                    let next_component_path = current_path.join(&next_component);
-                    if !next_component.contains('*') {
-                        // if the next component is not a wildcard, we can just add it to the current path
-                        queue.push_back(WildcardPathComponent {
-                            path: next_component_path.to_string_lossy().to_string(),
-                            remaining: current_component.remaining,
-                        });
-                        continue;
-                    }
-                    // if the next component is a wildcard, we need to add all the files in the current path to the final file paths
-
-                    let current_path_str = format!("{}/", current_path.to_string_lossy());
-                    let files = self
-                        .operator
-                        .list(&current_path_str)
-                        .await
-                        .map_err(|e| ZhangError::CustomError(format!("fail to list files in parent directory [{}] : {}", current_path.display(), e)))?;
-
-                    let re = regex::Regex::new(&next_component.replace('*', "[^/]+")).unwrap();
-
-                    for entry in files {
-                        let entry_name = entry.path();
-
-                        if entry.metadata().is_dir() {
-                            let striped_entry_name = entry.path().strip_prefix(&current_path_str).unwrap().strip_suffix("/").unwrap();
-                            if re.is_match(striped_entry_name) {
-                                // Build full path
-                                if !current_component.remaining.is_empty() {
-                                    queue.push_back(WildcardPathComponent {
-                                        path: current_path.join(striped_entry_name).to_string_lossy().to_string(),
-                                        remaining: current_component.remaining.clone(),
-                                    });
-                                }
-                            }
-                        } else {
-                            let striped_entry_name = entry_name.strip_prefix(&current_path_str).unwrap();
-                            if re.is_match(striped_entry_name) {
-                                // Build full path
-                                let is_remaining_empty = current_component.remaining.is_empty();
-                                if is_remaining_empty {
-                                    final_file_paths.push(current_path.join(striped_entry_name));
-                                }
-                            }
-                        }
+                for candidate in candidates {
+                    if !utils::has_path_visited(&visited, &candidate) && !utils::has_path_visited(&files, &candidate) {
+                        files.push(candidate);
                     }
                 }
-                for file_path in final_file_paths {
-                    let fullpath = if file_path.as_path().starts_with("/") {
-                        file_path
+            }
+            let parsed_files = join_all(files.iter().map(|pathbuf| self.load_file(&entry, pathbuf))).await;
+
+            current_level = vec![];
+            for (pathbuf, parsed_file) in files.into_iter().zip(parsed_files) {
+                let ParsedFile {
+                    hash,
+                    directives: entity_directives,
+                    appended,
+                } = parsed_file?;
+                entity_directives.iter().filter_map(|directive| self.go_next(directive)).for_each(|buf| {
+                    let fullpath = if buf.starts_with('/') {
+                        PathBuf::from_str(&buf).unwrap()
                     } else {
-                        entry.join(file_path)
+                        pathbuf.parent().map(|it| it.join(buf)).unwrap()
                     };
-                    load_queue.push_back(fullpath);
-                }
-                continue;
-            } else {
-                debug!("visited entry file: {:?}", striped_pathbuf.display());
-            }
-            if utils::has_path_visited(&visited, &pathbuf) {
-                continue;
+                    current_level.push(fullpath);
+                });
+                file_appends.push(appended.map(|it| it.offset(directives.len())));
+                directives.extend(entity_directives);
+                visited.push(pathbuf);
+                file_hashes.push(hash);
             }
-            let file_content = self.get_file_content(striped_pathbuf.clone()).await?;
-            let entity_directives = self.parse(&file_content, striped_pathbuf.clone())?;
-
-            entity_directives.iter().filter_map(|directive| self.go_next(directive)).for_each(|buf| {
-                let fullpath = if buf.starts_with('/') {
-                    PathBuf::from_str(&buf).unwrap()
-                } else {
-                    pathbuf.parent().map(|it| it.join(buf)).unwrap()
-                };
-                load_queue.push_back(fullpath);
-            });
-            directives.extend(entity_directives);
-            visited.push(pathbuf);
         }
+        self.parse_cache.retain(&visited);
         let res = LoadResult {
             directives: self.transform(directives)?,
             visited_files: visited,
+            file_hashes,
+            file_appends,
         };
         Ok(res)
     }
 
     async fn async_get(&self, path: String) -> ZhangResult<Vec<u8>> {
-        let path_for_read = path.to_owned();
-        let result = self.operator.read(&path_for_read).await;
-        match result {
-            Ok(data) => Ok(data.to_vec()),
-            Err(err) => {
-                if err.kind() == ErrorKind::NotFound {
-                    Ok(Vec::new())
-                } else {
-                    Err(ZhangError::CustomError(format!("Error getting file content from {}: {}", path, err)))
-                }
-            }
-        }
+        self.operator
+            .read(&path)
+            .await
+            .map(|data| data.to_vec())
+            .map_err(|err| ZhangError::CustomError(format!("Error getting file content from {}: {}", path, err)))
     }
 
     async fn async_append(&self, ledger: &Ledger, directives: Vec<Directive>) -> ZhangResult<()> {
+        let mut visited_files = ledger.visited_files.clone();
         for directive in directives {
-            self.append_directive(ledger, directive, None, true).await?;
+            self.append_directive(ledger, directive, None, Some(&mut visited_files)).await?;
         }
         Ok(())
     }
@@ -189,11 +136,115 @@ impl DataSource for OpendalDataSource {
         self.operator.write(&path, vec).await.expect("cannot write");
         Ok(())
     }
+
+    async fn async_remove(&self, _ledger: &Ledger, path: String) -> ZhangResult<()> {
+        info!("[opendal] remove path={}", &path);
+        self.operator
+            .delete(&path)
+            .await
+            .map_err(|err| ZhangError::CustomError(format!("Error removing file {}: {}", path, err)))
+    }
 }
 
 impl OpendalDataSource {
+    /// the content of the file, or empty content if the file does not exist
+    async fn get_or_empty(&self, path: &str) -> ZhangResult<Vec<u8>> {
+        match self.operator.read(path).await {
+            Ok(data) => Ok(data.to_vec()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(ZhangError::CustomError(format!("Error getting file content from {}: {}", path, err))),
+        }
+    }
+
+    async fn load_file(&self, entry: &Path, pathbuf: &Path) -> ZhangResult<ParsedFile> {
+        let striped_pathbuf = pathbuf.strip_prefix(entry).expect("Cannot strip entry").to_path_buf();
+        debug!("visited entry file: {:?}", striped_pathbuf.display());
+        let file_content = self.get_file_content(striped_pathbuf.clone()).await?;
+        self.parse_cache
+            .get_or_parse(&pathbuf.to_path_buf(), file_content.as_bytes(), || self.parse(&file_content, striped_pathbuf))
+    }
+
+    /// find all the files matched by the wildcard path
+    async fn expand_wildcard(&self, entry: &Path, striped_pathbuf: &Path) -> ZhangResult<Vec<PathBuf>> {
+        // Split path into components and find wildcard level
+        let mut path_components: Vec<String> = striped_pathbuf.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+        let first_component = path_components.remove(0);
+        let wildcard_component = WildcardPathComponent {
+            path: first_component,
+            remaining: path_components,
+        };
+        let mut queue: VecDeque<WildcardPathComponent> = VecDeque::new();
+        queue.push_back(wildcard_component);
+
+        let mut final_file_paths: Vec<PathBuf> = vec![];
+        while let Some(mut current_component) = queue.pop_front() {
+            let mut current_path = PathBuf::new();
+            current_path.push(current_component.path);
+
+            let next_component = current_component.remaining.remove(0);
+
+            let next_component_path = current_path.join(&next_component);
+            if !next_component.contains('*') {
+                // if the next component is not a wildcard, we can just add it to the current path
+                queue.push_back(WildcardPathComponent {
+                    path: next_component_path.to_string_lossy().to_string(),
+                    remaining: current_component.remaining,
+                });
+                continue;
+            }
+            // if the next component is a wildcard, we need to add all the files in the current path to the final file paths
+
+            let current_path_str = format!("{}/", current_path.to_string_lossy());
+            let files = self
+                .operator
+                .list(&current_path_str)
+                .await
+                .map_err(|e| ZhangError::CustomError(format!("fail to list files in parent directory [{}] : {}", current_path.display(), e)))?;
+
+            let re = regex::Regex::new(&next_component.replace('*', "[^/]+")).unwrap();
+
+            for entry in files {
+                let entry_name = entry.path();
+
+                if entry.metadata().is_dir() {
+                    let striped_entry_name = entry.path().strip_prefix(&current_path_str).unwrap().strip_suffix("/").unwrap();
+                    if re.is_match(striped_entry_name) {
+                        // Build full path
+                        if !current_component.remaining.is_empty() {
+                            queue.push_back(WildcardPathComponent {
+                                path: current_path.join(striped_entry_name).to_string_lossy().to_string(),
+                                remaining: current_component.remaining.clone(),
+                            });
+                        }
+                    }
+                } else {
+                    let striped_entry_name = entry_name.strip_prefix(&current_path_str).unwrap();
+                    if re.is_match(striped_entry_name) {
+                        // Build full path
+                        let is_remaining_empty = current_component.remaining.is_empty();
+                        if is_remaining_empty {
+                            final_file_paths.push(current_path.join(striped_entry_name));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(final_file_paths
+            .into_iter()
+            .map(|file_path| {
+                if file_path.as_path().starts_with("/") {
+                    file_path
+                } else {
+                    entry.join(file_path)
+                }
+            })
+            .collect())
+    }
+
     #[async_recursion]
-    async fn append_directive(&self, ledger: &Ledger, directive: Directive, file: Option<PathBuf>, check_file_visit: bool) -> ZhangResult<()> {
+    async fn append_directive(
+        &self, ledger: &Ledger, directive: Directive, file: Option<PathBuf>, visited_files: Option<&mut Vec<PathBuf>>,
+    ) -> ZhangResult<()> {
         let (entry, main_file_endpoint) = &ledger.entry;
 
         let endpoint = if let Some(file) = file {
@@ -228,23 +279,26 @@ impl OpendalDataSource {
         };
         let striped_endpoint = endpoint.strip_prefix(entry).expect("cannot strip entry prefix");
 
-        if !has_path_visited(&ledger.visited_files, &endpoint) && check_file_visit {
-            let path = match endpoint.strip_prefix(entry) {
-                Ok(relative_path) => relative_path.to_str().unwrap(),
-                Err(_) => endpoint.to_str().unwrap(),
-            };
-            self.append_directive(
-                ledger,
-                Directive::Include(Include {
-                    file: ZhangString::QuoteString(path.to_string()),
-                }),
-                None,
-                false,
-            )
-            .await?;
+        if let Some(visited_files) = visited_files {
+            if !has_path_visited(visited_files.iter(), &endpoint) {
+                let path = match endpoint.strip_prefix(entry) {
+                    Ok(relative_path) => relative_path.to_str().unwrap(),
+                    Err(_) => endpoint.to_str().unwrap(),
+                };
+                self.append_directive(
+                    ledger,
+                    Directive::Include(Include {
+                        file: ZhangString::QuoteString(path.to_string()),
+                    }),
+                    None,
+                    None,
+                )
+                .await?;
+                visited_files.push(endpoint.clone());
+            }
         }
 
-        let content_buf = ledger.data_source.async_get(striped_endpoint.to_string_lossy().to_string()).await?;
+        let content_buf = self.get_or_empty(&striped_endpoint.to_string_lossy()).await?;
         let content = String::from_utf8(content_buf)?;
 
         let appended_content = format!("{}\n{}\n", content, self.data_type.export(Spanned::new(directive, SpanInfo::default())));
@@ -257,7 +311,7 @@ impl OpendalDataSource {
     }
     pub async fn from_env(source: FileSystem, server_opts: &mut ServerOpts) -> OpendalDataSource {
         let operator = match source {
-            FileSystem::Fs => {
+            FileSystem::Fs | FileSystem::Git => {
                 let mut builder = Fs::default();
                 builder.root(server_opts.path.to_string_lossy().to_string().as_str());
                 // Operator::new(builder).unwrap().finish()
@@ -305,6 +359,7 @@ impl OpendalDataSource {
             operator,
             data_type: new_data_type,
             is_beancount,
+            parse_cache: ParseCache::default(),
         }
     }
 
@@ -337,7 +392,7 @@ impl OpendalDataSource {
     async fn get_file_content(&self, path: PathBuf) -> ZhangResult<String> {
         let path = path.to_str().expect("cannot convert path to string");
 
-        let vec = self.async_get(path.to_string()).await?;
+        let vec = self.get_or_empty(path).await?;
         Ok(String::from_utf8(vec).expect("invalid utf8 content"))
     }
 }
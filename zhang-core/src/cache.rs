@@ -0,0 +1,427 @@
+//! the processed ledger can be cached into a file, so that loading the ledger again skips parsing and processing
+//! if none of its files is changed.
+//!
+//! the cache file contains two lines, the header describing the ledger and the hash of every visited file,
+//! and the body containing the processed directives and store. The header is checked before the body is deserialized.
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, FixedOffset};
+use chrono_tz::Tz;
+use log::{info, warn};
+use serde::{Deserialize, Deserializer, Serialize};
+use zhang_ast::{Directive, SpanInfo, Spanned};
+
+use crate::data_source::DataSource;
+use crate::domains::Operations;
+use crate::error::IoErrorIntoZhangError;
+use crate::ledger::Ledger;
+use crate::options::InMemoryOptions;
+use crate::store::Store;
+use crate::utils::content_hash;
+use crate::{ZhangError, ZhangResult};
+
+/// the version of cache layout, cache files of other versions are ignored. It needs to be bumped once the store is changed.
+const CACHE_VERSION: u32 = 3;
+
+/// datetime in store is cached as the instant along with its offset, and is held in UTC once deserialized,
+/// until the ledger timezone is attached by [`Store::attach_timezone`], since the timezone is an option of ledger.
+pub(crate) fn deserialize_instant<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Tz>, D::Error> {
+    let datetime = DateTime::<FixedOffset>::deserialize(deserializer)?;
+    Ok(datetime.with_timezone(&Tz::UTC))
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheHeader {
+    version: u32,
+    entry: PathBuf,
+    endpoint: String,
+    timezone: String,
+    files: Vec<CachedFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedFile {
+    path: PathBuf,
+    hash: String,
+}
+
+#[derive(Serialize)]
+struct CacheBody<'a> {
+    metas: &'a [Spanned<Directive>],
+    directives: &'a [Spanned<Directive>],
+    store: &'a Store,
+    trx_counter: i32,
+}
+
+#[derive(Deserialize)]
+struct CachedLedger {
+    metas: Vec<Spanned<Directive>>,
+    directives: Vec<Spanned<Directive>>,
+    store: Store,
+    trx_counter: i32,
+}
+
+/// read the header and the body of cache file, `None` is returned if the cache is absent or written for other ledger
+fn read_cache(cache: &Path, entry: &Path, endpoint: &str) -> Option<(CacheHeader, String)> {
+    let mut content = match std::fs::read_to_string(cache) {
+        Ok(content) => content,
+        Err(e) => {
+            if e.kind() != ErrorKind::NotFound {
+                warn!("fail to read ledger cache {}: {}", cache.display(), e);
+            }
+            return None;
+        }
+    };
+    let body = content.split_off(content.find('\n')? + 1);
+    let header: CacheHeader = serde_json::from_str(&content)
+        .map_err(|e| warn!("invalid ledger cache {}: {}", cache.display(), e))
+        .ok()?;
+    if header.version != CACHE_VERSION || header.entry != entry || header.endpoint != endpoint {
+        info!("ledger cache {} is not written for current ledger", cache.display());
+        return None;
+    }
+    Some((header, body))
+}
+
+/// whether all the files are the same as the ones the cache is written from
+fn is_cache_fresh(header: &CacheHeader, contents: Vec<ZhangResult<Vec<u8>>>) -> bool {
+    header
+        .files
+        .iter()
+        .zip(contents)
+        .all(|(file, content)| content.is_ok_and(|content| content_hash(content).eq(&file.hash)))
+}
+
+/// write the content given by [Ledger::cache_content] into the cache file
+pub fn write_cache(cache: &Path, content: String) -> ZhangResult<()> {
+    std::fs::write(cache, content).with_path(cache)?;
+    info!("ledger cache is written into {}", cache.display());
+    Ok(())
+}
+
+/// rebuild the options from the processed option values
+fn restore_options(store: &Store) -> ZhangResult<InMemoryOptions> {
+    let mut options = InMemoryOptions::default();
+    // the options have been processed, so the side effects on store are dropped
+    let mut operations = Operations {
+        timezone: options.timezone,
+        store: Default::default(),
+        price_grips: Default::default(),
+    };
+    for (key, value) in store.options.iter() {
+        options.parse(key, value, &mut operations, &SpanInfo::default())?;
+    }
+    Ok(options)
+}
+
+impl Ledger {
+    /// load the ledger from the cache file if none of its files is changed,
+    /// otherwise load it from the data source and write it into the cache file.
+    pub fn load_with_cache(entry: PathBuf, endpoint: String, data_source: Arc<dyn DataSource>, cache: &Path) -> ZhangResult<Ledger> {
+        // the entry is kept as given if it cannot be canonicalized, the same as the async loader
+        let entry = entry.canonicalize().unwrap_or(entry);
+        if let Some((header, body)) = read_cache(cache, &entry, &endpoint) {
+            let contents = header
+                .files
+                .iter()
+                .map(|file| data_source.get(data_source.visited_file_path(&entry, &file.path)))
+                .collect();
+            if is_cache_fresh(&header, contents) {
+                match Ledger::restore(header, &body, (entry.clone(), endpoint.clone()), data_source.clone()) {
+                    Ok(ledger) => return Ok(ledger),
+                    Err(e) => warn!("fail to restore ledger from cache {}: {}", cache.display(), e),
+                }
+            }
+        }
+        let ledger = Ledger::load_with_data_source(entry, endpoint, data_source)?;
+        if let Err(e) = ledger.save_cache(cache) {
+            warn!("fail to write ledger cache {}: {}", cache.display(), e);
+        }
+        Ok(ledger)
+    }
+
+    pub async fn async_load_with_cache(entry: PathBuf, endpoint: String, data_source: Arc<dyn DataSource>, cache: &Path) -> ZhangResult<Ledger> {
+        // the cache is shared with the sync loader, whose entry is canonicalized, but the entry of remote data sources may not exist locally
+        let entry = entry.canonicalize().unwrap_or(entry);
+        if let Some((header, body)) = read_cache(cache, &entry, &endpoint) {
+            let mut contents = Vec::with_capacity(header.files.len());
+            for file in header.files.iter() {
+                contents.push(data_source.async_get(data_source.visited_file_path(&entry, &file.path)).await);
+            }
+            if is_cache_fresh(&header, contents) {
+                match Ledger::restore(header, &body, (entry.clone(), endpoint.clone()), data_source.clone()) {
+                    Ok(ledger) => return Ok(ledger),
+                    Err(e) => warn!("fail to restore ledger from cache {}: {}", cache.display(), e),
+                }
+            }
+        }
+        let ledger = Ledger::async_load(entry, endpoint, data_source).await?;
+        if let Err(e) = ledger.save_cache(cache) {
+            warn!("fail to write ledger cache {}: {}", cache.display(), e);
+        }
+        Ok(ledger)
+    }
+
+    /// write the processed ledger into the cache file
+    pub fn save_cache(&self, cache: &Path) -> ZhangResult<()> {
+        match self.cache_content()? {
+            Some(content) => write_cache(cache, content),
+            None => Ok(()),
+        }
+    }
+
+    /// serialize the processed ledger into the content of cache file, `None` is returned if the ledger cannot be cached.
+    ///
+    /// files are recorded with the hashes of the content they were parsed from, so a file changed after loading makes the cache stale.
+    /// it is split from writing, so that the file can be written without holding the ledger.
+    pub fn cache_content(&self) -> ZhangResult<Option<String>> {
+        if !self.is_cacheable() {
+            return Ok(None);
+        }
+        let (entry, endpoint) = &self.entry;
+        let header = CacheHeader {
+            version: CACHE_VERSION,
+            entry: entry.clone(),
+            endpoint: endpoint.clone(),
+            timezone: self.options.timezone.name().to_owned(),
+            files: self
+                .visited_files
+                .iter()
+                .zip(self.file_hashes.iter())
+                .map(|(path, hash)| CachedFile {
+                    path: path.clone(),
+                    hash: hash.clone(),
+                })
+                .collect(),
+        };
+        let store = self.store.read().unwrap();
+        let body = CacheBody {
+            metas: &self.metas,
+            directives: &self.directives,
+            store: &store,
+            trx_counter: self.trx_counter.load(Ordering::Relaxed),
+        };
+        let to_json_error = |e: serde_json::Error| ZhangError::CustomError(format!("fail to serialize ledger cache: {}", e));
+        Ok(Some(format!(
+            "{}\n{}",
+            serde_json::to_string(&header).map_err(to_json_error)?,
+            serde_json::to_string(&body).map_err(to_json_error)?
+        )))
+    }
+
+    /// plugins are not restorable, and files matched by wildcard include may be added without changing any visited file.
+    /// the data source must also report the hash of the content each visited file is parsed from.
+    fn is_cacheable(&self) -> bool {
+        let has_wildcard_include = self.metas.iter().any(|it| match &it.data {
+            Directive::Include(include) => include.file.as_str().contains('*'),
+            _ => false,
+        });
+        !self.options.features.plugins && !has_wildcard_include && self.file_hashes.len() == self.visited_files.len()
+    }
+
+    fn restore(header: CacheHeader, body: &str, entry: (PathBuf, String), data_source: Arc<dyn DataSource>) -> ZhangResult<Ledger> {
+        let timezone = header
+            .timezone
+            .parse::<Tz>()
+            .map_err(|e| ZhangError::CustomError(format!("invalid timezone in ledger cache: {}", e)))?;
+        let CachedLedger {
+            metas,
+            directives,
+            mut store,
+            trx_counter,
+        } = serde_json::from_str(body).map_err(|e| ZhangError::CustomError(format!("invalid ledger cache: {}", e)))?;
+
+        store.attach_timezone(timezone);
+        store.rebuild_posting_index();
+        let options = restore_options(&store)?;
+        info!("ledger is restored from cache");
+        let (visited_files, file_hashes) = header.files.into_iter().map(|it| (it.path, it.hash)).unzip();
+        Ok(Ledger {
+            entry,
+            data_source,
+            visited_files,
+            file_hashes,
+            options,
+            directives,
+            metas,
+            store: Arc::new(RwLock::new(store)),
+            trx_counter: AtomicI32::new(trx_counter),
+            #[cfg(feature = "plugin_runtime")]
+            plugins: crate::plugin::store::PluginStore::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use indoc::indoc;
+    use itertools::Itertools;
+    use tempfile::tempdir;
+
+    use crate::data_source::LocalFileSystemDataSource;
+    use crate::data_type::text::ZhangDataType;
+    use crate::ledger::Ledger;
+
+    #[test]
+    fn should_restore_ledger_from_cache_until_file_changed() {
+        let temp_dir = tempdir().unwrap().into_path();
+        let cache = temp_dir.join("cache.json");
+        std::fs::write(
+            temp_dir.join("main.zhang"),
+            indoc! {r#"
+                option "timezone" "Asia/Hong_Kong"
+                include "data.zhang"
+            "#},
+        )
+        .unwrap();
+        std::fs::write(
+            temp_dir.join("data.zhang"),
+            indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Food
+                2024-01-01 "KFC" "Lunch"
+                  Assets:Bank -10 CNY
+                  Expenses:Food 10 CNY
+            "#},
+        )
+        .unwrap();
+        let load = || {
+            let source = LocalFileSystemDataSource::new(ZhangDataType {});
+            Ledger::load_with_cache(temp_dir.clone(), "main.zhang".to_string(), Arc::new(source), &cache).unwrap()
+        };
+
+        let loaded = load();
+        // the cached payee is altered to tell whether the ledger is restored from cache
+        let cached = std::fs::read_to_string(&cache).unwrap();
+        std::fs::write(&cache, cached.replace("KFC", "McDonald")).unwrap();
+
+        let restored = load();
+        assert_eq!("Asia/Hong_Kong", restored.options.timezone.name());
+        assert_eq!(loaded.visited_files, restored.visited_files);
+        assert_eq!(loaded.directives.len(), restored.directives.len());
+        {
+            let store = restored.store.read().unwrap();
+            let transaction = store.transactions.values().exactly_one().ok().unwrap();
+            assert_eq!(Some("McDonald"), transaction.payee.as_deref());
+            let datetimes = store.dated_postings(None, None).map(|it| it.trx_datetime).collect::<Vec<_>>();
+            assert_eq!(2, datetimes.len());
+            assert!(datetimes.iter().all(|it| it.timezone().name() == "Asia/Hong_Kong"));
+        }
+
+        std::fs::write(
+            temp_dir.join("data.zhang"),
+            indoc! {r#"
+                1970-01-01 open Assets:Bank
+            "#},
+        )
+        .unwrap();
+        let reloaded = load();
+        assert_eq!(0, reloaded.store.read().unwrap().transactions.len());
+        assert_eq!(0, load().store.read().unwrap().transactions.len());
+    }
+
+    #[test]
+    fn should_attach_ledger_timezone_to_every_datetime_restored_from_cache() {
+        let temp_dir = tempdir().unwrap().into_path();
+        let cache = temp_dir.join("cache.json");
+        std::fs::write(
+            temp_dir.join("main.zhang"),
+            indoc! {r#"
+                option "timezone" "Asia/Hong_Kong"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Food
+                2024-01-01 budget Diet CNY
+                2024-01-01 08:30:00 budget-add Diet 100 CNY
+                2024-01-01 09:00:00 document Assets:Bank "receipt.pdf"
+                2024-01-01 12:00:00 "KFC" "Lunch"
+                  Assets:Bank -10 CNY
+                  Expenses:Food 10 CNY
+            "#},
+        )
+        .unwrap();
+        let load = || {
+            let source = LocalFileSystemDataSource::new(ZhangDataType {});
+            Ledger::load_with_cache(temp_dir.clone(), "main.zhang".to_string(), Arc::new(source), &cache).unwrap()
+        };
+        let datetimes_of = |ledger: &Ledger| {
+            let store = ledger.store.read().unwrap();
+            let mut datetimes = vec![];
+            for transaction in store.transactions.values() {
+                datetimes.push(transaction.datetime);
+                datetimes.extend(transaction.postings.iter().map(|it| it.trx_datetime));
+            }
+            datetimes.extend(store.postings().iter().map(|it| it.trx_datetime));
+            datetimes.extend(store.documents.iter().map(|it| it.datetime));
+            datetimes.extend(
+                store
+                    .budgets
+                    .values()
+                    .flat_map(|budget| budget.detail.values())
+                    .flat_map(|detail| detail.events.iter().map(|it| it.datetime)),
+            );
+            datetimes
+        };
+
+        let loaded = load();
+        // the cached payee is altered to tell whether the ledger is restored from cache
+        let cached = std::fs::read_to_string(&cache).unwrap();
+        std::fs::write(&cache, cached.replace("KFC", "McDonald")).unwrap();
+
+        let restored = load();
+        let payee = restored.store.read().unwrap().transactions.values().exactly_one().ok().unwrap().payee.clone();
+        assert_eq!(Some("McDonald".to_owned()), payee);
+        let datetimes = datetimes_of(&restored);
+        // the transaction, its postings in both places, the document and the budget events
+        assert_eq!(7, datetimes.len());
+        assert_eq!(datetimes_of(&loaded), datetimes);
+        assert!(datetimes.iter().all(|it| it.timezone().name() == "Asia/Hong_Kong"));
+    }
+
+    #[tokio::test]
+    async fn should_share_cache_between_sync_and_async_loading() {
+        let temp_dir = tempdir().unwrap().into_path();
+        let cache = temp_dir.join("cache.json");
+        std::fs::write(temp_dir.join("main.zhang"), "1970-01-01 open Assets:Bank\n").unwrap();
+        // the entry given by user is not canonical
+        let entry = temp_dir.join("..").join(temp_dir.file_name().unwrap());
+
+        let source = LocalFileSystemDataSource::new(ZhangDataType {});
+        Ledger::load_with_cache(entry.clone(), "main.zhang".to_string(), Arc::new(source), &cache).unwrap();
+        // the cached account is altered to tell whether the ledger is restored from cache
+        let cached = std::fs::read_to_string(&cache).unwrap();
+        std::fs::write(&cache, cached.replace("Assets:Bank", "Assets:Cash")).unwrap();
+
+        let source = LocalFileSystemDataSource::new(ZhangDataType {});
+        let restored = Ledger::async_load_with_cache(entry, "main.zhang".to_string(), Arc::new(source), &cache)
+            .await
+            .unwrap();
+        let store = restored.store.read().unwrap();
+        assert_eq!(vec!["Assets:Cash"], store.accounts.keys().collect_vec());
+    }
+
+    #[test]
+    fn should_not_restore_cache_if_file_is_changed_after_loading() {
+        let temp_dir = tempdir().unwrap().into_path();
+        let cache = temp_dir.join("cache.json");
+        std::fs::write(temp_dir.join("main.zhang"), "1970-01-01 open Assets:Bank\n").unwrap();
+        let load = || {
+            let source = LocalFileSystemDataSource::new(ZhangDataType {});
+            Ledger::load_with_data_source(temp_dir.clone(), "main.zhang".to_string(), Arc::new(source)).unwrap()
+        };
+        let ledger = load();
+
+        // the file is changed after the ledger is loaded, but before the cache is written
+        std::fs::write(temp_dir.join("main.zhang"), "1970-01-01 open Assets:Cash\n").unwrap();
+        ledger.save_cache(&cache).unwrap();
+
+        let source = LocalFileSystemDataSource::new(ZhangDataType {});
+        let reloaded = Ledger::load_with_cache(temp_dir.clone(), "main.zhang".to_string(), Arc::new(source), &cache).unwrap();
+        let store = reloaded.store.read().unwrap();
+        assert_eq!(vec!["Assets:Cash"], store.accounts.keys().collect_vec());
+    }
+}
@@ -1,15 +1,19 @@
-use std::collections::VecDeque;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use chrono::Datelike;
 use log::debug;
+use rayon::prelude::*;
 use zhang_ast::{Directive, Include, SpanInfo, Spanned, ZhangString};
 
 use crate::data_type::DataType;
 use crate::error::IoErrorIntoZhangError;
 use crate::ledger::Ledger;
-use crate::utils::has_path_visited;
-use crate::ZhangResult;
+use crate::utils::string_::StringExt;
+use crate::utils::{content_hash, has_path_visited};
+use crate::{ZhangError, ZhangResult};
 
 /// `DataSource` is the protocol to describe how the `DataType` be stored and be transformed into standard directives.
 /// The Data Source have two capabilities:
@@ -32,6 +36,11 @@ where
         unimplemented!()
     }
 
+    /// the path accepted by `get` of the file in [LoadResult::visited_files]
+    fn visited_file_path(&self, _entry: &Path, visited_file: &Path) -> String {
+        visited_file.to_string_lossy().to_string()
+    }
+
     fn save(&self, _ledger: &Ledger, _path: String, _content: &[u8]) -> ZhangResult<()> {
         unimplemented!()
     }
@@ -40,6 +49,26 @@ where
         unimplemented!()
     }
 
+    /// delete the file, used to undo the creation of a file
+    fn remove(&self, _ledger: &Ledger, _path: String) -> ZhangResult<()> {
+        unimplemented!()
+    }
+
+    /// replace the directive located by the span with the given directive, or remove it if `None` is given.
+    /// the span must be the one given by parsing, whose content is compared with the current file to detect conflicts.
+    fn replace(&self, ledger: &Ledger, span: &SpanInfo, directive: Option<Directive>) -> ZhangResult<()> {
+        let replacement = directive.map(|it| self.export(it)).transpose()?.map(String::from_utf8).transpose()?;
+        self.replace_content(ledger, span, replacement)
+    }
+
+    /// like [DataSource::replace], but the content located by the span is replaced with the given text as it is
+    fn replace_content(&self, ledger: &Ledger, span: &SpanInfo, replacement: Option<String>) -> ZhangResult<()> {
+        let path = span_path(span)?;
+        let content = String::from_utf8(self.get(path.clone())?)?;
+        let content = splice(content, span, replacement)?;
+        self.save(ledger, path, content.as_bytes())
+    }
+
     async fn async_load(&self, entry: String, endpoint: String) -> ZhangResult<LoadResult> {
         self.load(entry, endpoint)
     }
@@ -54,6 +83,115 @@ where
     async fn async_save(&self, ledger: &Ledger, path: String, content: &[u8]) -> ZhangResult<()> {
         self.save(ledger, path, content)
     }
+
+    async fn async_remove(&self, ledger: &Ledger, path: String) -> ZhangResult<()> {
+        self.remove(ledger, path)
+    }
+
+    async fn async_replace(&self, ledger: &Ledger, span: &SpanInfo, directive: Option<Directive>) -> ZhangResult<()> {
+        let replacement = directive.map(|it| self.export(it)).transpose()?.map(String::from_utf8).transpose()?;
+        self.async_replace_content(ledger, span, replacement).await
+    }
+
+    async fn async_replace_content(&self, ledger: &Ledger, span: &SpanInfo, replacement: Option<String>) -> ZhangResult<()> {
+        let path = span_path(span)?;
+        let content = String::from_utf8(self.async_get(path.clone()).await?)?;
+        let content = splice(content, span, replacement)?;
+        self.async_save(ledger, path, content.as_bytes()).await
+    }
+}
+
+fn span_path(span: &SpanInfo) -> ZhangResult<String> {
+    span.filename
+        .as_ref()
+        .map(|it| it.to_string_lossy().to_string())
+        .ok_or_else(|| ZhangError::CustomError("cannot locate the directive without source file".to_owned()))
+}
+
+/// replace the content at the span with the replacement, or remove it if no replacement is given
+fn splice(mut content: String, span: &SpanInfo, replacement: Option<String>) -> ZhangResult<String> {
+    if content.get(span.start..span.end) != Some(span.content.as_str()) {
+        return Err(ZhangError::ConflictedContent {
+            path: span.filename.clone().unwrap_or_default(),
+            content,
+        });
+    }
+    match replacement {
+        Some(replacement) => content.replace_by_span(span, &replacement),
+        None => content.remove_by_span(span),
+    }
+    Ok(content)
+}
+
+/// `ParseCache` keeps the parsed directives of each file keyed by the hash of its content,
+/// so that only the changed files are parsed again when the ledger is reloaded.
+#[derive(Default)]
+pub struct ParseCache {
+    entries: Mutex<HashMap<PathBuf, ParsedFile>>,
+}
+
+/// the directives parsed from a file, together with the hash of the content they are parsed from
+#[derive(Clone)]
+pub struct ParsedFile {
+    pub hash: String,
+    pub directives: Vec<Spanned<Directive>>,
+    /// the directives appended since the file was parsed last time, `None` if its content is changed otherwise
+    pub appended: Option<FileAppend>,
+}
+
+/// the directives appended to a file since its content of `base_hash` was parsed, `base_hash` is `None` if the file was not parsed before.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileAppend {
+    pub base_hash: Option<String>,
+    /// the range of the appended directives, in the directives of the file or in [LoadResult::directives]
+    pub directives: Range<usize>,
+}
+
+impl FileAppend {
+    /// move the range by the count of directives loaded before the file
+    pub fn offset(self, offset: usize) -> FileAppend {
+        FileAppend {
+            base_hash: self.base_hash,
+            directives: self.directives.start + offset..self.directives.end + offset,
+        }
+    }
+}
+
+impl ParseCache {
+    /// get the cached directives of the file if its content is not changed, otherwise parse it and cache the result
+    pub fn get_or_parse(&self, path: &PathBuf, content: &[u8], parse: impl FnOnce() -> ZhangResult<Vec<Spanned<Directive>>>) -> ZhangResult<ParsedFile> {
+        let hash = content_hash(content);
+        let previous = {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get(path) {
+                Some(parsed) if parsed.hash.eq(&hash) => {
+                    debug!("parse cache hit: {}", path.display());
+                    return Ok(parsed.clone());
+                }
+                _ => entries.remove(path),
+            }
+        };
+        let directives = parse()?;
+        let appended = match previous {
+            None => Some(FileAppend {
+                base_hash: None,
+                directives: 0..directives.len(),
+            }),
+            // the file is only appended if the previously parsed directives are kept as they are
+            Some(previous) => directives.starts_with(&previous.directives).then_some(FileAppend {
+                base_hash: Some(previous.hash),
+                directives: previous.directives.len()..directives.len(),
+            }),
+        };
+        let parsed = ParsedFile { hash, directives, appended };
+        self.entries.lock().unwrap().insert(path.clone(), parsed.clone());
+        Ok(parsed)
+    }
+
+    /// drop the files which are not included by ledger anymore
+    pub fn retain(&self, visited_files: &[PathBuf]) {
+        self.entries.lock().unwrap().retain(|path, _| has_path_visited(visited_files, path));
+    }
 }
 
 /// `LocalFileSystemDataSource` is the data source that store the data in the local file system.
@@ -63,12 +201,14 @@ where
 ///
 pub struct LocalFileSystemDataSource {
     data_type: Box<dyn DataType<Carrier = String> + 'static + Send + Sync>,
+    parse_cache: ParseCache,
 }
 
 impl LocalFileSystemDataSource {
     pub fn new<DT: DataType<Carrier = String> + Send + Sync + 'static>(data_type: DT) -> Self {
         LocalFileSystemDataSource {
             data_type: Box::new(data_type),
+            parse_cache: ParseCache::default(),
         }
     }
     fn go_next(&self, directive: &Spanned<Directive>) -> Option<String> {
@@ -78,11 +218,21 @@ impl LocalFileSystemDataSource {
         }
     }
 
+    fn load_file(&self, pathbuf: &PathBuf) -> ZhangResult<ParsedFile> {
+        let file_content = self.get(pathbuf.to_string_lossy().to_string())?;
+        self.parse_cache.get_or_parse(pathbuf, &file_content, || {
+            self.data_type
+                .transform(String::from_utf8_lossy(&file_content).to_string(), Some(pathbuf.to_string_lossy().to_string()))
+        })
+    }
+
     pub(crate) fn create_folder_if_not_exist(filename: &std::path::Path) {
         std::fs::create_dir_all(filename.parent().unwrap()).expect("cannot create folder recursive");
     }
 
-    fn append_directive(&self, ledger: &Ledger, directive: Directive, file: Option<PathBuf>, check_file_visit: bool) -> ZhangResult<()> {
+    /// append the directive into its target file, the target file is included into ledger if it is not in `visited_files`.
+    /// `visited_files` is updated with the newly included files, so that directives of the same batch are not included twice.
+    fn append_directive(&self, ledger: &Ledger, directive: Directive, file: Option<PathBuf>, visited_files: Option<&mut Vec<PathBuf>>) -> ZhangResult<()> {
         let (entry, main_file_endpoint) = &ledger.entry;
 
         let endpoint = file.unwrap_or_else(|| {
@@ -95,23 +245,29 @@ impl LocalFileSystemDataSource {
 
         LocalFileSystemDataSource::create_folder_if_not_exist(&endpoint);
 
-        if !has_path_visited(&ledger.visited_files, &endpoint) && check_file_visit {
-            let path = match endpoint.strip_prefix(entry) {
-                Ok(relative_path) => relative_path.to_str().unwrap(),
-                Err(_) => endpoint.to_str().unwrap(),
-            };
-            self.append_directive(
-                ledger,
-                Directive::Include(Include {
-                    file: ZhangString::QuoteString(path.to_string()),
-                }),
-                None,
-                false,
-            )?;
+        if let Some(visited_files) = visited_files {
+            if !has_path_visited(visited_files.iter(), &endpoint) {
+                let path = match endpoint.strip_prefix(entry) {
+                    Ok(relative_path) => relative_path.to_str().unwrap(),
+                    Err(_) => endpoint.to_str().unwrap(),
+                };
+                self.append_directive(
+                    ledger,
+                    Directive::Include(Include {
+                        file: ZhangString::QuoteString(path.to_string()),
+                    }),
+                    None,
+                    None,
+                )?;
+                visited_files.push(endpoint.clone());
+            }
         }
 
-        let content_buf = ledger.data_source.get(endpoint.to_string_lossy().to_string())?;
-        let content = String::from_utf8(content_buf)?;
+        let content = if endpoint.exists() {
+            String::from_utf8(ledger.data_source.get(endpoint.to_string_lossy().to_string())?)?
+        } else {
+            String::new()
+        };
 
         let appended_content = format!("{}\n{}\n", content, self.data_type.export(Spanned::new(directive, SpanInfo::default())));
 
@@ -138,36 +294,49 @@ impl DataSource for LocalFileSystemDataSource {
         let main_endpoint = entry.join(endpoint);
         let main_endpoint = main_endpoint.canonicalize().with_path(&main_endpoint)?;
 
-        let mut load_queue: VecDeque<PathBuf> = VecDeque::new();
-        load_queue.push_back(main_endpoint);
-
         let mut visited: Vec<PathBuf> = Vec::new();
+        let mut file_hashes = vec![];
+        let mut file_appends = vec![];
         let mut directives = vec![];
-        while let Some(pathbuf) = load_queue.pop_front() {
-            debug!("visited entry file: {:?}", pathbuf.display());
+        // files included at the same depth are parsed in parallel, and their directives are kept in the including order
+        let mut current_level = vec![main_endpoint];
+        while !current_level.is_empty() {
+            let mut files: Vec<PathBuf> = Vec::with_capacity(current_level.len());
+            for pathbuf in current_level {
+                if !has_path_visited(&visited, &pathbuf) && !has_path_visited(&files, &pathbuf) {
+                    files.push(pathbuf);
+                }
+            }
+            let parsed_files = files.par_iter().map(|pathbuf| self.load_file(pathbuf)).collect::<Vec<_>>();
 
-            if has_path_visited(&visited, &pathbuf) {
-                continue;
+            current_level = vec![];
+            for (pathbuf, parsed_file) in files.into_iter().zip(parsed_files) {
+                debug!("visited entry file: {:?}", pathbuf.display());
+                let ParsedFile {
+                    hash,
+                    directives: entity_directives,
+                    appended,
+                } = parsed_file?;
+                entity_directives.iter().filter_map(|directive| self.go_next(directive)).for_each(|buf| {
+                    let fullpath = if buf.starts_with('/') {
+                        PathBuf::from(&buf)
+                    } else {
+                        pathbuf.parent().map(|it| it.join(buf)).unwrap()
+                    };
+                    current_level.push(fullpath);
+                });
+                file_appends.push(appended.map(|it| it.offset(directives.len())));
+                directives.extend(entity_directives);
+                visited.push(pathbuf);
+                file_hashes.push(hash);
             }
-            let file_content = self.get(pathbuf.to_string_lossy().to_string())?;
-            let entity_directives = self
-                .data_type
-                .transform(String::from_utf8_lossy(&file_content).to_string(), Some(pathbuf.to_string_lossy().to_string()))?;
-
-            entity_directives.iter().filter_map(|directive| self.go_next(directive)).for_each(|buf| {
-                let fullpath = if buf.starts_with('/') {
-                    PathBuf::from(&buf)
-                } else {
-                    pathbuf.parent().map(|it| it.join(buf)).unwrap()
-                };
-                load_queue.push_back(fullpath);
-            });
-            directives.extend(entity_directives);
-            visited.push(pathbuf);
         }
+        self.parse_cache.retain(&visited);
         Ok(LoadResult {
             directives,
             visited_files: visited,
+            file_hashes,
+            file_appends,
         })
     }
 
@@ -175,9 +344,14 @@ impl DataSource for LocalFileSystemDataSource {
         std::fs::write(&path, content).with_path(PathBuf::from(path).as_path())
     }
 
+    fn remove(&self, _ledger: &Ledger, path: String) -> ZhangResult<()> {
+        std::fs::remove_file(&path).with_path(PathBuf::from(path).as_path())
+    }
+
     fn append(&self, ledger: &Ledger, directives: Vec<Directive>) -> ZhangResult<()> {
+        let mut visited_files = ledger.visited_files.clone();
         for directive in directives {
-            self.append_directive(ledger, directive, None, true)?;
+            self.append_directive(ledger, directive, None, Some(&mut visited_files))?;
         }
         Ok(())
     }
@@ -186,4 +360,213 @@ impl DataSource for LocalFileSystemDataSource {
 pub struct LoadResult {
     pub directives: Vec<Spanned<Directive>>,
     pub visited_files: Vec<PathBuf>,
+    /// the hash of the content each visited file is parsed from, in the same order as `visited_files`.
+    /// it may be empty if the data source does not track the content, and the ledger cannot be cached then.
+    pub file_hashes: Vec<String>,
+    /// the directives appended to each visited file since it was parsed last time, in the same order as `visited_files`.
+    /// it may be empty if the data source does not track the appending, and the whole ledger is processed on every reload then.
+    pub file_appends: Vec<Option<FileAppend>>,
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use indoc::indoc;
+    use tempfile::tempdir;
+    use zhang_ast::{Directive, ZhangString};
+
+    use crate::data_source::{DataSource, FileAppend, LocalFileSystemDataSource, ParseCache};
+    use crate::data_type::text::ZhangDataType;
+    use crate::data_type::DataType;
+    use crate::importer::csv::{CsvImporter, CsvMapping};
+    use crate::importer::Importer;
+    use crate::ledger::Ledger;
+    use crate::ZhangError;
+
+    #[test]
+    fn should_include_new_file_once_when_appending_directives() {
+        let temp_dir = tempdir().unwrap().into_path();
+        std::fs::write(temp_dir.join("main.zhang"), "1970-01-01 open Assets:Bank\n1970-01-01 open Expenses:Unknown\n").unwrap();
+        let source = LocalFileSystemDataSource::new(ZhangDataType {});
+        let mut ledger = Ledger::load_with_data_source(temp_dir.clone(), "main.zhang".to_string(), Arc::new(source)).unwrap();
+
+        let mapping: CsvMapping =
+            serde_json::from_str(r#"{"account": "Assets:Bank", "counter_account": "Expenses:Unknown", "date": "Date", "amount": "Amount", "currency": "CNY"}"#)
+                .unwrap();
+        let directives = CsvImporter::new(mapping)
+            .import("Date,Amount\n2024-02-01,-10\n2024-02-02,-20\n2024-03-01,100\n")
+            .unwrap();
+        ledger.data_source.append(&ledger, directives).unwrap();
+
+        let main = std::fs::read_to_string(temp_dir.join("main.zhang")).unwrap();
+        assert_eq!(1, main.matches("include \"data/2024/2.zhang\"").count());
+        assert_eq!(1, main.matches("include \"data/2024/3.zhang\"").count());
+
+        ledger.reload().unwrap();
+        assert_eq!(3, ledger.directives.iter().filter(|it| matches!(it.data, Directive::Transaction(_))).count());
+    }
+
+    #[test]
+    fn should_replace_and_remove_directive_by_span() {
+        let temp_dir = tempdir().unwrap().into_path();
+        std::fs::write(
+            temp_dir.join("main.zhang"),
+            indoc! {r#"
+                1970-01-01 open Assets:Bank
+
+                2024-01-01 "Coffee" ""
+                  ; cash back later
+                  Assets:Bank -5 CNY
+                  Expenses:Food
+
+                2024-01-02 "Bakery" ""
+                  Assets:Bank -3 CNY
+                  Expenses:Food
+            "#},
+        )
+        .unwrap();
+        let source = LocalFileSystemDataSource::new(ZhangDataType {});
+        let mut ledger = Ledger::load_with_data_source(temp_dir.clone(), "main.zhang".to_string(), Arc::new(source)).unwrap();
+        let transaction_span = |ledger: &Ledger, payee: &str| {
+            ledger
+                .directives
+                .iter()
+                .find(|it| matches!(&it.data, Directive::Transaction(trx) if trx.payee.as_ref().map(|it| it.as_str()) == Some(payee)))
+                .map(|it| it.span.clone())
+                .unwrap()
+        };
+
+        let span = transaction_span(&ledger, "Coffee");
+        let mut bakery = ledger.directives.iter().find(|it| it.span.start > span.start).unwrap().data.clone();
+        if let Directive::Transaction(trx) = &mut bakery {
+            trx.payee = Some(ZhangString::QuoteString("Tea".to_owned()));
+        }
+        ledger.data_source.replace(&ledger, &span, Some(bakery)).unwrap();
+        // the stale span no longer matches the file content
+        assert!(matches!(
+            ledger.data_source.replace(&ledger, &span, None),
+            Err(ZhangError::ConflictedContent { .. })
+        ));
+
+        ledger.reload().unwrap();
+        let span = transaction_span(&ledger, "Bakery");
+        ledger.data_source.replace(&ledger, &span, None).unwrap();
+
+        let main = std::fs::read_to_string(temp_dir.join("main.zhang")).unwrap();
+        assert!(!main.contains("Coffee"));
+        assert!(!main.contains("Bakery"));
+        assert!(main.contains("Tea"));
+    }
+
+    #[test]
+    fn should_load_included_files_in_including_order() {
+        let temp_dir = tempdir().unwrap().into_path();
+        std::fs::write(temp_dir.join("main.zhang"), "include \"a.zhang\"\ninclude \"b.zhang\"\ninclude \"a.zhang\"\n").unwrap();
+        std::fs::write(temp_dir.join("a.zhang"), "include \"c.zhang\"\n1970-01-01 open Assets:A\n").unwrap();
+        std::fs::write(temp_dir.join("b.zhang"), "1970-01-01 open Assets:B\n").unwrap();
+        std::fs::write(temp_dir.join("c.zhang"), "1970-01-01 open Assets:C\n").unwrap();
+
+        let source = LocalFileSystemDataSource::new(ZhangDataType {});
+        let result = source.load(temp_dir.to_string_lossy().to_string(), "main.zhang".to_string()).unwrap();
+
+        let files = result
+            .visited_files
+            .iter()
+            .map(|it| it.file_name().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["main.zhang", "a.zhang", "b.zhang", "c.zhang"], files);
+        let accounts = result
+            .directives
+            .iter()
+            .filter_map(|it| match &it.data {
+                Directive::Open(open) => Some(open.account.name().to_owned()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["Assets:A", "Assets:B", "Assets:C"], accounts);
+    }
+
+    #[test]
+    fn should_only_parse_changed_files_again() {
+        let cache = ParseCache::default();
+        let path = PathBuf::from("main.zhang");
+        let parsed = Cell::new(0);
+        let parse = |content: &str| {
+            parsed.set(parsed.get() + 1);
+            ZhangDataType {}.transform(content.to_owned(), None)
+        };
+
+        let first = cache
+            .get_or_parse(&path, b"1970-01-01 open Assets:Bank", || parse("1970-01-01 open Assets:Bank"))
+            .unwrap();
+        let second = cache
+            .get_or_parse(&path, b"1970-01-01 open Assets:Bank", || parse("1970-01-01 open Assets:Bank"))
+            .unwrap();
+        assert_eq!(1, parsed.get());
+        assert_eq!(first.directives, second.directives);
+        assert_eq!(first.hash, second.hash);
+
+        cache
+            .get_or_parse(&path, b"1970-01-01 open Assets:Cash", || parse("1970-01-01 open Assets:Cash"))
+            .unwrap();
+        assert_eq!(2, parsed.get());
+
+        cache.retain(&[]);
+        cache
+            .get_or_parse(&path, b"1970-01-01 open Assets:Cash", || parse("1970-01-01 open Assets:Cash"))
+            .unwrap();
+        assert_eq!(3, parsed.get());
+    }
+
+    #[test]
+    fn should_tell_appended_directives_of_file() {
+        let cache = ParseCache::default();
+        let path = PathBuf::from("main.zhang");
+        let parse = |content: &str| ZhangDataType {}.transform(content.to_owned(), None);
+        let original = "1970-01-01 open Assets:Bank\n";
+        let appended = "1970-01-01 open Assets:Bank\n1970-01-01 open Assets:Cash\n";
+        let changed = "1970-01-01 open Assets:Card\n1970-01-01 open Assets:Cash\n";
+
+        let first = cache.get_or_parse(&path, original.as_bytes(), || parse(original)).unwrap();
+        assert_eq!(
+            Some(FileAppend {
+                base_hash: None,
+                directives: 0..1
+            }),
+            first.appended
+        );
+
+        let second = cache.get_or_parse(&path, appended.as_bytes(), || parse(appended)).unwrap();
+        assert_eq!(
+            Some(FileAppend {
+                base_hash: Some(first.hash),
+                directives: 1..2
+            }),
+            second.appended
+        );
+
+        let third = cache.get_or_parse(&path, changed.as_bytes(), || parse(changed)).unwrap();
+        assert_eq!(None, third.appended);
+    }
+
+    #[test]
+    fn should_reload_changed_files() {
+        let temp_dir = tempdir().unwrap().into_path();
+        std::fs::write(temp_dir.join("main.zhang"), "include \"accounts.zhang\"\n1970-01-01 open Assets:Bank\n").unwrap();
+        std::fs::write(temp_dir.join("accounts.zhang"), "1970-01-01 open Expenses:Food\n").unwrap();
+        let source = LocalFileSystemDataSource::new(ZhangDataType {});
+        let mut ledger = Ledger::load_with_data_source(temp_dir.clone(), "main.zhang".to_string(), Arc::new(source)).unwrap();
+        assert_eq!(2, ledger.operations().all_accounts().unwrap().len());
+
+        std::fs::write(
+            temp_dir.join("accounts.zhang"),
+            "1970-01-01 open Expenses:Food\n1970-01-01 open Expenses:Travel\n",
+        )
+        .unwrap();
+        ledger.reload().unwrap();
+        assert_eq!(3, ledger.operations().all_accounts().unwrap().len());
+    }
 }
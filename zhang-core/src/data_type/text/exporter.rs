@@ -1,9 +1,14 @@
+use std::collections::{HashMap, VecDeque};
+
+use chrono::NaiveDateTime;
 use itertools::Itertools;
 use zhang_ast::amount::Amount;
 use zhang_ast::*;
 
+use crate::data_type::text::parser::{header_comment, is_posting_line, meta_line_comment, parse, standalone_comment_line};
 use crate::ledger::Ledger;
 use crate::utils::string_::escape_with_quote;
+use crate::{ZhangError, ZhangResult};
 
 pub trait ZhangDataTypeExportable {
     type Output;
@@ -81,24 +86,14 @@ impl ZhangDataTypeExportable for StringOrAccount {
 impl ZhangDataTypeExportable for Transaction {
     type Output = String;
     fn export(self) -> String {
-        let mut header = vec![
-            Some(self.date.export()),
-            self.flag.map(|it| it.export()),
-            self.payee.map(|it| it.export()),
-            self.narration.map(|it| it.export()),
-        ];
-        let mut tags = self.tags.into_iter().map(|it| Some(format!("#{}", it))).collect_vec();
-        let mut links = self.links.into_iter().map(|it| Some(format!("^{}", it))).collect_vec();
-        header.append(&mut tags);
-        header.append(&mut links);
-
+        let header = transaction_header(&self);
         let mut transaction = self
             .postings
             .into_iter()
             .map(|posting| posting.export())
             .map(|it| format!("  {}", it))
             .collect_vec();
-        transaction.insert(0, header.into_iter().flatten().join(" "));
+        transaction.insert(0, header);
         let mut txn_meta = self.meta.export().into_iter().map(|it| format!("  {}", it)).collect_vec();
         transaction.append(&mut txn_meta);
 
@@ -117,6 +112,7 @@ impl ZhangDataTypeExportable for Posting {
             self.units.map(|it| it.export()),
             cost_string,
             self.price.map(|it| it.export()),
+            self.comment.map(|it| format!("; {}", it)),
         ];
         vec1.into_iter().flatten().join(" ")
     }
@@ -253,6 +249,14 @@ impl ZhangDataTypeExportable for Event {
     }
 }
 
+impl ZhangDataTypeExportable for Query {
+    type Output = String;
+    fn export(self) -> String {
+        let line = [self.date.export(), "query".to_string(), self.name.export(), self.query_string.export()];
+        append_meta(self.meta, line.join(" "))
+    }
+}
+
 impl ZhangDataTypeExportable for Custom {
     type Output = String;
     fn export(self) -> String {
@@ -291,7 +295,7 @@ impl ZhangDataTypeExportable for Include {
 impl ZhangDataTypeExportable for Comment {
     type Output = String;
     fn export(self) -> String {
-        self.content
+        format!("; {}", self.content)
     }
 }
 
@@ -345,6 +349,7 @@ impl ZhangDataTypeExportable for Directive {
             Directive::Price(price) => price.export(),
             Directive::Event(event) => event.export(),
             Directive::Custom(custom) => custom.export(),
+            Directive::Query(query) => query.export(),
             Directive::Option(options) => options.export(),
             Directive::Plugin(plugin) => plugin.export(),
             Directive::Include(include) => include.export(),
@@ -365,11 +370,205 @@ impl ZhangDataTypeExportable for Ledger {
     }
 }
 
+/// options of the canonical formatter
+#[derive(Debug, Clone, Default)]
+pub struct FormatOptions {
+    /// reorder directives by their date. directives without date, like options and includes, are placed ahead.
+    pub sort_by_date: bool,
+}
+
+/// re-emit the content of a zhang file in canonical form.
+///
+/// postings are aligned per transaction and metas are ordered by key, while comment blocks and inline posting comments are preserved.
+/// blank lines between directives are collapsed into a single one.
+pub fn format_content(content: &str, options: &FormatOptions) -> ZhangResult<String> {
+    let directives = parse(content, None).map_err(|it| ZhangError::PestError {
+        path: String::new(),
+        msg: it.to_string(),
+    })?;
+
+    let mut previous_end = None;
+    let directives = directives
+        .into_iter()
+        .map(|directive| {
+            let separated = previous_end.is_some_and(|end| content[end..directive.span.start].matches('\n').count() > 1);
+            previous_end = Some(directive.span.end);
+            FormattedDirective {
+                separated,
+                is_comment: matches!(directive.data, Directive::Comment(_)),
+                datetime: directive.datetime(),
+                content: format_directive(directive),
+            }
+        })
+        .collect_vec();
+
+    let directives = if options.sort_by_date { sort_by_date(directives) } else { directives };
+
+    let mut formatted = String::with_capacity(content.len());
+    for (idx, directive) in directives.into_iter().enumerate() {
+        if idx != 0 {
+            formatted.push_str(if directive.separated { "\n\n" } else { "\n" });
+        }
+        formatted.push_str(&directive.content);
+    }
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+    Ok(formatted)
+}
+
+struct FormattedDirective {
+    /// whether it is separated from the previous directive by blank lines
+    separated: bool,
+    is_comment: bool,
+    datetime: Option<NaiveDateTime>,
+    content: String,
+}
+
+/// sort directives by date, comments are attached to the directive right after them and moved together.
+fn sort_by_date(directives: Vec<FormattedDirective>) -> Vec<FormattedDirective> {
+    let mut groups: Vec<Vec<FormattedDirective>> = vec![];
+    let mut comments = vec![];
+    for directive in directives {
+        if directive.is_comment {
+            comments.push(directive);
+        } else {
+            let mut group = std::mem::take(&mut comments);
+            group.push(directive);
+            groups.push(group);
+        }
+    }
+    groups.sort_by_key(|group| group.last().and_then(|directive| directive.datetime));
+    if !comments.is_empty() {
+        groups.push(comments);
+    }
+    groups.into_iter().flatten().collect_vec()
+}
+
+fn format_directive(directive: Spanned<Directive>) -> String {
+    let formatted = match directive.data {
+        Directive::Transaction(trx) => format_transaction(trx, &directive.span.content),
+        other => other.export(),
+    };
+    with_trailing_comments(formatted, &directive.span.content)
+}
+
+/// put the trailing comments of directive head and metas, which are discarded by parser, back to the formatted lines.
+/// comments of metas follow their keys since metas are reordered.
+fn with_trailing_comments(formatted: String, content: &str) -> String {
+    let mut lines = content.lines();
+    let mut header = lines.next().and_then(header_comment);
+    let mut meta_comments: HashMap<String, VecDeque<String>> = HashMap::new();
+    for (key, comment) in lines.filter_map(meta_line_comment) {
+        if let Some(comment) = comment {
+            meta_comments.entry(key).or_default().push_back(comment);
+        }
+    }
+    if header.is_none() && meta_comments.is_empty() {
+        return formatted;
+    }
+    formatted
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| {
+            let comment = if idx == 0 {
+                header.take()
+            } else {
+                meta_line_comment(line).and_then(|(key, _)| meta_comments.get_mut(&key)?.pop_front())
+            };
+            match comment {
+                Some(comment) => format!("{}  ; {}", line, comment),
+                None => line.to_owned(),
+            }
+        })
+        .join("\n")
+}
+
+fn transaction_header(trx: &Transaction) -> String {
+    let mut header = vec![
+        Some(trx.date.clone().export()),
+        trx.flag.clone().map(|it| it.export()),
+        trx.payee.clone().map(|it| it.export()),
+        trx.narration.clone().map(|it| it.export()),
+    ];
+    let mut tags = trx.tags.iter().map(|it| Some(format!("#{}", it))).collect_vec();
+    let mut links = trx.links.iter().map(|it| Some(format!("^{}", it))).collect_vec();
+    header.append(&mut tags);
+    header.append(&mut links);
+    header.into_iter().flatten().join(" ")
+}
+
+/// standalone comment lines inside the transaction, paired with the number of postings ahead of them
+fn transaction_comments(content: &str) -> Vec<(usize, String)> {
+    let mut postings = 0;
+    let mut comments = vec![];
+    for line in content.lines().skip(1) {
+        if is_posting_line(line) {
+            postings += 1;
+        } else if let Some(comment) = standalone_comment_line(line) {
+            comments.push((postings, comment));
+        }
+    }
+    comments
+}
+
+/// transaction with the account column padded and the numbers right aligned
+fn format_transaction(trx: Transaction, content: &str) -> String {
+    let header = transaction_header(&trx);
+    let postings = trx
+        .postings
+        .into_iter()
+        .map(|posting| {
+            let account = match &posting.flag {
+                Some(flag) => format!("{} {}", flag.clone().export(), posting.account.content),
+                None => posting.account.content.clone(),
+            };
+            (account, posting)
+        })
+        .collect_vec();
+    let account_width = postings.iter().map(|(account, _)| account.chars().count()).max().unwrap_or(0);
+    let number_width = postings
+        .iter()
+        .filter_map(|(_, posting)| posting.units.as_ref())
+        .map(|units| units.number.to_string().chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut comments = transaction_comments(content).into_iter().peekable();
+    let mut lines = vec![header];
+    for (idx, (account, posting)) in postings.into_iter().enumerate() {
+        while let Some((_, comment)) = comments.next_if(|(position, _)| *position == idx) {
+            lines.push(format!("  ; {}", comment));
+        }
+        let mut line = match posting.units {
+            Some(units) => format!("  {:<account_width$}  {:>number_width$} {}", account, units.number.to_string(), units.commodity),
+            None => format!("  {}", account),
+        };
+        if let Some(cost) = posting.cost {
+            line.push(' ');
+            line.push_str(&cost.export());
+        }
+        if let Some(price) = posting.price {
+            line.push(' ');
+            line.push_str(&price.export());
+        }
+        if let Some(comment) = posting.comment {
+            line.push_str("  ; ");
+            line.push_str(&comment);
+        }
+        lines.push(line);
+    }
+    lines.extend(comments.map(|(_, comment)| format!("  ; {}", comment)));
+    lines.extend(trx.meta.export().into_iter().map(|it| format!("  {}", it)));
+    lines.join("\n")
+}
+
 #[cfg(test)]
 mod test {
 
     use indoc::indoc;
 
+    use crate::data_type::text::exporter::{format_content, FormatOptions};
     use crate::data_type::text::ZhangDataType;
     use crate::data_type::DataType;
 
@@ -564,6 +763,123 @@ mod test {
         );
     }
 
+    #[test]
+    fn comment() {
+        assert_parse!(
+            "comment directive",
+            indoc! {r#"
+            ; hello world
+        "#}
+        );
+        assert_parse!(
+            "posting with inline comment",
+            indoc! {r#"
+            1970-01-01 * "Payee" "Narration"
+              Assets:123 -1 CNY ; from card
+              Expenses:TestCategory:One 1 CNY
+        "#}
+        );
+    }
+
+    #[test]
+    fn format_content_should_align_postings() {
+        let content = indoc! {r#"
+            ; daily expenses
+            1970-01-01 * "Payee" "Narration"
+                Assets:Card   -100.50 CNY ; paid by card
+                Expenses:Food:Dinner 100.50 CNY
+                b: "2"
+                a: "1"
+
+
+
+            1970-01-02 open Assets:Card
+        "#};
+        let expected = indoc! {r#"
+            ; daily expenses
+            1970-01-01 * "Payee" "Narration"
+              Assets:Card           -100.50 CNY  ; paid by card
+              Expenses:Food:Dinner   100.50 CNY
+              a: "1"
+              b: "2"
+
+            1970-01-02 open Assets:Card
+        "#};
+        assert_eq!(expected, format_content(content, &FormatOptions::default()).unwrap());
+        assert_eq!(expected, format_content(expected, &FormatOptions::default()).unwrap());
+    }
+
+    #[test]
+    fn format_content_should_keep_comments_between_postings() {
+        let content = indoc! {r#"
+            2023-12-06 "KFC" "VME50 Package"
+              ; before postings
+              Assets:BankCard -50 CNY
+              // between postings
+              Expenses:Food
+              a: "b"
+              * after postings
+        "#};
+        let expected = indoc! {r#"
+            2023-12-06 "KFC" "VME50 Package"
+              ; before postings
+              Assets:BankCard  -50 CNY
+              ; between postings
+              Expenses:Food
+              ; after postings
+              a: "b"
+        "#};
+        assert_eq!(expected, format_content(content, &FormatOptions::default()).unwrap());
+    }
+
+    #[test]
+    fn format_content_should_keep_trailing_comments() {
+        let content = indoc! {r#"
+            option "title" "Example" ; ledger title
+            1970-01-01 open Assets:Bank ; bank account note
+              b: "2" // second meta
+              a: "1"
+            2024-01-01 "KFC" "Lunch" #food ; header comment
+              Assets:Bank -10 CNY
+              Expenses:Food
+              note: "paid" ; meta comment
+        "#};
+        let expected = indoc! {r#"
+            option "title" "Example"  ; ledger title
+            1970-01-01 open Assets:Bank  ; bank account note
+              a: "1"
+              b: "2"  ; second meta
+            2024-01-01 "KFC" "Lunch" #food  ; header comment
+              Assets:Bank    -10 CNY
+              Expenses:Food
+              note: "paid"  ; meta comment
+        "#};
+        assert_eq!(expected, format_content(content, &FormatOptions::default()).unwrap());
+        assert_eq!(expected, format_content(expected, &FormatOptions::default()).unwrap());
+    }
+
+    #[test]
+    fn format_content_should_sort_by_date() {
+        let content = indoc! {r#"
+            1970-01-02 open Assets:B
+
+            ; open the first account
+            1970-01-01 open Assets:A
+            option "title" "Example"
+            ; end of file
+        "#};
+        let expected = indoc! {r#"
+            option "title" "Example"
+
+            ; open the first account
+            1970-01-01 open Assets:A
+            1970-01-02 open Assets:B
+            ; end of file
+        "#};
+        let options = FormatOptions { sort_by_date: true };
+        assert_eq!(expected, format_content(content, &options).unwrap());
+    }
+
     #[test]
     fn note() {
         assert_parse!(
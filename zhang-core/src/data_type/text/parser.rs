@@ -404,6 +404,33 @@ fn transaction_line(i: &str) -> IResult<&str, (Option<Posting>, Option<(String,
     Ok((i, result))
 }
 
+/// Whether the given line inside a transaction is a posting.
+pub(crate) fn is_posting_line(line: &str) -> bool {
+    transaction_posting(line.trim_start()).is_ok()
+}
+
+/// The body of a standalone comment line inside a transaction, which is discarded by
+/// `transaction_line`. `None` is returned for postings and metadata lines.
+pub(crate) fn standalone_comment_line(line: &str) -> Option<String> {
+    let line = line.trim_start();
+    if transaction_posting(line).is_ok() || key_value_line(line).is_ok() {
+        return None;
+    }
+    valuable_comment_body(line).ok().map(|(_, body)| body)
+}
+
+/// The key of a metadata line together with its trailing comment, which is discarded by
+/// `meta_line` and `transaction_line`. `None` is returned for postings and other lines.
+pub(crate) fn meta_line_comment(line: &str) -> Option<(String, Option<String>)> {
+    let line = line.trim_start();
+    if transaction_posting(line).is_ok() {
+        return None;
+    }
+    let (rest, (key, _)) = key_value_line(line).ok()?;
+    let comment = preceded(space0, valuable_comment_body)(rest).ok().map(|(_, body)| body);
+    Some((key, comment))
+}
+
 /// `transaction_lines = transaction_line+`
 fn transaction_lines(i: &str) -> IResult<&str, Vec<(Option<Posting>, Option<(String, ZhangString)>)>> {
     many1(transaction_line)(i)
@@ -603,6 +630,22 @@ fn event_body(date: Date, i: &str) -> IResult<&str, Directive> {
     ))
 }
 
+fn query_body(date: Date, i: &str) -> IResult<&str, Directive> {
+    let (i, _) = space1(i)?;
+    let (i, name) = string(i)?;
+    let (i, _) = space1(i)?;
+    let (i, query_string) = string(i)?;
+    Ok((
+        i,
+        Directive::Query(Query {
+            date,
+            name,
+            query_string,
+            meta: Meta::default(),
+        }),
+    ))
+}
+
 fn commodity_body(date: Date, i: &str) -> IResult<&str, Directive> {
     let (i, _) = space1(i)?;
     let (i, currency) = commodity_name(i)?;
@@ -715,6 +758,7 @@ fn dated_directive(original: &str) -> IResult<&str, Directive> {
         "event" => event_body(date, rest),
         "commodity" => commodity_body(date, rest),
         "custom" => custom_body(date, rest),
+        "query" => query_body(date, rest),
         "budget" => budget_body(date, rest),
         "budget-add" => budget_add_body(date, rest),
         "budget-transfer" => budget_transfer_body(date, rest),
@@ -771,12 +815,16 @@ fn metable_item(i: &str) -> IResult<&str, Directive> {
     Ok((i, directive))
 }
 
-/// `transaction = date flag? ("payee"? "narration"?) tags_or_links? comment? transaction_lines`
+type TransactionHead = (Date, Option<Flag>, Vec<ZhangString>, (Vec<String>, Vec<String>));
+
+/// `transaction_head = date flag? ("payee"? "narration"?) tags_or_links?`
+fn transaction_head(i: &str) -> IResult<&str, TransactionHead> {
+    tuple((parse_date, opt(transaction_flag), many_m_n(0, 2, preceded(space1, quote_string)), tags_or_links))(i)
+}
+
+/// `transaction = transaction_head comment? transaction_lines`
 fn transaction(original: &str) -> IResult<&str, Directive> {
-    let (i, date) = parse_date(original)?;
-    let (i, flag) = opt(transaction_flag)(i)?;
-    let (i, strings) = many_m_n(0, 2, preceded(space1, quote_string))(i)?;
-    let (i, (tags, links)) = tags_or_links(i)?;
+    let (i, (date, flag, strings, (tags, links))) = transaction_head(original)?;
     let (i, _) = space0(i)?;
     let (i, _) = opt(inline_comment)(i)?;
     let (i, lines) = transaction_lines(i)?;
@@ -818,6 +866,19 @@ fn transaction(original: &str) -> IResult<&str, Directive> {
     Ok((i, Directive::Transaction(transaction)))
 }
 
+/// The trailing comment on the first line of a directive, which is discarded by the
+/// parser. `None` is returned if the line is not a directive head or has no comment.
+pub(crate) fn header_comment(line: &str) -> Option<String> {
+    let (rest, _) = alt((
+        recognize(option_directive),
+        recognize(include_directive),
+        recognize(alt((plugin_directive, dated_directive))),
+        recognize(transaction_head),
+    ))(line)
+    .ok()?;
+    preceded(space0, valuable_comment_body)(rest).ok().map(|(_, body)| body)
+}
+
 /// Parse one top-level item. Returns `None` for items that produce no directive
 /// (currently only impossible-to-reach empty lines, kept for completeness).
 fn content_item(i: &str) -> IResult<&str, Option<Directive>> {
@@ -1093,6 +1154,32 @@ mod test {
             }
         }
     }
+    mod query {
+
+        use indoc::indoc;
+        use zhang_ast::Directive;
+
+        use crate::data_type::text::parser::parse;
+
+        #[test]
+        fn should_parse() {
+            let mut vec = parse(
+                indoc! {r#"
+                            1970-01-01 query "cash" "SELECT account, balance FROM accounts"
+                        "#},
+                None,
+            )
+            .unwrap();
+            assert_eq!(vec.len(), 1);
+            let directive = vec.pop().unwrap().data;
+            assert!(matches!(directive, Directive::Query(..)));
+            if let Directive::Query(inner) = directive {
+                assert_eq!(inner.date, date!(1970, 1, 1));
+                assert_eq!(inner.name, quote!("cash"));
+                assert_eq!(inner.query_string, quote!("SELECT account, balance FROM accounts"));
+            }
+        }
+    }
     mod plugin {
 
         use indoc::indoc;
@@ -16,13 +16,15 @@ use zhang_ast::{Account, AccountType, Currency, Date, Flag, Meta, PostingCost, R
 
 use crate::domains::schemas::{
     AccountBalanceDomain, AccountDailyBalanceDomain, AccountDomain, AccountJournalDomain, AccountStatus, CommodityDomain, ErrorDomain, MetaDomain, MetaType,
-    OptionDomain, PriceDomain, TransactionInfoDomain,
+    OptionDomain, PriceDomain,
 };
 use crate::inventory::{BookingMethod, TransactionInference};
 use crate::store::{
-    BudgetDomain, BudgetEvent, BudgetEventType, BudgetIntervalDetail, CommodityLotRecord, DocumentDomain, DocumentType, PostingDomain, Store, TransactionDomain,
+    BudgetDomain, BudgetEvent, BudgetEventType, BudgetIntervalDetail, CommodityLotRecord, DocumentDomain, DocumentType, PostingDomain, QueryDomain, Store,
+    TransactionDomain,
 };
 use crate::utils::id::FromSpan;
+use crate::utils::price_grip::PriceGrip;
 use crate::{ZhangError, ZhangResult};
 
 pub mod schemas;
@@ -38,6 +40,8 @@ pub struct AccountCommodityLot {
 pub struct Operations {
     pub timezone: Tz,
     pub store: Arc<RwLock<Store>>,
+    /// price grips built by [Operations::price_grip], which are dropped once a price is inserted through this operations
+    pub(crate) price_grips: HashMap<NaiveDateTime, PriceGrip>,
 }
 
 impl Operations {
@@ -178,7 +182,7 @@ impl Operations {
             previous_amount,
             after_amount,
         };
-        store.postings.push(posting.clone());
+        store.insert_posting(posting.clone());
         let txn_header = store
             .transactions
             .get_mut(trx_id)
@@ -206,6 +210,7 @@ impl Operations {
 
     /// insert single price
     pub(crate) fn insert_price(&mut self, datetime: DateTime<Tz>, commodity: &str, amount: &BigDecimal, target_commodity: &str) -> ZhangResult<()> {
+        self.price_grips.clear();
         let mut store = self.write();
         store.prices.push(PriceDomain {
             datetime: datetime.naive_local(),
@@ -222,13 +227,9 @@ impl Operations {
         let account = Account::from_str(account_name).map_err(|_| ZhangError::InvalidAccount)?;
 
         let posting: Option<&PostingDomain> = store
-            .postings
-            .iter()
-            .filter(|posting| posting.account.eq(&account))
-            .filter(|posting| posting.after_amount.commodity.eq(&currency))
-            .filter(|posting| posting.trx_datetime.le(&datetime))
-            .sorted_by_key(|posting| posting.trx_datetime)
-            .next_back();
+            .account_dated_postings(&account, None, Some(datetime))
+            .rev()
+            .find(|posting| posting.after_amount.commodity.eq(&currency));
 
         Ok(posting.map(|it| Amount {
             number: it.after_amount.number.clone(),
@@ -406,7 +407,7 @@ impl Operations {
 
         let mut ret: HashMap<Account, IndexMap<Currency, BTreeMap<NaiveDate, Amount>>> = HashMap::new();
 
-        for posting in store.postings.iter().cloned().sorted_by_key(|posting| posting.trx_datetime) {
+        for posting in store.dated_postings(None, None).cloned() {
             let posting: PostingDomain = posting;
             let date = posting.trx_datetime.naive_local().date();
 
@@ -442,11 +443,29 @@ impl Operations {
             .filter(|price| price.target_commodity.eq(to.as_ref()))
             .filter(|price| price.datetime.le(&date))
             .sorted_by(|a, b| a.datetime.cmp(&b.datetime))
-            .next()
+            .next_back()
             .cloned();
         Ok(x)
     }
 
+    /// the graph of the latest prices on or before the date, which is built once per date and reused afterwards
+    pub fn price_grip(&mut self, date: NaiveDateTime) -> ZhangResult<&PriceGrip> {
+        if !self.price_grips.contains_key(&date) {
+            let mut grip = PriceGrip::default();
+            for price in self
+                .read()
+                .prices
+                .iter()
+                .filter(|price| price.datetime.le(&date))
+                .sorted_by_key(|it| it.datetime)
+            {
+                grip.insert(price.commodity.clone(), price.target_commodity.clone(), price.amount.clone());
+            }
+            self.price_grips.insert(date, grip);
+        }
+        Ok(&self.price_grips[&date])
+    }
+
     pub fn metas(&self, type_: MetaType, type_identifier: impl AsRef<str>) -> ZhangResult<Vec<MetaDomain>> {
         let store = self.read();
         Ok(store
@@ -521,14 +540,9 @@ impl Operations {
         Ok(store.transactions.get(id).cloned())
     }
 
-    pub fn transaction_span(&mut self, id: &Uuid) -> ZhangResult<Option<TransactionInfoDomain>> {
+    pub fn transaction_span(&mut self, id: &Uuid) -> ZhangResult<Option<SpanInfo>> {
         let store = self.read();
-        Ok(store.transactions.get(id).map(|it| TransactionInfoDomain {
-            id: it.id.to_string(),
-            source_file: it.span.filename.clone().unwrap_or_default(),
-            span_start: it.span.start,
-            span_end: it.span.end,
-        }))
+        Ok(store.transactions.get(id).map(|it| it.span.clone()))
     }
 
     /// get target account's latest balance
@@ -540,13 +554,7 @@ impl Operations {
 
         let mut ret: IndexMap<Currency, BTreeMap<NaiveDate, Amount>> = IndexMap::new();
 
-        for posting in store
-            .postings
-            .iter()
-            .filter(|posting| posting.account.eq(&account))
-            .cloned()
-            .sorted_by_key(|posting| posting.trx_datetime)
-        {
+        for posting in store.account_dated_postings(&account, None, None).cloned() {
             let posting: PostingDomain = posting;
             let date = posting.trx_datetime.naive_local().date();
 
@@ -577,13 +585,7 @@ impl Operations {
 
         let mut ret: HashMap<Currency, HashMap<NaiveDate, Amount>> = HashMap::new();
 
-        for posting in store
-            .postings
-            .iter()
-            .filter(|posting| posting.account.eq(&account))
-            .cloned()
-            .sorted_by_key(|posting| posting.trx_datetime)
-        {
+        for posting in store.account_dated_postings(&account, None, None).cloned() {
             let posting: PostingDomain = posting;
             let date = posting.trx_datetime.naive_local().date();
 
@@ -599,7 +601,7 @@ impl Operations {
         let account = Account::from_str(account).map_err(|_| ZhangError::InvalidAccount)?;
 
         let mut ret = vec![];
-        for posting in store.postings.iter().filter(|posting| posting.account.eq(&account)).cloned().sorted_by(|a, b| {
+        for posting in store.account_dated_postings(&account, None, None).cloned().sorted_by(|a, b| {
             a.trx_datetime
                 .cmp(&b.trx_datetime)
                 .reverse()
@@ -624,10 +626,7 @@ impl Operations {
     pub fn dated_journals(&mut self, from: DateTime<Utc>, to: DateTime<Utc>) -> ZhangResult<Vec<PostingDomain>> {
         let store = self.read();
         Ok(store
-            .postings
-            .iter()
-            .filter(|posting| posting.trx_datetime.ge(&from))
-            .filter(|posting| posting.trx_datetime.le(&to))
+            .dated_postings(Some(from.with_timezone(&Tz::UTC)), Some(to.with_timezone(&Tz::UTC)))
             .cloned()
             .collect_vec())
     }
@@ -636,10 +635,7 @@ impl Operations {
 
         let mut ret = vec![];
         for posting in store
-            .postings
-            .iter()
-            .filter(|posting| posting.trx_datetime.ge(&from))
-            .filter(|posting| posting.trx_datetime.le(&to))
+            .dated_postings(Some(from.with_timezone(&Tz::UTC)), Some(to.with_timezone(&Tz::UTC)))
             .filter(|posting| posting.account.account_type == account_type)
             .cloned()
         {
@@ -663,10 +659,7 @@ impl Operations {
 
         let mut ret = vec![];
         for posting in store
-            .postings
-            .iter()
-            .filter(|posting| posting.trx_datetime.ge(&from))
-            .filter(|posting| posting.trx_datetime.le(&to))
+            .dated_postings(Some(from), Some(to))
             .filter(|posting| accounts.contains(&posting.account.content))
             .cloned()
         {
@@ -739,14 +732,7 @@ impl Operations {
 
         let mut ret: IndexMap<Currency, BTreeMap<NaiveDate, Amount>> = IndexMap::new();
 
-        for posting in store
-            .postings
-            .iter()
-            .filter(|posting| posting.account.eq(&account))
-            .filter(|positing| positing.trx_datetime.le(&date))
-            .cloned()
-            .sorted_by_key(|posting| posting.trx_datetime)
-        {
+        for posting in store.account_dated_postings(&account, None, Some(date.with_timezone(&Tz::UTC))).cloned() {
             let posting: PostingDomain = posting;
             let date = posting.trx_datetime.naive_local().date();
 
@@ -983,4 +969,35 @@ impl Operations {
         let metas = self.metas(MetaType::AccountMeta, account_name)?;
         Ok(metas.into_iter().filter(|meta| meta.key.eq("budget")).map(|meta| meta.value).collect_vec())
     }
+
+    /// all saved queries in definition order
+    pub fn queries(&self) -> ZhangResult<Vec<QueryDomain>> {
+        let store = self.read();
+        Ok(store.queries.values().cloned().collect_vec())
+    }
+
+    pub fn query(&self, name: impl AsRef<str>) -> ZhangResult<Option<QueryDomain>> {
+        let store = self.read();
+        Ok(store.queries.get(name.as_ref()).cloned())
+    }
+
+    /// check if saved query exists
+    pub fn contains_query(&self, name: impl AsRef<str>) -> bool {
+        let store = self.read();
+        store.queries.contains_key(name.as_ref())
+    }
+
+    pub fn insert_query(&mut self, name: impl Into<String>, date: NaiveDateTime, query_string: impl Into<String>) -> ZhangResult<()> {
+        let mut store = self.write();
+        let name = name.into();
+        store.queries.insert(
+            name.clone(),
+            QueryDomain {
+                name,
+                date,
+                query_string: query_string.into(),
+            },
+        );
+        Ok(())
+    }
 }
@@ -1,11 +1,10 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
 
 use bigdecimal::BigDecimal;
 use chrono::{NaiveDate, NaiveDateTime};
 #[cfg(feature = "openapi")]
 use gotcha_core::Schematic;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use strum::{AsRefStr, EnumString};
 use zhang_ast::amount::Amount;
 use zhang_ast::error::ErrorKind;
@@ -25,7 +24,7 @@ pub struct OptionDomain {
     pub value: String,
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccountDomain {
     pub date: NaiveDateTime,
     pub r#type: String,
@@ -34,7 +33,7 @@ pub struct AccountDomain {
     pub alias: Option<String>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Copy, Serialize, AsRefStr, EnumString)]
+#[derive(Clone, Debug, PartialEq, Eq, Copy, Serialize, Deserialize, AsRefStr, EnumString)]
 #[cfg_attr(feature = "openapi", derive(Schematic))]
 pub enum AccountStatus {
     Open,
@@ -56,7 +55,7 @@ pub struct AccountDailyBalanceDomain {
     pub balance: Amount,
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PriceDomain {
     pub datetime: NaiveDateTime,
     pub commodity: Currency,
@@ -64,7 +63,7 @@ pub struct PriceDomain {
     pub target_commodity: Currency,
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MetaDomain {
     pub meta_type: String,
     pub type_identifier: String,
@@ -72,7 +71,7 @@ pub struct MetaDomain {
     pub value: String,
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommodityDomain {
     pub name: String,
     pub precision: i32,
@@ -81,14 +80,6 @@ pub struct CommodityDomain {
     pub rounding: Rounding,
 }
 
-#[derive(Debug, Clone)]
-pub struct TransactionInfoDomain {
-    pub id: String,
-    pub source_file: PathBuf,
-    pub span_start: usize,
-    pub span_end: usize,
-}
-
 #[derive(Debug, Clone, Serialize)]
 #[cfg_attr(feature = "openapi", derive(Schematic))]
 pub struct AccountJournalDomain {
@@ -102,7 +93,7 @@ pub struct AccountJournalDomain {
     pub account_after: Amount,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ErrorDomain {
     pub id: String,
     pub span: Option<SpanInfo>,
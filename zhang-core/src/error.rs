@@ -37,6 +37,10 @@ pub enum ZhangError {
     #[error("file not found")]
     FileNotFound,
 
+    /// the current content of the file is carried, so that clients can merge their changes against it
+    #[error("content of file {path:?} has been changed since it was loaded")]
+    ConflictedContent { path: PathBuf, content: String },
+
     #[error("custom error: {0}")]
     CustomError(String),
 }
@@ -0,0 +1,299 @@
+use bigdecimal::BigDecimal;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use zhang_ast::amount::Amount;
+use zhang_ast::{Account, Date, Directive, Transaction, ZhangString};
+
+use crate::importer::{mapping_account, parse_number, transfer, Importer};
+use crate::{ZhangError, ZhangResult};
+
+/// a column of the csv file, referred by its header name or its zero-based index
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CsvColumn {
+    Index(usize),
+    Name(String),
+}
+
+/// describe how the columns of a bank statement are mapped into transactions.
+///
+/// either `amount` or at least one of `debit` and `credit` must be given,
+/// and either `currency` or `currency_column` must be given.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CsvMapping {
+    /// the account the statement belongs to
+    pub account: String,
+    /// the account on the other side of every imported transaction
+    pub counter_account: String,
+
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    /// lines to skip before the header, e.g. the account summary some banks put on top of the records
+    #[serde(default)]
+    pub skip_lines: usize,
+    #[serde(default = "default_has_header")]
+    pub has_header: bool,
+
+    pub date: CsvColumn,
+    /// chrono format of the date column, date time formats are supported as well
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+
+    /// signed amount, positive amount flows into `account`
+    pub amount: Option<CsvColumn>,
+    /// money flowing out of `account`
+    pub debit: Option<CsvColumn>,
+    /// money flowing into `account`
+    pub credit: Option<CsvColumn>,
+    /// flip the sign of amounts, for statements like credit cards where spending is positive
+    #[serde(default)]
+    pub negate: bool,
+
+    pub currency: Option<String>,
+    pub currency_column: Option<CsvColumn>,
+
+    pub payee: Option<CsvColumn>,
+    pub narration: Option<CsvColumn>,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_has_header() -> bool {
+    true
+}
+
+fn default_date_format() -> String {
+    "%Y-%m-%d".to_owned()
+}
+
+pub struct CsvImporter {
+    pub mapping: CsvMapping,
+}
+
+/// the mapping with all its columns resolved into indexes
+struct ResolvedMapping {
+    account: Account,
+    counter_account: Account,
+    date: usize,
+    amount: Option<usize>,
+    debit: Option<usize>,
+    credit: Option<usize>,
+    currency: Option<usize>,
+    payee: Option<usize>,
+    narration: Option<usize>,
+}
+
+impl CsvImporter {
+    pub fn new(mapping: CsvMapping) -> Self {
+        Self { mapping }
+    }
+
+    fn resolve(&self, headers: Option<&::csv::StringRecord>) -> ZhangResult<ResolvedMapping> {
+        let mapping = &self.mapping;
+        let column = |column: &CsvColumn| -> ZhangResult<usize> {
+            match (column, headers) {
+                (CsvColumn::Index(index), _) => Ok(*index),
+                (CsvColumn::Name(name), Some(headers)) => headers
+                    .iter()
+                    .position(|header| header.trim() == name)
+                    .ok_or_else(|| ZhangError::CustomError(format!("column {} is not found in csv header", name))),
+                (CsvColumn::Name(name), None) => Err(ZhangError::CustomError(format!(
+                    "column {} must be referred by index since the csv file has no header",
+                    name
+                ))),
+            }
+        };
+        let optional_column = |it: Option<&CsvColumn>| it.map(column).transpose();
+
+        if mapping.amount.is_none() && mapping.debit.is_none() && mapping.credit.is_none() {
+            return Err(ZhangError::CustomError("one of amount, debit and credit column must be given".to_owned()));
+        }
+        if mapping.currency.is_none() && mapping.currency_column.is_none() {
+            return Err(ZhangError::CustomError("either currency or currency column must be given".to_owned()));
+        }
+        Ok(ResolvedMapping {
+            account: mapping_account(&mapping.account)?,
+            counter_account: mapping_account(&mapping.counter_account)?,
+            date: column(&mapping.date)?,
+            amount: optional_column(mapping.amount.as_ref())?,
+            debit: optional_column(mapping.debit.as_ref())?,
+            credit: optional_column(mapping.credit.as_ref())?,
+            currency: optional_column(mapping.currency_column.as_ref())?,
+            payee: optional_column(mapping.payee.as_ref())?,
+            narration: optional_column(mapping.narration.as_ref())?,
+        })
+    }
+
+    fn parse_date(&self, raw: &str) -> Option<Date> {
+        let format = &self.mapping.date_format;
+        NaiveDateTime::parse_from_str(raw, format)
+            .map(Date::Datetime)
+            .or_else(|_| NaiveDate::parse_from_str(raw, format).map(Date::Date))
+            .ok()
+    }
+
+    fn transaction(&self, mapping: &ResolvedMapping, record: &::csv::StringRecord) -> ZhangResult<Transaction> {
+        let line = record.position().map(|it| it.line()).unwrap_or_default() + self.mapping.skip_lines as u64;
+        let error = |msg: String| ZhangError::CustomError(format!("line {}: {}", line, msg));
+        let field = |index: usize| record.get(index).map(str::trim).unwrap_or_default();
+        let optional_field = |index: Option<usize>| index.map(field).filter(|it| !it.is_empty());
+        let number = |index: Option<usize>| -> ZhangResult<Option<BigDecimal>> {
+            optional_field(index)
+                .map(|raw| parse_number(raw).ok_or_else(|| error(format!("invalid amount: {}", raw))))
+                .transpose()
+        };
+
+        let raw_date = field(mapping.date);
+        let date = self
+            .parse_date(raw_date)
+            .ok_or_else(|| error(format!("cannot parse date {} with format {}", raw_date, self.mapping.date_format)))?;
+
+        let number = match mapping.amount {
+            Some(amount) => number(Some(amount))?,
+            None => match (number(mapping.debit)?, number(mapping.credit)?) {
+                (None, None) => None,
+                (debit, credit) => Some(credit.unwrap_or_default() - debit.unwrap_or_default()),
+            },
+        };
+        let number = number.ok_or_else(|| error("amount is missing".to_owned()))?;
+        let number = if self.mapping.negate { -number } else { number };
+
+        let currency = match optional_field(mapping.currency) {
+            Some(currency) => currency.to_owned(),
+            None => self.mapping.currency.clone().ok_or_else(|| error("currency is missing".to_owned()))?,
+        };
+
+        Ok(Transaction {
+            payee: optional_field(mapping.payee).map(|it| ZhangString::QuoteString(it.to_owned())),
+            narration: optional_field(mapping.narration).map(|it| ZhangString::QuoteString(it.to_owned())),
+            ..transfer(date, &mapping.account, &mapping.counter_account, Amount::new(number, currency))
+        })
+    }
+}
+
+impl Importer for CsvImporter {
+    fn import(&self, content: &str) -> ZhangResult<Vec<Directive>> {
+        let content = content.trim_start_matches('\u{feff}');
+        let content: String = content.split_inclusive('\n').skip(self.mapping.skip_lines).collect();
+
+        let mut delimiter = [0; 4];
+        let delimiter = self.mapping.delimiter.encode_utf8(&mut delimiter).as_bytes();
+        if delimiter.len() != 1 {
+            return Err(ZhangError::CustomError(format!(
+                "delimiter must be an ascii character: {}",
+                self.mapping.delimiter
+            )));
+        }
+        let mut reader = ::csv::ReaderBuilder::new()
+            .delimiter(delimiter[0])
+            .has_headers(self.mapping.has_header)
+            .flexible(true)
+            .from_reader(content.as_bytes());
+
+        let headers = if self.mapping.has_header {
+            Some(reader.headers().map_err(csv_error)?.clone())
+        } else {
+            None
+        };
+        let mapping = self.resolve(headers.as_ref())?;
+
+        let mut ret = vec![];
+        for record in reader.records() {
+            let record = record.map_err(csv_error)?;
+            if record.iter().all(|it| it.trim().is_empty()) {
+                continue;
+            }
+            ret.push(Directive::Transaction(self.transaction(&mapping, &record)?));
+        }
+        Ok(ret)
+    }
+}
+
+fn csv_error(e: ::csv::Error) -> ZhangError {
+    ZhangError::CustomError(format!("invalid csv content: {}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+    use indoc::indoc;
+    use zhang_ast::{Date, Directive, ZhangString};
+
+    use crate::importer::csv::{CsvImporter, CsvMapping};
+    use crate::importer::Importer;
+
+    fn mapping(json: &str) -> CsvMapping {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn should_import_signed_amount_by_header_name() {
+        let importer = CsvImporter::new(mapping(
+            r#"{"account": "Assets:Bank", "counter_account": "Expenses:Unknown", "date": "Date", "date_format": "%d/%m/%Y",
+                "amount": "Amount", "currency": "CNY", "payee": "Payee", "narration": "Memo"}"#,
+        ));
+        let content = indoc! {r#"
+            Date,Payee,Memo,Amount
+            01/02/2024,Coffee Shop,"Latte, large","-1,234.50"
+            03/02/2024,Employer,,"5,000.00"
+        "#};
+        let directives = importer.import(content).unwrap();
+        assert_eq!(2, directives.len());
+
+        let Directive::Transaction(trx) = &directives[0] else { unreachable!() };
+        assert_eq!(Date::Date(chrono::NaiveDate::from_ymd_opt(2024, 2, 1).unwrap()), trx.date);
+        assert_eq!(Some(ZhangString::QuoteString("Coffee Shop".to_owned())), trx.payee);
+        assert_eq!(Some(ZhangString::QuoteString("Latte, large".to_owned())), trx.narration);
+        assert_eq!("Assets:Bank", trx.postings[0].account.name());
+        let units = trx.postings[0].units.as_ref().unwrap();
+        assert_eq!(BigDecimal::from_str("-1234.50").unwrap(), units.number);
+        assert_eq!("CNY", units.commodity);
+        assert_eq!("Expenses:Unknown", trx.postings[1].account.name());
+        assert!(trx.postings[1].units.is_none());
+
+        let Directive::Transaction(trx) = &directives[1] else { unreachable!() };
+        assert_eq!(None, trx.narration);
+        assert_eq!(BigDecimal::from_str("5000.00").unwrap(), trx.postings[0].units.as_ref().unwrap().number);
+    }
+
+    #[test]
+    fn should_import_debit_and_credit_columns_by_index() {
+        let importer = CsvImporter::new(mapping(
+            r#"{"account": "Liabilities:CreditCard", "counter_account": "Expenses:Unknown", "delimiter": ";", "skip_lines": 1, "has_header": false,
+                "date": 0, "date_format": "%Y-%m-%d %H:%M", "debit": 2, "credit": 3, "currency_column": 4, "narration": 1}"#,
+        ));
+        let content = indoc! {r#"
+            Statement of card 1234
+            2024-02-01 12:30;Dinner;88.00;;USD
+
+            2024-02-02 09:00;Refund;;10.00;USD
+        "#};
+        let directives = importer.import(content).unwrap();
+        assert_eq!(2, directives.len());
+
+        let Directive::Transaction(trx) = &directives[0] else { unreachable!() };
+        assert!(matches!(trx.date, Date::Datetime(_)));
+        let units = trx.postings[0].units.as_ref().unwrap();
+        assert_eq!(BigDecimal::from_str("-88.00").unwrap(), units.number);
+        assert_eq!("USD", units.commodity);
+
+        let Directive::Transaction(trx) = &directives[1] else { unreachable!() };
+        assert_eq!(BigDecimal::from_str("10.00").unwrap(), trx.postings[0].units.as_ref().unwrap().number);
+    }
+
+    #[test]
+    fn should_report_line_of_invalid_record() {
+        let importer = CsvImporter::new(mapping(
+            r#"{"account": "Assets:Bank", "counter_account": "Expenses:Unknown", "date": "Date", "amount": "Amount", "currency": "CNY"}"#,
+        ));
+        let error = importer.import("Date,Amount\n2024-02-01,1\n2024-13-01,2\n").unwrap_err();
+        assert!(error.to_string().contains("line 3"), "{}", error);
+
+        let error = importer.import("Day,Amount\n2024-02-01,1\n").unwrap_err();
+        assert!(error.to_string().contains("column Date is not found"), "{}", error);
+    }
+}
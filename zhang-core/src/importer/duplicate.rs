@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use chrono::{NaiveDate, NaiveDateTime};
+#[cfg(feature = "openapi")]
+use gotcha_core::Schematic;
+use serde::Serialize;
+use strum::EnumString;
+use uuid::Uuid;
+use zhang_ast::amount::Amount;
+use zhang_ast::{Account, BalanceCheck, Directive, Flag, Transaction, ZhangString};
+
+use crate::domains::schemas::MetaType;
+use crate::domains::Operations;
+use crate::store::TransactionDomain;
+use crate::ZhangResult;
+
+/// the meta key pointing to the existing transaction which the marked transaction duplicates
+pub const DUPLICATE_OF_META: &str = "duplicate_of";
+
+/// how two transactions are considered the same one
+#[derive(Debug, Clone)]
+pub struct DuplicateRule {
+    /// existing transactions within the days before and after the given one are compared
+    pub date_window: u32,
+    /// the meta key carrying the id given by external system, like `fitid` of OFX statements.
+    /// transactions with the same id are always duplicated, and those with different ids never are.
+    pub external_id_key: Option<String>,
+    /// the minimum score of a candidate to be treated as duplicated
+    pub threshold: u8,
+}
+
+impl Default for DuplicateRule {
+    fn default() -> Self {
+        Self {
+            date_window: 3,
+            external_id_key: None,
+            threshold: 80,
+        }
+    }
+}
+
+/// what to do with imported transactions which have duplicated candidates
+#[derive(Debug, Clone, Copy, PartialEq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum DuplicateAction {
+    Keep,
+    /// flag the transaction with `!` and point it to the existing one with `duplicate_of` meta
+    Mark,
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "openapi", derive(Schematic))]
+pub struct DuplicateCandidate {
+    pub transaction_id: Uuid,
+    pub datetime: NaiveDateTime,
+    pub payee: Option<String>,
+    pub narration: Option<String>,
+    /// how likely the candidate is the same transaction, from 0 to 100
+    pub score: u8,
+}
+
+impl Operations {
+    /// find the existing transactions which may be the same one as the given transaction, the most likely comes first.
+    ///
+    /// a candidate must have a posting of the same account and amount for every posting with explicit amount of the given one,
+    /// and is scored by how close their dates are and whether payees are equal, a candidate with different payee scores lower.
+    pub fn duplicate_candidates(&mut self, transaction: &Transaction, rule: &DuplicateRule) -> ZhangResult<Vec<DuplicateCandidate>> {
+        let given = Comparison::of_transaction(transaction, rule);
+
+        let store = self.read();
+        // the external ids of existing transactions, keyed by transaction id
+        let external_ids: HashMap<&str, &str> = match (rule.external_id_key.as_ref(), given.external_id) {
+            (Some(key), Some(_)) => store
+                .metas
+                .iter()
+                .filter(|it| it.meta_type.eq(MetaType::TransactionMeta.as_ref()) && it.key.eq(key))
+                .map(|it| (it.type_identifier.as_str(), it.value.as_str()))
+                .collect(),
+            _ => HashMap::new(),
+        };
+
+        let mut ret = vec![];
+        for candidate in store.transactions.values() {
+            if matches!(candidate.flag, Flag::BalanceCheck | Flag::BalancePad) {
+                continue;
+            }
+            let external_id = external_ids.get(candidate.id.to_string().as_str()).copied();
+            if let Some(score) = given.score(&Comparison::of_domain(candidate, external_id), rule) {
+                ret.push(DuplicateCandidate::new(candidate, score));
+            }
+        }
+        ret.sort_by(|a, b| b.score.cmp(&a.score).then(b.datetime.cmp(&a.datetime)));
+        Ok(ret)
+    }
+
+    /// apply the action on imported transactions whose best candidate reaches the threshold, or which repeat the external id of a transaction accepted earlier in the same batch.
+    /// balance checks asserted already are dropped unless the action is [DuplicateAction::Keep], and other directives are kept as they are.
+    pub fn handle_duplicates(&mut self, directives: Vec<Directive>, rule: &DuplicateRule, action: DuplicateAction) -> ZhangResult<Vec<Directive>> {
+        if action == DuplicateAction::Keep {
+            return Ok(directives);
+        }
+        let mut ret = Vec::with_capacity(directives.len());
+        for directive in directives {
+            match directive {
+                Directive::Transaction(mut transaction) => {
+                    let existing = self
+                        .duplicate_candidates(&transaction, rule)?
+                        .into_iter()
+                        .next()
+                        .filter(|it| it.score >= rule.threshold);
+                    let duplicated = existing.is_some() || is_duplicated_in_batch(&transaction, &ret, rule);
+                    match (duplicated, action) {
+                        (false, _) => ret.push(Directive::Transaction(transaction)),
+                        (true, DuplicateAction::Skip) => {}
+                        (true, _) => {
+                            transaction.flag = Some(Flag::Warning);
+                            // the one duplicated in the same batch does not exist yet, so there is nothing to point to
+                            if let Some(candidate) = existing {
+                                transaction
+                                    .meta
+                                    .insert(DUPLICATE_OF_META.to_owned(), ZhangString::QuoteString(candidate.transaction_id.to_string()));
+                            }
+                            ret.push(Directive::Transaction(transaction));
+                        }
+                    }
+                }
+                Directive::BalanceCheck(balance) => {
+                    let asserted = self.is_balance_asserted(&balance)
+                        || ret
+                            .iter()
+                            .any(|it| matches!(it, Directive::BalanceCheck(accepted) if is_same_balance_check(accepted, &balance)));
+                    if !asserted {
+                        ret.push(Directive::BalanceCheck(balance));
+                    }
+                }
+                others => ret.push(others),
+            }
+        }
+        Ok(ret)
+    }
+
+    /// whether the store has the balance check of the same account, date and amount
+    fn is_balance_asserted(&mut self, balance: &BalanceCheck) -> bool {
+        let date = balance.date.naive_date();
+        let store = self.read();
+        // the posting of balance check carries the distance, and the balance after it is the asserted amount
+        store.transactions.values().any(|it| {
+            matches!(it.flag, Flag::BalanceCheck)
+                && it.datetime.date_naive() == date
+                && it
+                    .postings
+                    .iter()
+                    .any(|posting| posting.account == balance.account && posting.after_amount == balance.amount)
+        })
+    }
+}
+
+fn is_same_balance_check(a: &BalanceCheck, b: &BalanceCheck) -> bool {
+    a.account == b.account && a.date.naive_date() == b.date.naive_date() && a.amount == b.amount
+}
+
+/// whether the transaction carries the same external id as any transaction accepted earlier in the same batch, which is not in the store yet.
+/// rows without external id are never duplicated within a batch, repeated rows of one statement are different transactions.
+fn is_duplicated_in_batch(transaction: &Transaction, accepted: &[Directive], rule: &DuplicateRule) -> bool {
+    let Some(external_id) = Comparison::of_transaction(transaction, rule).external_id else {
+        return false;
+    };
+    accepted.iter().any(|directive| match directive {
+        Directive::Transaction(candidate) => Comparison::of_transaction(candidate, rule).external_id == Some(external_id),
+        _ => false,
+    })
+}
+
+/// what transactions are compared by to tell whether they are the same one
+struct Comparison<'a> {
+    date: NaiveDate,
+    /// the payee in lowercase
+    payee: Option<String>,
+    external_id: Option<&'a str>,
+    /// the postings with explicit amount
+    postings: Vec<(&'a Account, &'a Amount)>,
+}
+
+impl<'a> Comparison<'a> {
+    fn of_transaction(transaction: &'a Transaction, rule: &DuplicateRule) -> Self {
+        Self {
+            date: transaction.date.naive_date(),
+            payee: transaction.payee.as_ref().map(|it| it.as_str().to_lowercase()),
+            external_id: rule
+                .external_id_key
+                .as_ref()
+                .and_then(|key| transaction.meta.get_one(key))
+                .map(|value| value.as_str()),
+            postings: transaction
+                .postings
+                .iter()
+                .filter_map(|it| it.units.as_ref().map(|units| (&it.account, units)))
+                .collect(),
+        }
+    }
+
+    fn of_domain(transaction: &'a TransactionDomain, external_id: Option<&'a str>) -> Self {
+        Self {
+            date: transaction.datetime.date_naive(),
+            payee: transaction.payee.as_ref().map(|it| it.to_lowercase()),
+            external_id,
+            postings: transaction
+                .postings
+                .iter()
+                .filter_map(|it| it.unit.as_ref().map(|unit| (&it.account, unit)))
+                .collect(),
+        }
+    }
+
+    /// the score of the candidate being the same transaction, `None` if it cannot be.
+    /// transactions with the same external id are always the same, and those with different ones never are.
+    fn score(&self, candidate: &Comparison, rule: &DuplicateRule) -> Option<u8> {
+        if let (Some(id), Some(candidate_id)) = (self.external_id, candidate.external_id) {
+            return (id == candidate_id).then_some(100);
+        }
+
+        let days = (candidate.date - self.date).num_days().unsigned_abs();
+        if days > rule.date_window as u64 || self.postings.is_empty() {
+            return None;
+        }
+        if !self.postings.iter().all(|posting| candidate.postings.contains(posting)) {
+            return None;
+        }
+
+        let closeness = if rule.date_window == 0 {
+            30
+        } else {
+            30 * (rule.date_window as u64 - days) / rule.date_window as u64
+        };
+        // different payees make it less likely to be the same transaction, even if they are on the same day
+        let payee = match (&self.payee, &candidate.payee) {
+            (Some(payee), Some(candidate_payee)) if payee == candidate_payee => 10,
+            (Some(_), Some(_)) => -20,
+            _ => 0,
+        };
+        Some((60 + closeness as i64 + payee) as u8)
+    }
+}
+
+impl DuplicateCandidate {
+    fn new(transaction: &TransactionDomain, score: u8) -> Self {
+        Self {
+            transaction_id: transaction.id,
+            datetime: transaction.datetime.naive_local(),
+            payee: transaction.payee.clone(),
+            narration: transaction.narration.clone(),
+            score,
+        }
+    }
+}
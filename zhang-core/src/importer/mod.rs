@@ -0,0 +1,77 @@
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use indexmap::IndexSet;
+use zhang_ast::amount::Amount;
+use zhang_ast::{Account, Date, Directive, Flag, Meta, Posting, Transaction};
+
+use crate::{ZhangError, ZhangResult};
+
+pub mod csv;
+pub mod duplicate;
+pub mod ofx;
+pub mod rule;
+
+/// importer turns a statement exported by banks into directives which can be appended into ledger
+pub trait Importer {
+    fn import(&self, content: &str) -> ZhangResult<Vec<Directive>>;
+
+    /// the meta key of imported transactions carrying the id given by the bank, which is used for detecting duplicates
+    fn external_id_key(&self) -> Option<&str> {
+        None
+    }
+}
+
+pub(crate) fn mapping_account(name: &str) -> ZhangResult<Account> {
+    Account::from_str(name).map_err(|_| ZhangError::CustomError(format!("invalid account in import mapping: {}", name)))
+}
+
+/// the transaction moving `units` into the statement account, the amount of counter posting is left to be inferred
+pub(crate) fn transfer(date: Date, account: &Account, counter_account: &Account, units: Amount) -> Transaction {
+    let posting = |account: &Account, units: Option<Amount>| Posting {
+        flag: None,
+        account: account.clone(),
+        units,
+        cost: None,
+        price: None,
+        comment: None,
+    };
+    Transaction {
+        date,
+        flag: Some(Flag::Okay),
+        payee: None,
+        narration: None,
+        tags: IndexSet::new(),
+        links: IndexSet::new(),
+        postings: vec![posting(account, Some(units)), posting(counter_account, None)],
+        meta: Meta::default(),
+    }
+}
+
+/// parse numbers written by banks, like `1,234.50`, `+12.00` and `(12.00)` for negative amount
+pub(crate) fn parse_number(raw: &str) -> Option<BigDecimal> {
+    let (negative, raw) = match raw.strip_prefix('(').and_then(|it| it.strip_suffix(')')) {
+        Some(inner) => (true, inner),
+        None => (false, raw),
+    };
+    let cleaned: String = raw.chars().filter(|it| !matches!(it, ',' | ' ' | '+')).collect();
+    let number = BigDecimal::from_str(&cleaned).ok()?;
+    Some(if negative { -number } else { number })
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use crate::importer::parse_number;
+
+    #[test]
+    fn should_parse_bank_numbers() {
+        assert_eq!(Some(BigDecimal::from_str("1234.50").unwrap()), parse_number("1,234.50"));
+        assert_eq!(Some(BigDecimal::from_str("12").unwrap()), parse_number("+12"));
+        assert_eq!(Some(BigDecimal::from_str("-12.00").unwrap()), parse_number("(12.00)"));
+        assert_eq!(None, parse_number("abc"));
+    }
+}
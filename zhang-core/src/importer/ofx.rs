@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+
+use chrono::{Days, NaiveDate};
+use serde::{Deserialize, Serialize};
+use zhang_ast::amount::Amount;
+use zhang_ast::{Account, BalanceCheck, Date, Directive, Meta, Transaction, ZhangString};
+
+use crate::importer::{mapping_account, parse_number, transfer, Importer};
+use crate::{ZhangError, ZhangResult};
+
+/// the meta key carrying the `FITID` of imported transactions, which is unique per account in the bank's side
+pub const FITID_META: &str = "fitid";
+
+/// describe which accounts the records of an OFX/QFX statement belong to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OfxMapping {
+    /// the account the statement belongs to
+    pub account: String,
+    /// the account on the other side of every imported transaction
+    pub counter_account: String,
+    /// the currency used when the statement does not declare `CURDEF`
+    pub currency: Option<String>,
+}
+
+pub struct OfxImporter {
+    pub mapping: OfxMapping,
+}
+
+/// element of OFX content, OFX 1.x is SGML whose leaf elements are not closed, so closing tags are only meaningful for aggregates
+#[derive(Debug, PartialEq)]
+enum Element {
+    Start { tag: String, value: String },
+    End(String),
+}
+
+/// the aggregate being read and its leaf values
+struct Aggregate {
+    tag: String,
+    fields: HashMap<String, String>,
+}
+
+impl OfxImporter {
+    pub fn new(mapping: OfxMapping) -> Self {
+        Self { mapping }
+    }
+
+    fn transaction(&self, fields: &HashMap<String, String>, currency: &str, account: &Account, counter_account: &Account) -> ZhangResult<Transaction> {
+        let field = |name: &str| fields.get(name).map(String::as_str).filter(|it| !it.is_empty());
+        let fitid = field("FITID");
+        let error = |msg: String| ZhangError::CustomError(format!("invalid transaction[FITID={}]: {}", fitid.unwrap_or_default(), msg));
+
+        let date = field("DTPOSTED")
+            .and_then(parse_date)
+            .ok_or_else(|| error("DTPOSTED is missing or invalid".to_owned()))?;
+        let number = field("TRNAMT")
+            .and_then(parse_number)
+            .ok_or_else(|| error("TRNAMT is missing or invalid".to_owned()))?;
+
+        let mut meta = Meta::default();
+        if let Some(fitid) = fitid {
+            meta.insert(FITID_META.to_owned(), ZhangString::QuoteString(fitid.to_owned()));
+        }
+        Ok(Transaction {
+            payee: field("NAME").or(field("PAYEE")).map(|it| ZhangString::QuoteString(it.to_owned())),
+            narration: field("MEMO").map(|it| ZhangString::QuoteString(it.to_owned())),
+            meta,
+            ..transfer(Date::Date(date), account, counter_account, Amount::new(number, currency))
+        })
+    }
+
+    /// `DTASOF` is the moment the balance is reported, while balance directive checks the balance at the start of the day,
+    /// so the check is placed on the next day.
+    fn balance(&self, fields: &HashMap<String, String>, currency: &str, account: &Account) -> ZhangResult<BalanceCheck> {
+        let field = |name: &str| fields.get(name).map(String::as_str).filter(|it| !it.is_empty());
+        let error = |msg: &str| ZhangError::CustomError(format!("invalid ledger balance: {}", msg));
+
+        let date = field("DTASOF")
+            .and_then(parse_date)
+            .and_then(|it| it.checked_add_days(Days::new(1)))
+            .ok_or_else(|| error("DTASOF is missing or invalid"))?;
+        let number = field("BALAMT").and_then(parse_number).ok_or_else(|| error("BALAMT is missing or invalid"))?;
+        Ok(BalanceCheck {
+            date: Date::Date(date),
+            account: account.clone(),
+            amount: Amount::new(number, currency),
+            tolerance: None,
+            meta: Meta::default(),
+        })
+    }
+}
+
+impl Importer for OfxImporter {
+    fn external_id_key(&self) -> Option<&str> {
+        Some(FITID_META)
+    }
+
+    fn import(&self, content: &str) -> ZhangResult<Vec<Directive>> {
+        let account = mapping_account(&self.mapping.account)?;
+        let counter_account = mapping_account(&self.mapping.counter_account)?;
+
+        let mut currency = None;
+        let mut transactions = vec![];
+        let mut balance = None;
+        let mut aggregate: Option<Aggregate> = None;
+        let elements = elements(content);
+        if !elements.iter().any(|it| matches!(it, Element::Start { tag, .. } if tag == "OFX")) {
+            return Err(ZhangError::CustomError("content is not an OFX statement".to_owned()));
+        }
+        for element in elements {
+            match element {
+                Element::Start { tag, .. } if tag == "STMTTRN" || tag == "LEDGERBAL" => {
+                    aggregate = Some(Aggregate { tag, fields: HashMap::new() });
+                }
+                Element::Start { tag, value } => match aggregate.as_mut() {
+                    Some(aggregate) => {
+                        aggregate.fields.entry(tag).or_insert(value);
+                    }
+                    None if tag == "CURDEF" => currency = Some(value),
+                    None => {}
+                },
+                Element::End(tag) if aggregate.as_ref().is_some_and(|it| it.tag == tag) => {
+                    let Aggregate { tag, fields } = aggregate.take().expect("aggregate must exist");
+                    if tag == "STMTTRN" {
+                        transactions.push(fields);
+                    } else {
+                        balance = Some(fields);
+                    }
+                }
+                Element::End(_) => {}
+            }
+        }
+
+        let currency = currency
+            .filter(|it| !it.is_empty())
+            .or_else(|| self.mapping.currency.clone())
+            .ok_or_else(|| ZhangError::CustomError("statement has no CURDEF and currency is not given".to_owned()))?;
+
+        let mut ret = vec![];
+        for fields in &transactions {
+            ret.push(Directive::Transaction(self.transaction(fields, &currency, &account, &counter_account)?));
+        }
+        if let Some(fields) = balance {
+            ret.push(Directive::BalanceCheck(self.balance(&fields, &currency, &account)?));
+        }
+        Ok(ret)
+    }
+}
+
+/// OFX datetime is `YYYYMMDD[HHMMSS[.XXX]][[gmt offset:tz name]]`, only the date part is used
+fn parse_date(raw: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(raw.get(0..8)?, "%Y%m%d").ok()
+}
+
+/// split the content into elements, the headers before `<OFX>` and the processing instructions of OFX 2.x are skipped.
+fn elements(content: &str) -> Vec<Element> {
+    let mut ret = vec![];
+    let mut rest = content;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = rest[..end].trim();
+        rest = &rest[end + 1..];
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        match tag.strip_prefix('/') {
+            Some(tag) => ret.push(Element::End(tag.trim().to_uppercase())),
+            None => {
+                let value_end = rest.find('<').unwrap_or(rest.len());
+                ret.push(Element::Start {
+                    tag: tag.to_uppercase(),
+                    value: unescape(rest[..value_end].trim()),
+                });
+            }
+        }
+    }
+    ret
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+    use chrono::NaiveDate;
+    use indoc::indoc;
+    use zhang_ast::{Date, Directive, ZhangString};
+
+    use crate::importer::ofx::{OfxImporter, OfxMapping, FITID_META};
+    use crate::importer::Importer;
+
+    fn importer(currency: Option<&str>) -> OfxImporter {
+        OfxImporter::new(OfxMapping {
+            account: "Assets:Bank".to_owned(),
+            counter_account: "Expenses:Unknown".to_owned(),
+            currency: currency.map(|it| it.to_owned()),
+        })
+    }
+
+    #[test]
+    fn should_import_sgml_statement() {
+        let content = indoc! {r#"
+            OFXHEADER:100
+            DATA:OFXSGML
+            VERSION:102
+
+            <OFX>
+            <BANKMSGSRSV1><STMTTRNRS><STMTRS>
+            <CURDEF>USD
+            <BANKACCTFROM><BANKID>123<ACCTID>456<ACCTTYPE>CHECKING</BANKACCTFROM>
+            <BANKTRANLIST>
+            <DTSTART>20240101<DTEND>20240131
+            <STMTTRN>
+            <TRNTYPE>DEBIT
+            <DTPOSTED>20240105120000.000[-5:EST]
+            <TRNAMT>-12.50
+            <FITID>2024010501
+            <NAME>Coffee &amp; Tea
+            <MEMO>Latte
+            </STMTTRN>
+            <STMTTRN>
+            <TRNTYPE>CREDIT
+            <DTPOSTED>20240110
+            <TRNAMT>1000.00
+            <FITID>2024011001
+            <NAME>Employer
+            </STMTTRN>
+            </BANKTRANLIST>
+            <LEDGERBAL><BALAMT>2987.50<DTASOF>20240131235959</LEDGERBAL>
+            </STMTRS></STMTTRNRS></BANKMSGSRSV1>
+            </OFX>
+        "#};
+        let directives = importer(None).import(content).unwrap();
+        assert_eq!(3, directives.len());
+
+        let Directive::Transaction(trx) = &directives[0] else { unreachable!() };
+        assert_eq!(Date::Date(NaiveDate::from_ymd_opt(2024, 1, 5).unwrap()), trx.date);
+        assert_eq!(Some(ZhangString::QuoteString("Coffee & Tea".to_owned())), trx.payee);
+        assert_eq!(Some(ZhangString::QuoteString("Latte".to_owned())), trx.narration);
+        assert_eq!(Some(&ZhangString::QuoteString("2024010501".to_owned())), trx.meta.get_one(FITID_META));
+        let units = trx.postings[0].units.as_ref().unwrap();
+        assert_eq!(BigDecimal::from_str("-12.50").unwrap(), units.number);
+        assert_eq!("USD", units.commodity);
+        assert_eq!("Expenses:Unknown", trx.postings[1].account.name());
+
+        let Directive::Transaction(trx) = &directives[1] else { unreachable!() };
+        assert_eq!(None, trx.narration);
+
+        let Directive::BalanceCheck(balance) = &directives[2] else { unreachable!() };
+        assert_eq!(Date::Date(NaiveDate::from_ymd_opt(2024, 2, 1).unwrap()), balance.date);
+        assert_eq!("Assets:Bank", balance.account.name());
+        assert_eq!(BigDecimal::from_str("2987.50").unwrap(), balance.amount.number);
+    }
+
+    #[test]
+    fn should_import_xml_statement_with_configured_currency() {
+        let content = indoc! {r#"
+            <?xml version="1.0" encoding="UTF-8"?>
+            <?OFX OFXHEADER="200" VERSION="220"?>
+            <OFX>
+              <CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS>
+                <BANKTRANLIST>
+                  <STMTTRN>
+                    <TRNTYPE>DEBIT</TRNTYPE>
+                    <DTPOSTED>20240203</DTPOSTED>
+                    <TRNAMT>-30</TRNAMT>
+                    <FITID>A1</FITID>
+                    <PAYEE><NAME>Grocery</NAME></PAYEE>
+                  </STMTTRN>
+                </BANKTRANLIST>
+              </CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1>
+            </OFX>
+        "#};
+        let directives = importer(Some("EUR")).import(content).unwrap();
+        assert_eq!(1, directives.len());
+        let Directive::Transaction(trx) = &directives[0] else { unreachable!() };
+        assert_eq!(Some(ZhangString::QuoteString("Grocery".to_owned())), trx.payee);
+        assert_eq!("EUR", trx.postings[0].units.as_ref().unwrap().commodity);
+
+        assert!(importer(None).import(content).is_err());
+        assert!(importer(Some("EUR")).import("Date,Amount\n2024-02-01,1\n").is_err());
+    }
+}
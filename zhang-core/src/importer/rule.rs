@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use indexmap::IndexMap;
+use itertools::Itertools;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use zhang_ast::{Account, Directive, Flag, Transaction, ZhangString};
+
+use crate::domains::Operations;
+use crate::importer::mapping_account;
+use crate::{ZhangError, ZhangResult};
+
+/// a rule assigning the counter account, tags, links and metas to the transactions it matches.
+///
+/// all the given conditions must be satisfied for a transaction to be matched.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CategorizeRule {
+    /// case-insensitive regex matched against payee
+    pub payee: Option<String>,
+    /// case-insensitive regex matched against narration
+    pub narration: Option<String>,
+    /// the account the transaction comes from, like the account of the statement
+    pub account: Option<String>,
+    /// the inclusive lower bound of the amount, which is the amount of `account` posting if given, otherwise any posting
+    pub min_amount: Option<BigDecimal>,
+    /// the inclusive upper bound of the amount
+    pub max_amount: Option<BigDecimal>,
+
+    /// the account assigned to the posting without explicit amount
+    pub counter_account: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub links: Vec<String>,
+    #[serde(default)]
+    pub meta: IndexMap<String, String>,
+}
+
+struct CompiledRule {
+    payee: Option<Regex>,
+    narration: Option<Regex>,
+    account: Option<Account>,
+    counter_account: Option<Account>,
+    rule: CategorizeRule,
+}
+
+/// categorizer applies the first matched rule on transactions
+#[derive(Default)]
+pub struct Categorizer {
+    rules: Vec<CompiledRule>,
+}
+
+impl Categorizer {
+    pub fn new(rules: Vec<CategorizeRule>) -> ZhangResult<Self> {
+        let regex = |pattern: Option<&String>| -> ZhangResult<Option<Regex>> {
+            pattern
+                .map(|it| {
+                    RegexBuilder::new(it)
+                        .case_insensitive(true)
+                        .build()
+                        .map_err(|e| ZhangError::CustomError(format!("invalid regex in categorize rule: {}", e)))
+                })
+                .transpose()
+        };
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                Ok(CompiledRule {
+                    payee: regex(rule.payee.as_ref())?,
+                    narration: regex(rule.narration.as_ref())?,
+                    account: rule.account.as_deref().map(mapping_account).transpose()?,
+                    counter_account: rule.counter_account.as_deref().map(mapping_account).transpose()?,
+                    rule,
+                })
+            })
+            .collect::<ZhangResult<Vec<_>>>()?;
+        Ok(Self { rules })
+    }
+
+    /// apply the first matched rule on the transaction, return whether any rule is matched
+    pub fn categorize(&self, transaction: &mut Transaction) -> bool {
+        self.apply(transaction, true)
+    }
+
+    /// apply the first matched rule on the transaction except its counter account, for the accounts chosen by user explicitly
+    pub fn categorize_keeping_accounts(&self, transaction: &mut Transaction) -> bool {
+        self.apply(transaction, false)
+    }
+
+    fn apply(&self, transaction: &mut Transaction, assign_counter_account: bool) -> bool {
+        let Some(rule) = self.rules.iter().find(|it| it.matches(transaction)) else {
+            return false;
+        };
+        if let Some(counter_account) = rule.counter_account.as_ref().filter(|_| assign_counter_account) {
+            if let Some(posting) = transaction.postings.iter_mut().find(|it| it.units.is_none()) {
+                posting.account = counter_account.clone();
+            }
+        }
+        transaction.tags.extend(rule.rule.tags.iter().cloned());
+        transaction.links.extend(rule.rule.links.iter().cloned());
+        for (key, value) in rule.rule.meta.iter() {
+            transaction.meta.insert(key.to_owned(), ZhangString::QuoteString(value.to_owned()));
+        }
+        true
+    }
+
+    /// categorize the transactions among directives, other directives are kept as they are
+    pub fn categorize_all(&self, directives: Vec<Directive>) -> Vec<Directive> {
+        directives
+            .into_iter()
+            .map(|directive| match directive {
+                Directive::Transaction(mut transaction) => {
+                    self.categorize(&mut transaction);
+                    Directive::Transaction(transaction)
+                }
+                others => others,
+            })
+            .collect()
+    }
+}
+
+impl CompiledRule {
+    fn matches(&self, transaction: &Transaction) -> bool {
+        let text_matched = |regex: Option<&Regex>, text: Option<&ZhangString>| match regex {
+            Some(regex) => text.is_some_and(|it| regex.is_match(it.as_str())),
+            None => true,
+        };
+        if !text_matched(self.payee.as_ref(), transaction.payee.as_ref()) || !text_matched(self.narration.as_ref(), transaction.narration.as_ref()) {
+            return false;
+        }
+
+        let mut postings = transaction
+            .postings
+            .iter()
+            .filter(|it| self.account.as_ref().is_none_or(|account| it.account.eq(account)))
+            .peekable();
+        if postings.peek().is_none() {
+            return false;
+        }
+        if self.rule.min_amount.is_none() && self.rule.max_amount.is_none() {
+            return true;
+        }
+        postings.filter_map(|it| it.units.as_ref()).any(|units| {
+            self.rule.min_amount.as_ref().is_none_or(|min| units.number.ge(min)) && self.rule.max_amount.as_ref().is_none_or(|max| units.number.le(max))
+        })
+    }
+}
+
+impl Operations {
+    /// the accounts used with each payee in existing transactions, the most frequent comes first
+    pub fn payee_accounts(&mut self) -> ZhangResult<HashMap<String, Vec<String>>> {
+        let store = self.read();
+        let mut counter: HashMap<&str, IndexMap<&Account, usize>> = HashMap::new();
+        for transaction in store.transactions.values().sorted_by_key(|it| it.sequence) {
+            if matches!(transaction.flag, Flag::BalanceCheck | Flag::BalancePad) {
+                continue;
+            }
+            let Some(payee) = transaction.payee.as_deref().filter(|it| !it.is_empty()) else {
+                continue;
+            };
+            let accounts = counter.entry(payee).or_default();
+            for posting in transaction.postings.iter() {
+                *accounts.entry(&posting.account).or_default() += 1;
+            }
+        }
+        Ok(counter
+            .into_iter()
+            .map(|(payee, mut accounts)| {
+                accounts.sort_by(|_, a, _, b| b.cmp(a));
+                (payee.to_owned(), accounts.into_keys().map(|it| it.name().to_owned()).collect())
+            })
+            .collect())
+    }
+
+    /// learn rules from existing transactions of the account, which assign the most frequent counter account of each payee
+    pub fn learned_rules(&mut self, account: &str) -> ZhangResult<Vec<CategorizeRule>> {
+        let account = Account::from_str(account).map_err(|_| ZhangError::InvalidAccount)?;
+        let store = self.read();
+        let mut counter: IndexMap<String, IndexMap<&Account, usize>> = IndexMap::new();
+        for transaction in store.transactions.values().sorted_by_key(|it| it.sequence) {
+            if matches!(transaction.flag, Flag::BalanceCheck | Flag::BalancePad) || !transaction.postings.iter().any(|it| it.account.eq(&account)) {
+                continue;
+            }
+            let Some(payee) = transaction.payee.as_deref().filter(|it| !it.is_empty()) else {
+                continue;
+            };
+            let accounts = counter.entry(payee.to_lowercase()).or_default();
+            for posting in transaction.postings.iter().filter(|it| it.account.ne(&account)) {
+                *accounts.entry(&posting.account).or_default() += 1;
+            }
+        }
+        Ok(counter
+            .into_iter()
+            .filter_map(|(payee, accounts)| {
+                // the first account wins when several accounts are equally frequent
+                let (counter_account, _) = accounts.into_iter().rev().max_by_key(|(_, count)| *count)?;
+                Some(CategorizeRule {
+                    payee: Some(format!("^{}$", regex::escape(&payee))),
+                    account: Some(account.name().to_owned()),
+                    counter_account: Some(counter_account.name().to_owned()),
+                    ..Default::default()
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+    use chrono::NaiveDate;
+    use indexmap::IndexMap;
+    use zhang_ast::amount::Amount;
+    use zhang_ast::{Account, Date, Transaction, ZhangString};
+
+    use crate::importer::rule::{CategorizeRule, Categorizer};
+    use crate::importer::transfer;
+
+    fn transaction(payee: &str, amount: i32) -> Transaction {
+        Transaction {
+            payee: Some(ZhangString::QuoteString(payee.to_owned())),
+            ..transfer(
+                Date::Date(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()),
+                &Account::from_str("Assets:Bank").unwrap(),
+                &Account::from_str("Expenses:Unknown").unwrap(),
+                Amount::new(BigDecimal::from(amount), "USD"),
+            )
+        }
+    }
+
+    #[test]
+    fn should_apply_first_matched_rule() {
+        let categorizer = Categorizer::new(vec![
+            CategorizeRule {
+                payee: Some("starbucks".to_owned()),
+                max_amount: Some(BigDecimal::from(-50)),
+                counter_account: Some("Expenses:Party".to_owned()),
+                ..Default::default()
+            },
+            CategorizeRule {
+                payee: Some("starbucks".to_owned()),
+                account: Some("Assets:Bank".to_owned()),
+                counter_account: Some("Expenses:Coffee".to_owned()),
+                tags: vec!["coffee".to_owned()],
+                meta: IndexMap::from([("category".to_owned(), "drink".to_owned())]),
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+        let mut coffee = transaction("STARBUCKS #123", -5);
+        assert!(categorizer.categorize(&mut coffee));
+        assert_eq!("Expenses:Coffee", coffee.postings[1].account.name());
+        assert!(coffee.tags.contains("coffee"));
+        assert_eq!(Some("drink"), coffee.meta.get_one("category").map(|it| it.as_str()));
+
+        let mut party = transaction("Starbucks", -80);
+        assert!(categorizer.categorize(&mut party));
+        assert_eq!("Expenses:Party", party.postings[1].account.name());
+
+        let mut other = transaction("Bakery", -5);
+        assert!(!categorizer.categorize(&mut other));
+        assert_eq!("Expenses:Unknown", other.postings[1].account.name());
+    }
+
+    #[test]
+    fn should_keep_accounts_if_required() {
+        let categorizer = Categorizer::new(vec![CategorizeRule {
+            payee: Some("starbucks".to_owned()),
+            counter_account: Some("Expenses:Coffee".to_owned()),
+            tags: vec!["coffee".to_owned()],
+            ..Default::default()
+        }])
+        .unwrap();
+
+        let mut coffee = transaction("Starbucks", -5);
+        assert!(categorizer.categorize_keeping_accounts(&mut coffee));
+        assert_eq!("Expenses:Unknown", coffee.postings[1].account.name());
+        assert!(coffee.tags.contains("coffee"));
+    }
+
+    #[test]
+    fn should_reject_invalid_regex() {
+        let rule = CategorizeRule {
+            payee: Some("(".to_owned()),
+            ..Default::default()
+        };
+        assert!(Categorizer::new(vec![rule]).is_err());
+    }
+}
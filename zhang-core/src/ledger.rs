@@ -1,6 +1,6 @@
 use std::borrow::Cow;
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::atomic::AtomicI32;
 use std::sync::{Arc, RwLock};
@@ -10,7 +10,7 @@ use itertools::Itertools;
 use log::{error, info};
 use zhang_ast::{Directive, DirectiveType, Options, Plugin, SpanInfo, Spanned};
 
-use crate::data_source::DataSource;
+use crate::data_source::{DataSource, LoadResult};
 use crate::domains::Operations;
 use crate::error::IoErrorIntoZhangError;
 use crate::options::{BuiltinOption, InMemoryOptions};
@@ -24,6 +24,8 @@ pub struct Ledger {
     pub data_source: Arc<dyn DataSource>,
 
     pub visited_files: Vec<PathBuf>,
+    /// the hash of the content each visited file is parsed from, which is used to tell whether the cache is fresh
+    pub(crate) file_hashes: Vec<String>,
 
     pub options: InMemoryOptions,
 
@@ -42,6 +44,7 @@ pub struct LedgerProcessContext {
     pub directives: Vec<Spanned<Directive>>,
     pub entry: (PathBuf, String),
     pub visited_files: Vec<PathBuf>,
+    pub file_hashes: Vec<String>,
     pub data_source: Arc<dyn DataSource>,
 }
 
@@ -64,21 +67,7 @@ impl SplitDirectives {
 
         let dated_directives = Ledger::sort_directives_datetime(dated_directive);
 
-        // find all options which are not defined by users
-        let options_key: HashSet<Cow<str>> = meta_directives
-            .iter()
-            .filter_map(|it| match &it.data {
-                Directive::Option(option) => Some(Cow::Borrowed(option.key.as_str())),
-                _ => None,
-            })
-            .collect();
-
-        // merge built-in options and user-defined options
-        let merged_metas = BuiltinOption::default_options(options_key)
-            .into_iter()
-            .chain(meta_directives)
-            .rev()
-            .collect_vec();
+        let merged_metas = Self::merge_metas(meta_directives);
         let grouped_directives = merged_metas.iter().rev().chain(dated_directives.iter()).cloned().collect_vec();
 
         let mut options_directives = vec![];
@@ -102,6 +91,24 @@ impl SplitDirectives {
             other_directives,
         }
     }
+
+    /// merge built-in options and user-defined meta directives
+    fn merge_metas(meta_directives: Vec<Spanned<Directive>>) -> Vec<Spanned<Directive>> {
+        // find all options which are not defined by users
+        let options_key: HashSet<Cow<str>> = meta_directives
+            .iter()
+            .filter_map(|it| match &it.data {
+                Directive::Option(option) => Some(Cow::Borrowed(option.key.as_str())),
+                _ => None,
+            })
+            .collect();
+
+        BuiltinOption::default_options(options_key)
+            .into_iter()
+            .chain(meta_directives)
+            .rev()
+            .collect_vec()
+    }
 }
 
 impl Ledger {
@@ -118,6 +125,7 @@ impl Ledger {
             directives: load_result.directives,
             entry: (entry, endpoint),
             visited_files: load_result.visited_files,
+            file_hashes: load_result.file_hashes,
             data_source,
         })
     }
@@ -128,6 +136,7 @@ impl Ledger {
             directives: load_result.directives,
             entry: (entry, endpoint),
             visited_files: load_result.visited_files,
+            file_hashes: load_result.file_hashes,
             data_source,
         })
         .await
@@ -138,6 +147,7 @@ impl Ledger {
             options: InMemoryOptions::default(),
             entry: context.entry,
             visited_files: context.visited_files,
+            file_hashes: context.file_hashes,
             directives: vec![],
             metas: vec![],
             data_source: context.data_source,
@@ -180,6 +190,7 @@ impl Ledger {
             options: InMemoryOptions::default(),
             entry: context.entry,
             visited_files: context.visited_files,
+            file_hashes: context.file_hashes,
             directives: vec![],
             metas: vec![],
             data_source: context.data_source,
@@ -213,13 +224,19 @@ impl Ledger {
         Ok(ret_ledger)
     }
 
+    /// reload the ledger from data source, only the appended directives are processed if possible
     pub fn reload(&mut self) -> ZhangResult<()> {
-        let (entry, endpoint) = &mut self.entry;
+        let (entry, endpoint) = &self.entry;
         let transform_result = self.data_source.load(entry.to_string_lossy().to_string(), endpoint.clone())?;
+        if self.process_appended_directives(&transform_result) {
+            return Ok(());
+        }
+        let (entry, endpoint) = &self.entry;
         let reload_ledger = Ledger::process(LedgerProcessContext {
             directives: transform_result.directives,
             entry: (entry.clone(), endpoint.clone()),
             visited_files: transform_result.visited_files,
+            file_hashes: transform_result.file_hashes,
             data_source: self.data_source.clone(),
         })?;
         *self = reload_ledger;
@@ -227,12 +244,17 @@ impl Ledger {
     }
 
     pub async fn async_reload(&mut self) -> ZhangResult<()> {
-        let (entry, endpoint) = &mut self.entry;
+        let (entry, endpoint) = &self.entry;
         let transform_result = self.data_source.async_load(entry.to_string_lossy().to_string(), endpoint.clone()).await?;
+        if self.process_appended_directives(&transform_result) {
+            return Ok(());
+        }
+        let (entry, endpoint) = &self.entry;
         let reload_ledger = Ledger::async_process(LedgerProcessContext {
             directives: transform_result.directives,
             entry: (entry.clone(), endpoint.clone()),
             visited_files: transform_result.visited_files,
+            file_hashes: transform_result.file_hashes,
             data_source: self.data_source.clone(),
         })
         .await?;
@@ -245,13 +267,94 @@ impl Ledger {
         Operations {
             store: self.store.clone(),
             timezone,
+            price_grips: HashMap::new(),
         }
     }
 }
 
 impl Ledger {
-    fn sort_directives_datetime(mut directives: Vec<Spanned<Directive>>) -> Vec<Spanned<Directive>> {
-        directives.sort_by(|a, b| match (a.datetime(), b.datetime()) {
+    /// process the directives which are appended since last processing without replaying the whole ledger.
+    ///
+    /// it only happens if every changed file is only appended since the content it is processed from, which is told by [LoadResult::file_appends],
+    /// and all the appended directives are ordered after the processed ones, which is the common case of adding transactions and balances.
+    /// return `false` if nothing is processed, and the whole ledger need to be processed again.
+    fn process_appended_directives(&mut self, load_result: &LoadResult) -> bool {
+        // plugins may change the processed directives, so the appended directives cannot be processed alone
+        if self.options.features.plugins {
+            return false;
+        }
+        // the data source must track the content and appending of every visited file
+        let visited_count = load_result.visited_files.len();
+        if visited_count == 0
+            || load_result.file_hashes.len() != visited_count
+            || load_result.file_appends.len() != visited_count
+            || self.file_hashes.len() != self.visited_files.len()
+        {
+            return false;
+        }
+        let processed_hashes: HashMap<&PathBuf, &String> = self.visited_files.iter().zip(self.file_hashes.iter()).collect();
+        // appending never excludes the processed files
+        if load_result.visited_files.iter().filter(|it| processed_hashes.contains_key(it)).count() != processed_hashes.len() {
+            return false;
+        }
+
+        let mut appended_metas = vec![];
+        let mut appended_directives = vec![];
+        for ((path, hash), append) in load_result
+            .visited_files
+            .iter()
+            .zip(load_result.file_hashes.iter())
+            .zip(load_result.file_appends.iter())
+        {
+            let processed_hash = processed_hashes.get(path).copied();
+            if processed_hash == Some(hash) {
+                continue;
+            }
+            let Some(append) = append.as_ref().filter(|it| it.base_hash.as_ref() == processed_hash) else {
+                return false;
+            };
+            for directive in &load_result.directives[append.directives.clone()] {
+                match directive.datetime() {
+                    Some(_) => appended_directives.push(directive),
+                    None => appended_metas.push(directive),
+                }
+            }
+        }
+        // including new files does not affect the processing
+        if !appended_metas.iter().all(|it| matches!(it.data, Directive::Include(_) | Directive::Comment(_))) {
+            return false;
+        }
+
+        appended_directives.sort_by(|a, b| Ledger::compare_directives_datetime(a, b));
+        // only the processed directives of the same or later datetime need to be checked, since the processed ones are sorted
+        let is_ordered_after_processed = |directive: &&Spanned<Directive>| {
+            self.directives
+                .iter()
+                .rev()
+                .take_while(|processed| processed.datetime() >= directive.datetime())
+                .all(|processed| Ledger::compare_directives_datetime(directive, processed) != Ordering::Less)
+        };
+        if !appended_directives.iter().all(is_ordered_after_processed) {
+            return false;
+        }
+
+        info!("processing {} appended directives", appended_directives.len());
+        let appended_directives = appended_directives.into_iter().cloned().collect_vec();
+        // the store may be partially updated on failure, so the whole ledger is processed again
+        if let Err(e) = self.handle_other_directives(appended_directives.clone()) {
+            error!("fail to process appended directives: {}", e);
+            return false;
+        }
+        self.directives.extend(appended_directives);
+        // metas are kept in reversed order
+        self.metas.splice(0..0, appended_metas.into_iter().rev().cloned());
+        self.visited_files = load_result.visited_files.clone();
+        self.file_hashes = load_result.file_hashes.clone();
+        true
+    }
+
+    fn compare_directives_datetime(a: &Spanned<Directive>, b: &Spanned<Directive>) -> Ordering {
+        match (a.datetime(), b.datetime()) {
             (Some(a_datetime), Some(b_datetime)) => match a_datetime.cmp(&b_datetime) {
                 Ordering::Equal => match (a.directive_type(), b.directive_type()) {
                     (DirectiveType::BalancePad | DirectiveType::BalanceCheck, DirectiveType::BalancePad | DirectiveType::BalanceCheck) => Ordering::Equal,
@@ -264,7 +367,11 @@ impl Ledger {
                 other => other,
             },
             _ => Ordering::Greater,
-        });
+        }
+    }
+
+    fn sort_directives_datetime(mut directives: Vec<Spanned<Directive>>) -> Vec<Spanned<Directive>> {
+        directives.sort_by(Ledger::compare_directives_datetime);
         directives
     }
 
@@ -311,6 +418,7 @@ impl Ledger {
                 Directive::Price(price) => price.handler(self, &directive.span)?,
                 Directive::Event(_) => {}
                 Directive::Custom(_) => {}
+                Directive::Query(query) => query.handler(self, &directive.span)?,
                 Directive::Plugin(_) => unreachable!("plugin directive should not be passed into the processor here"),
                 Directive::Include(_) => {}
                 Directive::Comment(_) => {}
@@ -634,11 +742,16 @@ mod test {
     }
 
     mod price {
+        use std::collections::HashMap;
+
         use bigdecimal::BigDecimal;
-        use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+        use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+        use chrono_tz::Tz;
         use indoc::indoc;
+        use zhang_ast::amount::Amount;
 
         use crate::ledger::test::load_from_temp_str;
+        use crate::utils::calculable::Calculable;
 
         #[test]
         fn should_get_price() {
@@ -660,6 +773,65 @@ mod test {
                 .unwrap();
             assert_eq!(BigDecimal::from(7), option.amount)
         }
+
+        #[test]
+        fn should_get_latest_price() {
+            let ledger = load_from_temp_str(indoc! {r#"
+                    1970-02-01 price USD 7 CNY
+                    1970-03-01 price USD 8 CNY
+                "#});
+
+            let mut operations = ledger.operations();
+            let date = NaiveDateTime::new(NaiveDate::from_ymd_opt(1970, 4, 1).unwrap(), NaiveTime::from_hms_opt(0, 0, 0).unwrap());
+            let price = operations.get_price(date, "USD", "CNY").unwrap().unwrap();
+            assert_eq!(BigDecimal::from(8), price.amount)
+        }
+
+        #[test]
+        fn should_calculate_via_inverse_and_intermediate_prices() {
+            let ledger = load_from_temp_str(indoc! {r#"
+                    option "operating_currency" "CNY"
+                    1970-02-01 price CNY 0.125 USD
+                    1970-02-01 price VOO 400 USD
+                "#});
+
+            let mut operations = ledger.operations();
+            let date = Tz::UTC.with_ymd_and_hms(1970, 3, 1, 0, 0, 0).unwrap();
+            let amounts = vec![
+                Amount::new(BigDecimal::from(10), "CNY"),
+                Amount::new(BigDecimal::from(1), "USD"),
+                Amount::new(BigDecimal::from(2), "VOO"),
+                Amount::new(BigDecimal::from(3), "EUR"),
+            ];
+            let calculated = amounts.calculate(date, &mut operations).unwrap();
+            assert_eq!(Amount::new(BigDecimal::from(6418), "CNY"), calculated.calculated);
+            assert_eq!(HashMap::from([("EUR".to_owned(), BigDecimal::from(3))]), calculated.unconverted);
+            assert_eq!(4, calculated.detail.len());
+        }
+
+        #[test]
+        fn should_rebuild_price_grip_after_price_inserted() {
+            let ledger = load_from_temp_str(indoc! {r#"
+                    option "operating_currency" "CNY"
+                    1970-02-01 price USD 7 CNY
+                "#});
+
+            let mut operations = ledger.operations();
+            let date = Tz::UTC.with_ymd_and_hms(1970, 3, 1, 0, 0, 0).unwrap();
+            let amounts = vec![Amount::new(BigDecimal::from(1), "USD")];
+            assert_eq!(
+                Amount::new(BigDecimal::from(7), "CNY"),
+                amounts.calculate(date, &mut operations).unwrap().calculated
+            );
+
+            operations
+                .insert_price(Tz::UTC.with_ymd_and_hms(1970, 2, 2, 0, 0, 0).unwrap(), "USD", &BigDecimal::from(8), "CNY")
+                .unwrap();
+            assert_eq!(
+                Amount::new(BigDecimal::from(8), "CNY"),
+                amounts.calculate(date, &mut operations).unwrap().calculated
+            );
+        }
     }
 
     mod account {
@@ -679,4 +851,144 @@ mod test {
             Ok(())
         }
     }
+
+    mod reload {
+        use std::sync::Arc;
+
+        use indoc::indoc;
+        use tempfile::tempdir;
+
+        use crate::data_source::LocalFileSystemDataSource;
+        use crate::data_type::text::ZhangDataType;
+        use crate::ledger::Ledger;
+
+        fn append(path: &std::path::Path, content: &str) {
+            let original = std::fs::read_to_string(path).unwrap();
+            std::fs::write(path, format!("{}\n{}", original, content)).unwrap();
+        }
+
+        #[test]
+        fn should_only_process_appended_directives_given_later_datetime() {
+            let temp_dir = tempdir().unwrap().into_path();
+            let main = temp_dir.join("main.zhang");
+            std::fs::write(
+                &main,
+                indoc! {r#"
+                    1970-01-01 open Assets:Bank
+                    1970-01-01 open Expenses:Food
+                    2024-01-01 "KFC" ""
+                      Assets:Bank -5 CNY
+                      Expenses:Food
+                "#},
+            )
+            .unwrap();
+            let source = LocalFileSystemDataSource::new(ZhangDataType {});
+            let mut ledger = Ledger::load_with_data_source(temp_dir.clone(), "main.zhang".to_string(), Arc::new(source)).unwrap();
+            let store = ledger.store.clone();
+
+            append(
+                &main,
+                indoc! {r#"
+                    2024-01-02 "KFC" ""
+                      Assets:Bank -3 CNY
+                      Expenses:Food
+                "#},
+            );
+            ledger.reload().unwrap();
+            assert!(Arc::ptr_eq(&store, &ledger.store));
+            assert_eq!(2, ledger.store.read().unwrap().transactions.len());
+            assert_eq!(4, ledger.directives.len());
+
+            // directives earlier than the processed ones require a full replay
+            append(
+                &main,
+                indoc! {r#"
+                    2023-12-31 "KFC" ""
+                      Assets:Bank -1 CNY
+                      Expenses:Food
+                "#},
+            );
+            ledger.reload().unwrap();
+            assert!(!Arc::ptr_eq(&store, &ledger.store));
+            assert_eq!(3, ledger.store.read().unwrap().transactions.len());
+            assert!(ledger.operations().errors().unwrap().is_empty());
+        }
+
+        #[test]
+        fn should_only_process_appended_directives_given_same_day() {
+            let temp_dir = tempdir().unwrap().into_path();
+            let main = temp_dir.join("main.zhang");
+            std::fs::write(
+                &main,
+                indoc! {r#"
+                    1970-01-01 open Assets:Bank
+                    1970-01-01 open Expenses:Food
+                    2024-01-01 "KFC" ""
+                      Assets:Bank -5 CNY
+                      Expenses:Food
+                "#},
+            )
+            .unwrap();
+            let source = LocalFileSystemDataSource::new(ZhangDataType {});
+            let mut ledger = Ledger::load_with_data_source(temp_dir.clone(), "main.zhang".to_string(), Arc::new(source)).unwrap();
+            let store = ledger.store.clone();
+
+            // transactions of the same day are ordered after the processed ones
+            append(
+                &main,
+                indoc! {r#"
+                    2024-01-01 "KFC" ""
+                      Assets:Bank -3 CNY
+                      Expenses:Food
+                "#},
+            );
+            ledger.reload().unwrap();
+            assert!(Arc::ptr_eq(&store, &ledger.store));
+            assert_eq!(2, ledger.store.read().unwrap().transactions.len());
+
+            // balances are ordered before the transactions of the same day, which requires a full replay
+            append(
+                &main,
+                indoc! {r#"
+                    2024-01-01 balance Assets:Bank 0 CNY
+                "#},
+            );
+            ledger.reload().unwrap();
+            assert!(!Arc::ptr_eq(&store, &ledger.store));
+            assert_eq!(5, ledger.directives.len());
+            assert!(ledger.operations().errors().unwrap().is_empty());
+        }
+
+        #[test]
+        fn should_only_process_appended_directives_of_newly_included_file() {
+            let temp_dir = tempdir().unwrap().into_path();
+            let main = temp_dir.join("main.zhang");
+            std::fs::write(
+                &main,
+                indoc! {r#"
+                    1970-01-01 open Assets:Bank
+                    1970-01-01 open Expenses:Food
+                "#},
+            )
+            .unwrap();
+            let source = LocalFileSystemDataSource::new(ZhangDataType {});
+            let mut ledger = Ledger::load_with_data_source(temp_dir.clone(), "main.zhang".to_string(), Arc::new(source)).unwrap();
+            let store = ledger.store.clone();
+
+            std::fs::write(
+                temp_dir.join("2024.zhang"),
+                indoc! {r#"
+                    2024-01-01 "KFC" ""
+                      Assets:Bank -3 CNY
+                      Expenses:Food
+                "#},
+            )
+            .unwrap();
+            append(&main, "include \"2024.zhang\"\n");
+            ledger.reload().unwrap();
+            assert!(Arc::ptr_eq(&store, &ledger.store));
+            assert_eq!(1, ledger.store.read().unwrap().transactions.len());
+            assert_eq!(2, ledger.visited_files.len());
+        }
+    }
 }
@@ -3,17 +3,20 @@ pub use error::ZhangError;
 #[macro_use]
 pub mod utils;
 
+pub mod cache;
 pub mod constants;
 pub mod data_source;
 pub mod data_type;
 pub mod domains;
 pub mod error;
+pub mod importer;
 pub mod inventory;
 pub mod ledger;
 pub mod options;
 #[cfg(feature = "plugin_runtime")]
 pub mod plugin;
 pub(crate) mod process;
+pub mod reports;
 pub mod store;
 
 pub mod features;
@@ -439,6 +442,24 @@ mod test {
             assert_eq!(domain.metas.get("account_name").unwrap(), "Assets:MyCard");
             Ok(())
         }
+
+        #[test]
+        fn should_raise_error_given_duplicated_query() -> Result<(), Box<dyn std::error::Error>> {
+            let ledger = load_from_text(indoc! {r#"
+                    1970-01-01 query "cash" "SELECT * FROM accounts"
+                    1970-01-02 query "cash" "SELECT * FROM postings"
+                "#});
+
+            let mut operations = ledger.operations();
+            let mut errors = operations.errors()?;
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors.pop().unwrap().error_type, ErrorKind::DefineDuplicatedQuery);
+
+            let queries = operations.queries()?;
+            assert_eq!(queries.len(), 1);
+            assert_eq!(queries[0].query_string, "SELECT * FROM accounts");
+            Ok(())
+        }
     }
     mod timezone {
         use indoc::indoc;
@@ -531,4 +552,619 @@ mod test {
             assert_eq!(1, result.len());
         }
     }
+
+    mod reports {
+        use bigdecimal::BigDecimal;
+        use chrono::NaiveDate;
+        use indoc::indoc;
+
+        use crate::data_type::text::exporter::ZhangDataTypeExportable;
+        use crate::reports::{ReportInterval, CLOSE_BOOKS_TAG, CURRENT_EARNINGS_ACCOUNT, EARNINGS_ACCOUNT};
+        use crate::test::load_from_text;
+
+        #[test]
+        fn should_roll_up_balance_sheet_and_fold_net_income_into_equity() {
+            let ledger = load_from_text(indoc! {r#"
+                1970-01-01 open Assets:Bank:Checking
+                1970-01-01 open Assets:Bank:Saving
+                1970-01-01 open Assets:Cash
+                1970-01-01 open Liabilities:CreditCard
+                1970-01-01 open Income:Salary
+                1970-01-01 open Expenses:Food
+
+                2023-01-01 "Company" "Salary"
+                  Assets:Bank:Checking 1000 CNY
+                  Income:Salary -1000 CNY
+
+                2023-01-02 "Bank" "Transfer"
+                  Assets:Bank:Checking -300 CNY
+                  Assets:Bank:Saving 300 CNY
+
+                2023-01-03 "KFC" "Lunch"
+                  Liabilities:CreditCard -50 CNY
+                  Expenses:Food 50 CNY
+
+                2023-02-01 "KFC" "Dinner"
+                  Assets:Cash -10 CNY
+                  Expenses:Food 10 CNY
+            "#});
+            let mut operations = ledger.operations();
+            let date = NaiveDate::from_ymd_opt(2023, 1, 31)
+                .unwrap()
+                .and_hms_opt(23, 59, 59)
+                .unwrap()
+                .and_local_timezone(operations.timezone)
+                .unwrap();
+
+            let sheet = operations.balance_sheet(date).unwrap();
+
+            let accounts = sheet.assets.iter().map(|node| node.account.as_str()).collect::<Vec<_>>();
+            assert_eq!(
+                vec!["Assets", "Assets:Bank", "Assets:Bank:Checking", "Assets:Bank:Saving", "Assets:Cash"],
+                accounts
+            );
+            let bank = &sheet.assets[1];
+            assert_eq!(Some("Assets".to_owned()), bank.parent);
+            assert_eq!(vec!["Assets:Bank:Checking", "Assets:Bank:Saving"], bank.children);
+            assert_eq!(BigDecimal::from(0), bank.balance.calculated.number);
+            assert_eq!(BigDecimal::from(1000), bank.total.calculated.number);
+            assert_eq!(BigDecimal::from(1000), sheet.assets[0].total.calculated.number);
+            assert_eq!(BigDecimal::from(-50), sheet.liabilities[0].total.calculated.number);
+
+            assert_eq!(BigDecimal::from(-950), sheet.net_income.calculated.number);
+            let current_earnings = sheet.equity.iter().find(|node| node.account == CURRENT_EARNINGS_ACCOUNT).unwrap();
+            assert_eq!(BigDecimal::from(-950), current_earnings.total.calculated.number);
+
+            let sum = &sheet.assets[0].total.calculated.number + &sheet.liabilities[0].total.calculated.number + &sheet.equity[0].total.calculated.number;
+            assert_eq!(BigDecimal::from(0), sum);
+        }
+
+        #[test]
+        fn should_split_range_by_calendar_interval() {
+            let timezone = chrono_tz::Asia::Shanghai;
+            let from = NaiveDate::from_ymd_opt(2023, 2, 15)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_local_timezone(timezone)
+                .unwrap();
+            let to = NaiveDate::from_ymd_opt(2023, 7, 10)
+                .unwrap()
+                .and_hms_opt(23, 59, 59)
+                .unwrap()
+                .and_local_timezone(timezone)
+                .unwrap();
+
+            let months = ReportInterval::Month.split(from, to);
+            assert_eq!(6, months.len());
+            assert_eq!(NaiveDate::from_ymd_opt(2023, 2, 28).unwrap(), months[0].1.date_naive());
+            assert_eq!(NaiveDate::from_ymd_opt(2023, 3, 1).unwrap(), months[1].0.date_naive());
+            assert_eq!(to, months[5].1);
+
+            let quarters = ReportInterval::Quarter.split(from, to);
+            assert_eq!(3, quarters.len());
+            assert_eq!(NaiveDate::from_ymd_opt(2023, 4, 1).unwrap(), quarters[1].0.date_naive());
+            assert_eq!(NaiveDate::from_ymd_opt(2023, 7, 1).unwrap(), quarters[2].0.date_naive());
+
+            assert_eq!(1, ReportInterval::Year.split(from, to).len());
+        }
+
+        #[test]
+        fn should_compare_income_statement_with_previous_period() {
+            let ledger = load_from_text(indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Income:Salary
+                1970-01-01 open Expenses:Food:Lunch
+                1970-01-01 open Expenses:Food:Dinner
+
+                2023-01-10 "KFC" "Lunch"
+                  Assets:Bank -30 CNY
+                  Expenses:Food:Lunch 30 CNY
+
+                2023-03-01 "Company" "Salary"
+                  Assets:Bank 1000 CNY
+                  Income:Salary -1000 CNY
+
+                2023-03-10 "KFC" "Lunch"
+                  Assets:Bank -20 CNY
+                  Expenses:Food:Lunch 20 CNY
+
+                2023-04-10 "KFC" "Dinner"
+                  Assets:Bank -50 CNY
+                  Expenses:Food:Dinner 50 CNY
+            "#});
+            let mut operations = ledger.operations();
+            let timezone = operations.timezone;
+            let from = NaiveDate::from_ymd_opt(2023, 3, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_local_timezone(timezone)
+                .unwrap();
+            let to = NaiveDate::from_ymd_opt(2023, 4, 30)
+                .unwrap()
+                .and_hms_opt(23, 59, 59)
+                .unwrap()
+                .and_local_timezone(timezone)
+                .unwrap();
+
+            let statement = operations.income_statement(from, to, ReportInterval::Month).unwrap();
+            assert_eq!(2, statement.periods.len());
+            assert_eq!(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(), statement.previous_from.date());
+
+            let accounts = statement.expenses.iter().map(|node| node.account.as_str()).collect::<Vec<_>>();
+            assert_eq!(vec!["Expenses", "Expenses:Food", "Expenses:Food:Dinner", "Expenses:Food:Lunch"], accounts);
+
+            let food = &statement.expenses[1].amounts;
+            assert_eq!(BigDecimal::from(20), food.periods[0].calculated.number);
+            assert_eq!(BigDecimal::from(50), food.periods[1].calculated.number);
+            assert_eq!(BigDecimal::from(70), food.total.calculated.number);
+            assert_eq!(BigDecimal::from(30), food.previous_total.calculated.number);
+            assert_eq!(BigDecimal::from(40), food.change);
+
+            assert_eq!(BigDecimal::from(-1000), statement.income[0].amounts.total.calculated.number);
+            assert_eq!(BigDecimal::from(-930), statement.net_income.total.calculated.number);
+            assert_eq!(BigDecimal::from(50), statement.net_income.periods[1].calculated.number);
+        }
+
+        #[test]
+        fn should_compare_quarter_with_previous_quarter() {
+            let ledger = load_from_text(indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Food
+
+                2022-12-31 "KFC" "Lunch"
+                  Assets:Bank -10 CNY
+                  Expenses:Food 10 CNY
+
+                2023-01-01 "KFC" "Lunch"
+                  Assets:Bank -20 CNY
+                  Expenses:Food 20 CNY
+
+                2023-03-31 "KFC" "Lunch"
+                  Assets:Bank -30 CNY
+                  Expenses:Food 30 CNY
+
+                2023-04-01 "KFC" "Lunch"
+                  Assets:Bank -40 CNY
+                  Expenses:Food 40 CNY
+            "#});
+            let mut operations = ledger.operations();
+            let timezone = operations.timezone;
+            let datetime =
+                |date: NaiveDate, hour: u32, minute: u32, second: u32| date.and_hms_opt(hour, minute, second).unwrap().and_local_timezone(timezone).unwrap();
+            let from = datetime(NaiveDate::from_ymd_opt(2023, 4, 1).unwrap(), 0, 0, 0);
+            let to = datetime(NaiveDate::from_ymd_opt(2023, 6, 30).unwrap(), 23, 59, 59);
+
+            let statement = operations.income_statement(from, to, ReportInterval::Quarter).unwrap();
+            assert_eq!(
+                NaiveDate::from_ymd_opt(2023, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+                statement.previous_from
+            );
+            assert_eq!(
+                NaiveDate::from_ymd_opt(2023, 3, 31).unwrap().and_hms_opt(23, 59, 59).unwrap(),
+                statement.previous_to
+            );
+            assert_eq!(BigDecimal::from(40), statement.expenses[0].amounts.total.calculated.number);
+            assert_eq!(BigDecimal::from(50), statement.expenses[0].amounts.previous_total.calculated.number);
+
+            // ranges not made of whole months are shifted by their length
+            let from = datetime(NaiveDate::from_ymd_opt(2023, 4, 1).unwrap(), 0, 0, 0);
+            let to = datetime(NaiveDate::from_ymd_opt(2023, 4, 10).unwrap(), 23, 59, 59);
+            let statement = operations.income_statement(from, to, ReportInterval::Month).unwrap();
+            assert_eq!(
+                NaiveDate::from_ymd_opt(2023, 3, 22).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+                statement.previous_from
+            );
+        }
+
+        #[test]
+        fn should_list_debit_and_credit_in_trial_balance() {
+            let ledger = load_from_text(indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Income:Salary
+                1970-01-01 open Expenses:Food
+
+                2023-01-01 "Company" "Salary"
+                  Assets:Bank 1000 CNY
+                  Income:Salary -1000 CNY
+
+                2023-01-03 "KFC" "Lunch"
+                  Assets:Bank -50 CNY
+                  Expenses:Food 50 CNY
+
+                2023-02-01 "KFC" "Dinner"
+                  Assets:Bank -10 CNY
+                  Expenses:Food 10 CNY
+            "#});
+            let mut operations = ledger.operations();
+            let date = NaiveDate::from_ymd_opt(2023, 1, 31)
+                .unwrap()
+                .and_hms_opt(23, 59, 59)
+                .unwrap()
+                .and_local_timezone(operations.timezone)
+                .unwrap();
+
+            let trial_balance = operations.trial_balance(date).unwrap();
+
+            let accounts = trial_balance.rows.iter().map(|row| row.account.as_str()).collect::<Vec<_>>();
+            assert_eq!(vec!["Assets:Bank", "Expenses:Food", "Income:Salary"], accounts);
+            let bank = &trial_balance.rows[0];
+            assert_eq!(BigDecimal::from(1000), bank.debit.calculated.number);
+            assert_eq!(BigDecimal::from(-50), bank.credit.calculated.number);
+            assert_eq!(BigDecimal::from(950), bank.balance.calculated.number);
+            assert_eq!(BigDecimal::from(0), trial_balance.rows[2].debit.calculated.number);
+
+            assert_eq!(BigDecimal::from(1050), trial_balance.total_debit.calculated.number);
+            assert_eq!(BigDecimal::from(-1050), trial_balance.total_credit.calculated.number);
+        }
+
+        #[test]
+        fn should_close_income_and_expenses_into_earnings() {
+            let content = indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Income:Salary
+                1970-01-01 open Expenses:Food
+
+                2023-01-01 "Company" "Salary"
+                  Assets:Bank 1000 CNY
+                  Income:Salary -1000 CNY
+
+                2023-06-03 "KFC" "Lunch"
+                  Assets:Bank -50 CNY
+                  Expenses:Food 50 CNY
+
+                2024-01-03 "KFC" "Lunch"
+                  Assets:Bank -20 CNY
+                  Expenses:Food 20 CNY
+            "#};
+            let ledger = load_from_text(content);
+            let mut operations = ledger.operations();
+
+            let directives = operations.close_books(NaiveDate::from_ymd_opt(2023, 12, 31).unwrap()).unwrap();
+            assert_eq!(None, operations.last_closed_date());
+            assert_eq!(2, directives.len());
+            let generated = directives.into_iter().map(|it| it.export()).collect::<Vec<_>>().join("\n\n");
+            assert_eq!(
+                indoc! {r#"
+                    2023-12-31 open Equity:Earnings
+
+                    2023-12-31 23:59:59 * "Close books" #close-books
+                      Expenses:Food -50 CNY
+                      Income:Salary 1000 CNY
+                      Equity:Earnings -950 CNY"#},
+                generated
+            );
+
+            let ledger = load_from_text(&format!("{}\n{}\n", content, generated));
+            let mut operations = ledger.operations();
+            assert_eq!(NaiveDate::from_ymd_opt(2023, 12, 31), operations.last_closed_date());
+            let date = NaiveDate::from_ymd_opt(2024, 1, 31)
+                .unwrap()
+                .and_hms_opt(23, 59, 59)
+                .unwrap()
+                .and_local_timezone(operations.timezone)
+                .unwrap();
+            let trial_balance = operations.trial_balance(date).unwrap();
+            let balance_of = |account: &str| {
+                trial_balance
+                    .rows
+                    .iter()
+                    .find(|row| row.account == account)
+                    .map(|row| row.balance.calculated.number.clone())
+                    .unwrap()
+            };
+            assert_eq!(BigDecimal::from(20), balance_of("Expenses:Food"));
+            assert_eq!(BigDecimal::from(0), balance_of("Income:Salary"));
+            assert_eq!(BigDecimal::from(-950), balance_of(EARNINGS_ACCOUNT));
+        }
+
+        #[test]
+        fn should_keep_income_statement_of_closed_year() {
+            let content = indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Income:Salary
+                1970-01-01 open Expenses:Food
+
+                2023-01-01 "Company" "Salary"
+                  Assets:Bank 1000 CNY
+                  Income:Salary -1000 CNY
+
+                2023-12-31 18:00:00 "KFC" "Dinner"
+                  Assets:Bank -50 CNY
+                  Expenses:Food 50 CNY
+            "#};
+            let ledger = load_from_text(content);
+            let generated = ledger
+                .operations()
+                .close_books(NaiveDate::from_ymd_opt(2023, 12, 31).unwrap())
+                .unwrap()
+                .into_iter()
+                .map(|it| it.export())
+                .collect::<Vec<_>>()
+                .join("\n\n");
+
+            let ledger = load_from_text(&format!("{}\n{}\n", content, generated));
+            let mut operations = ledger.operations();
+            {
+                // the dinner later on the closing day is processed before the closing, which leaves nothing in the account
+                let store = operations.read();
+                let closing = store.transactions.values().find(|it| it.tags.contains(&CLOSE_BOOKS_TAG.to_owned())).unwrap();
+                let food = closing.postings.iter().find(|it| it.account.content == "Expenses:Food").unwrap();
+                assert_eq!(BigDecimal::from(0), food.after_amount.number);
+            }
+
+            let timezone = operations.timezone;
+            let datetime =
+                |date: NaiveDate, hour: u32, minute: u32, second: u32| date.and_hms_opt(hour, minute, second).unwrap().and_local_timezone(timezone).unwrap();
+            let from = datetime(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(), 0, 0, 0);
+            let to = datetime(NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(), 23, 59, 59);
+            let statement = operations.income_statement(from, to, ReportInterval::Quarter).unwrap();
+            assert_eq!(BigDecimal::from(-1000), statement.income[0].amounts.total.calculated.number);
+            assert_eq!(BigDecimal::from(50), statement.expenses[0].amounts.total.calculated.number);
+            assert_eq!(BigDecimal::from(-950), statement.net_income.total.calculated.number);
+            assert_eq!(BigDecimal::from(50), statement.net_income.periods[3].calculated.number);
+        }
+
+        #[test]
+        fn should_not_treat_user_transaction_narrated_close_books_as_closed() {
+            let ledger = load_from_text(indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Food
+
+                2023-12-31 * "Close books"
+                  Assets:Bank -50 CNY
+                  Expenses:Food 50 CNY
+            "#});
+            let operations = ledger.operations();
+            assert_eq!(None, operations.last_closed_date());
+        }
+    }
+
+    mod duplicate {
+        use std::str::FromStr;
+
+        use chrono::NaiveDate;
+        use indexmap::IndexSet;
+        use indoc::indoc;
+        use zhang_ast::amount::Amount;
+        use zhang_ast::{Account, BalanceCheck, Date, Directive, Flag, Meta, Posting, Transaction, ZhangString};
+
+        use crate::importer::duplicate::{DuplicateAction, DuplicateRule, DUPLICATE_OF_META};
+        use crate::test::load_from_text;
+
+        fn transaction(date: &str, payee: &str, amount: i32, meta: Option<(&str, &str)>) -> Transaction {
+            let posting = |account: &str, units: Option<Amount>| Posting {
+                flag: None,
+                account: Account::from_str(account).unwrap(),
+                units,
+                cost: None,
+                price: None,
+                comment: None,
+            };
+            let mut metas = Meta::default();
+            if let Some((key, value)) = meta {
+                metas.insert(key.to_owned(), ZhangString::QuoteString(value.to_owned()));
+            }
+            Transaction {
+                date: Date::Date(NaiveDate::from_str(date).unwrap()),
+                flag: Some(Flag::Okay),
+                payee: Some(ZhangString::QuoteString(payee.to_owned())),
+                narration: None,
+                tags: IndexSet::new(),
+                links: IndexSet::new(),
+                postings: vec![posting("Assets:Bank", Some(Amount::new(amount.into(), "CNY"))), posting("Expenses:Food", None)],
+                meta: metas,
+            }
+        }
+
+        const LEDGER: &str = indoc! {r#"
+            1970-01-01 open Assets:Bank
+            1970-01-01 open Expenses:Food
+
+            2024-01-05 "Coffee" ""
+              fitid: "0001"
+              Assets:Bank -12 CNY
+              Expenses:Food
+
+            2024-01-10 "Bakery" ""
+              Assets:Bank -12 CNY
+              Expenses:Food
+        "#};
+
+        #[test]
+        fn should_score_candidates_by_date_and_payee() {
+            let ledger = load_from_text(LEDGER);
+            let mut operations = ledger.operations();
+
+            let candidates = operations
+                .duplicate_candidates(&transaction("2024-01-05", "coffee", -12, None), &DuplicateRule::default())
+                .unwrap();
+            assert_eq!(1, candidates.len());
+            assert_eq!(Some("Coffee".to_owned()), candidates[0].payee);
+            assert_eq!(100, candidates[0].score);
+
+            let candidates = operations
+                .duplicate_candidates(&transaction("2024-01-07", "Coffee", -12, None), &DuplicateRule::default())
+                .unwrap();
+            assert_eq!(2, candidates.len());
+            assert_eq!(Some("Coffee".to_owned()), candidates[0].payee);
+            assert_eq!(80, candidates[0].score);
+            assert_eq!(Some("Bakery".to_owned()), candidates[1].payee);
+            assert_eq!(40, candidates[1].score);
+
+            let candidates = operations
+                .duplicate_candidates(&transaction("2024-01-05", "Coffee", -13, None), &DuplicateRule::default())
+                .unwrap();
+            assert!(candidates.is_empty());
+        }
+
+        #[test]
+        fn should_not_treat_transaction_of_different_payee_on_next_day_as_duplicated() {
+            let ledger = load_from_text(LEDGER);
+            let mut operations = ledger.operations();
+
+            let given = transaction("2024-01-06", "Tea", -12, None);
+            let candidates = operations.duplicate_candidates(&given, &DuplicateRule::default()).unwrap();
+            assert_eq!(1, candidates.len());
+            assert_eq!(Some("Coffee".to_owned()), candidates[0].payee);
+            assert_eq!(60, candidates[0].score);
+
+            let skipped = operations
+                .handle_duplicates(vec![Directive::Transaction(given)], &DuplicateRule::default(), DuplicateAction::Skip)
+                .unwrap();
+            assert_eq!(1, skipped.len());
+        }
+
+        #[test]
+        fn should_match_by_external_id() {
+            let ledger = load_from_text(LEDGER);
+            let mut operations = ledger.operations();
+            let rule = DuplicateRule {
+                external_id_key: Some("fitid".to_owned()),
+                ..Default::default()
+            };
+
+            let candidates = operations
+                .duplicate_candidates(&transaction("2024-02-01", "Other", -99, Some(("fitid", "0001"))), &rule)
+                .unwrap();
+            assert_eq!(1, candidates.len());
+            assert_eq!(100, candidates[0].score);
+
+            let candidates = operations
+                .duplicate_candidates(&transaction("2024-01-05", "Coffee", -12, Some(("fitid", "0002"))), &rule)
+                .unwrap();
+            assert!(candidates.iter().all(|it| it.payee != Some("Coffee".to_owned())));
+        }
+
+        #[test]
+        fn should_mark_or_skip_duplicated_transactions() {
+            let ledger = load_from_text(LEDGER);
+            let mut operations = ledger.operations();
+            let directives = vec![
+                Directive::Transaction(transaction("2024-01-05", "Coffee", -12, None)),
+                Directive::Transaction(transaction("2024-01-20", "Coffee", -12, None)),
+            ];
+
+            let skipped = operations
+                .handle_duplicates(directives.clone(), &DuplicateRule::default(), DuplicateAction::Skip)
+                .unwrap();
+            assert_eq!(1, skipped.len());
+
+            let marked = operations
+                .handle_duplicates(directives, &DuplicateRule::default(), DuplicateAction::Mark)
+                .unwrap();
+            assert_eq!(2, marked.len());
+            let Directive::Transaction(first) = &marked[0] else { unreachable!() };
+            assert_eq!(Some(Flag::Warning), first.flag);
+            assert!(first.meta.get_one(DUPLICATE_OF_META).is_some());
+            let Directive::Transaction(second) = &marked[1] else { unreachable!() };
+            assert_eq!(Some(Flag::Okay), second.flag);
+        }
+
+        #[test]
+        fn should_skip_asserted_balance_and_duplicates_in_same_batch() {
+            let ledger = load_from_text(indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Food
+
+                2024-01-05 "Coffee" ""
+                  Assets:Bank -12 CNY
+                  Expenses:Food
+
+                2024-01-06 balance Assets:Bank -12 CNY
+            "#});
+            let mut operations = ledger.operations();
+            let balance = |date: &str| {
+                Directive::BalanceCheck(BalanceCheck {
+                    date: Date::Date(NaiveDate::from_str(date).unwrap()),
+                    account: Account::from_str("Assets:Bank").unwrap(),
+                    amount: Amount::new((-12).into(), "CNY"),
+                    tolerance: None,
+                    meta: Meta::default(),
+                })
+            };
+            let rule = DuplicateRule {
+                external_id_key: Some("fitid".to_owned()),
+                ..Default::default()
+            };
+            // the statement is imported again, with the balance line repeated and a row of the same id exported twice,
+            // along with two real purchases of the same amount on the same day, which have no id
+            let directives = vec![
+                Directive::Transaction(transaction("2024-02-01", "Bakery", -5, Some(("fitid", "B1")))),
+                Directive::Transaction(transaction("2024-02-01", "Bakery", -5, Some(("fitid", "B1")))),
+                Directive::Transaction(transaction("2024-02-03", "Tea", -7, None)),
+                Directive::Transaction(transaction("2024-02-03", "Tea", -7, None)),
+                balance("2024-01-06"),
+                balance("2024-02-02"),
+                balance("2024-02-02"),
+            ];
+
+            let skipped = operations.handle_duplicates(directives.clone(), &rule, DuplicateAction::Skip).unwrap();
+            assert_eq!(4, skipped.len());
+            assert!(matches!(&skipped[0], Directive::Transaction(it) if it.date == Date::Date(NaiveDate::from_str("2024-02-01").unwrap())));
+            assert_eq!(directives[2], skipped[1]);
+            assert_eq!(directives[3], skipped[2]);
+            assert_eq!(balance("2024-02-02"), skipped[3]);
+
+            let marked = operations.handle_duplicates(directives, &rule, DuplicateAction::Mark).unwrap();
+            assert_eq!(5, marked.len());
+            let Directive::Transaction(second) = &marked[1] else { unreachable!() };
+            assert_eq!(Some(Flag::Warning), second.flag);
+            assert!(second.meta.get_one(DUPLICATE_OF_META).is_none());
+            for directive in &marked[2..4] {
+                let Directive::Transaction(purchase) = directive else { unreachable!() };
+                assert_eq!(Some(Flag::Okay), purchase.flag);
+            }
+        }
+    }
+
+    mod categorize {
+        use indoc::indoc;
+
+        use crate::test::load_from_text;
+
+        #[test]
+        fn should_learn_most_frequent_counter_account_of_payee() {
+            let ledger = load_from_text(indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Assets:Cash
+                1970-01-01 open Expenses:Coffee
+                1970-01-01 open Expenses:Food
+
+                2024-01-01 "Starbucks" ""
+                  Assets:Bank -5 CNY
+                  Expenses:Food
+
+                2024-01-02 "Starbucks" ""
+                  Assets:Bank -5 CNY
+                  Expenses:Coffee
+
+                2024-01-03 "starbucks" ""
+                  Assets:Bank -5 CNY
+                  Expenses:Coffee
+
+                2024-01-04 "Starbucks" ""
+                  Assets:Cash -5 CNY
+                  Expenses:Food
+            "#});
+            let mut operations = ledger.operations();
+
+            let rules = operations.learned_rules("Assets:Bank").unwrap();
+            assert_eq!(1, rules.len());
+            assert_eq!(Some("^starbucks$"), rules[0].payee.as_deref());
+            assert_eq!(Some("Assets:Bank"), rules[0].account.as_deref());
+            assert_eq!(Some("Expenses:Coffee"), rules[0].counter_account.as_deref());
+
+            let payee_accounts = operations.payee_accounts().unwrap();
+            assert_eq!(
+                vec!["Assets:Bank", "Expenses:Food", "Expenses:Coffee", "Assets:Cash"],
+                payee_accounts.get("Starbucks").unwrap().iter().map(|it| it.as_str()).collect::<Vec<_>>()
+            );
+            assert_eq!(
+                vec!["Assets:Bank", "Expenses:Coffee"],
+                payee_accounts.get("starbucks").unwrap().iter().map(|it| it.as_str()).collect::<Vec<_>>()
+            );
+        }
+    }
 }
@@ -0,0 +1,27 @@
+use std::collections::HashMap;
+
+use zhang_ast::error::ErrorKind;
+use zhang_ast::{Query, SpanInfo};
+
+use crate::ledger::Ledger;
+use crate::process::DirectiveProcess;
+use crate::ZhangResult;
+
+impl DirectiveProcess for Query {
+    fn validate(&mut self, ledger: &mut Ledger, span: &SpanInfo) -> ZhangResult<bool> {
+        let mut operations = ledger.operations();
+        if operations.contains_query(self.name.as_str()) {
+            operations.new_error(ErrorKind::DefineDuplicatedQuery, span, HashMap::default())?;
+            Ok(false)
+        } else {
+            Ok(true)
+        }
+    }
+
+    fn process(&mut self, ledger: &mut Ledger, _span: &SpanInfo) -> ZhangResult<()> {
+        let mut operations = ledger.operations();
+        let date = self.date.to_timezone_datetime(&ledger.options.timezone).naive_local();
+        operations.insert_query(self.name.as_str(), date, self.query_string.as_str())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,517 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::AddAssign;
+use std::str::FromStr;
+
+use bigdecimal::{BigDecimal, Zero};
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveDateTime};
+use chrono_tz::Tz;
+#[cfg(feature = "openapi")]
+use gotcha_core::Schematic;
+use indexmap::IndexSet;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use zhang_ast::amount::{Amount, CalculatedAmount};
+use zhang_ast::{Account, AccountType, Date, Directive, Flag, Meta, Open, Posting, Transaction, ZhangString};
+
+use crate::domains::Operations;
+use crate::store::{PostingDomain, Store, TransactionDomain};
+use crate::utils::calculable::Calculable;
+use crate::{ZhangError, ZhangResult};
+
+/// the virtual equity account holding the net income which is not closed yet
+pub const CURRENT_EARNINGS_ACCOUNT: &str = "Equity:Earnings:Current";
+/// the equity account receiving the income and expenses balances when closing books
+pub const EARNINGS_ACCOUNT: &str = "Equity:Earnings";
+/// the narration of the transaction generated by closing books
+pub const CLOSE_BOOKS_NARRATION: &str = "Close books";
+/// the tag marking the transaction generated by closing books
+pub const CLOSE_BOOKS_TAG: &str = "close-books";
+
+/// a node of the account tree. children are referred by name to keep the tree flat.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "openapi", derive(Schematic))]
+pub struct AccountTreeNode {
+    pub account: String,
+    pub parent: Option<String>,
+    /// the depth of node, the root account type like `Assets` is 0
+    pub depth: usize,
+    pub children: Vec<String>,
+    /// the balance held by the account itself
+    pub balance: CalculatedAmount,
+    /// the balance rolled up from the account and all its descendants
+    pub total: CalculatedAmount,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(Schematic))]
+pub enum ReportInterval {
+    Month,
+    Quarter,
+    Year,
+}
+
+impl ReportInterval {
+    /// split the range into calendar periods, the first and the last period may be partial
+    pub fn split(&self, from: DateTime<Tz>, to: DateTime<Tz>) -> Vec<(DateTime<Tz>, DateTime<Tz>)> {
+        let timezone = from.timezone();
+        let mut periods = vec![];
+        let mut start = from;
+        while start <= to {
+            let next_start = start_of_day(self.next_boundary(start.date_naive()), &timezone);
+            periods.push((start, (next_start - Duration::seconds(1)).min(to)));
+            start = next_start;
+        }
+        periods
+    }
+
+    fn next_boundary(&self, date: NaiveDate) -> NaiveDate {
+        let (year, month) = match self {
+            ReportInterval::Month => (date.year(), date.month() + 1),
+            ReportInterval::Quarter => (date.year(), date.month0() / 3 * 3 + 4),
+            ReportInterval::Year => (date.year() + 1, 1),
+        };
+        let (year, month) = if month > 12 { (year + 1, month - 12) } else { (year, month) };
+        NaiveDate::from_ymd_opt(year, month, 1).expect("invalid calendar boundary")
+    }
+}
+
+fn start_of_day(date: NaiveDate, timezone: &Tz) -> DateTime<Tz> {
+    // midnight does not exist in some timezones on the day of DST transition
+    (0..24)
+        .find_map(|hour| date.and_hms_opt(hour, 0, 0)?.and_local_timezone(*timezone).earliest())
+        .expect("cannot find a valid local time of the day")
+}
+
+/// the range of the same length right before the given one.
+/// a range of whole months, like a quarter or a year, is shifted by calendar months, so that the previous range is whole months as well.
+fn previous_range(from: DateTime<Tz>, to: DateTime<Tz>) -> (DateTime<Tz>, DateTime<Tz>) {
+    let timezone = from.timezone();
+    let previous_to = from - Duration::seconds(1);
+    let start = from.date_naive();
+    if let Some(end) = to.date_naive().succ_opt() {
+        let whole_months =
+            start.day() == 1 && end.day() == 1 && from == start_of_day(start, &timezone) && to == start_of_day(end, &timezone) - Duration::seconds(1);
+        let months = (end.year() - start.year()) * 12 + end.month() as i32 - start.month() as i32;
+        if whole_months && months > 0 {
+            if let Some(previous_start) = start.checked_sub_months(Months::new(months as u32)) {
+                return (start_of_day(previous_start, &timezone), previous_to);
+            }
+        }
+    }
+    (previous_to - (to - from), previous_to)
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "openapi", derive(Schematic))]
+pub struct ReportPeriod {
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "openapi", derive(Schematic))]
+pub struct PeriodAmounts {
+    /// amounts of each period column
+    pub periods: Vec<CalculatedAmount>,
+    pub total: CalculatedAmount,
+    /// the amount of the previous period with the same length, which is the previous months for a range of whole months
+    pub previous_total: CalculatedAmount,
+    /// the change of converted total compared with the previous period
+    pub change: BigDecimal,
+}
+
+impl PeriodAmounts {
+    fn new(periods: Vec<CalculatedAmount>, total: CalculatedAmount, previous_total: CalculatedAmount) -> Self {
+        let change = &total.calculated.number - &previous_total.calculated.number;
+        PeriodAmounts {
+            periods,
+            total,
+            previous_total,
+            change,
+        }
+    }
+}
+
+/// a node of the income statement tree, amounts are rolled up from the account and all its descendants
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "openapi", derive(Schematic))]
+pub struct IncomeStatementNode {
+    pub account: String,
+    pub parent: Option<String>,
+    pub depth: usize,
+    pub children: Vec<String>,
+    pub amounts: PeriodAmounts,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "openapi", derive(Schematic))]
+pub struct IncomeStatement {
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+    pub previous_from: NaiveDateTime,
+    pub previous_to: NaiveDateTime,
+    pub periods: Vec<ReportPeriod>,
+    pub income: Vec<IncomeStatementNode>,
+    pub expenses: Vec<IncomeStatementNode>,
+    /// the sum of income and expenses
+    pub net_income: PeriodAmounts,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "openapi", derive(Schematic))]
+pub struct TrialBalanceRow {
+    pub account: String,
+    /// the sum of positive postings
+    pub debit: CalculatedAmount,
+    /// the sum of negative postings
+    pub credit: CalculatedAmount,
+    pub balance: CalculatedAmount,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "openapi", derive(Schematic))]
+pub struct TrialBalance {
+    pub date: NaiveDateTime,
+    pub rows: Vec<TrialBalanceRow>,
+    pub total_debit: CalculatedAmount,
+    pub total_credit: CalculatedAmount,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "openapi", derive(Schematic))]
+pub struct BalanceSheet {
+    pub date: NaiveDateTime,
+    pub assets: Vec<AccountTreeNode>,
+    pub liabilities: Vec<AccountTreeNode>,
+    /// equity tree, including the net income to date under [`CURRENT_EARNINGS_ACCOUNT`]
+    pub equity: Vec<AccountTreeNode>,
+    pub net_income: CalculatedAmount,
+}
+
+impl Operations {
+    /// the balance sheet at the given date.
+    /// income and expenses to date are folded into equity, so that assets, liabilities and equity sum up to zero.
+    pub fn balance_sheet(&mut self, date: DateTime<Tz>) -> ZhangResult<BalanceSheet> {
+        let mut amounts = {
+            let store = self.read();
+            let mut amounts = sum_postings(store.dated_postings(None, Some(date)));
+            // accounts opened before the date are listed even if they have no posting
+            for account in store.accounts.values().filter(|account| account.date.le(&date.naive_local())) {
+                let account = Account::from_str(&account.name).map_err(|_| ZhangError::InvalidAccount)?;
+                amounts.entry(account).or_default();
+            }
+            amounts
+        };
+
+        let mut net_income = vec![];
+        amounts.retain(|account, account_amounts| match account.account_type {
+            AccountType::Income | AccountType::Expenses => {
+                net_income.append(account_amounts);
+                false
+            }
+            _ => true,
+        });
+        let net_income = merge_amounts(net_income);
+        let current_earnings = Account::from_str(CURRENT_EARNINGS_ACCOUNT).map_err(|_| ZhangError::InvalidAccount)?;
+        amounts.entry(current_earnings).or_default().extend(net_income.iter().cloned());
+        let amounts = amounts
+            .into_iter()
+            .map(|(account, account_amounts)| (account, merge_amounts(account_amounts)))
+            .collect();
+
+        Ok(BalanceSheet {
+            date: date.naive_local(),
+            assets: self.account_tree(AccountType::Assets, &amounts, date)?,
+            liabilities: self.account_tree(AccountType::Liabilities, &amounts, date)?,
+            equity: self.account_tree(AccountType::Equity, &amounts, date)?,
+            net_income: net_income.calculate(date, self)?,
+        })
+    }
+
+    /// the income statement between the given dates, split into period columns by the interval and compared with the previous period of the same length.
+    pub fn income_statement(&mut self, from: DateTime<Tz>, to: DateTime<Tz>, interval: ReportInterval) -> ZhangResult<IncomeStatement> {
+        let (previous_from, previous_to) = previous_range(from, to);
+        let periods = interval.split(from, to);
+
+        let (total_amounts, previous_amounts, period_amounts) = {
+            let store = self.read();
+            let range_amounts = |from: &DateTime<Tz>, to: &DateTime<Tz>| sum_postings(store.income_statement_postings(Some(*from), Some(*to)));
+            (
+                range_amounts(&from, &to),
+                range_amounts(&previous_from, &previous_to),
+                periods.iter().map(|(from, to)| range_amounts(from, to)).collect_vec(),
+            )
+        };
+
+        // every range holds the same accounts, so that the trees are aligned node by node
+        let accounts: HashSet<Account> = total_amounts.keys().chain(previous_amounts.keys()).cloned().collect();
+        let align = |mut amounts: HashMap<Account, Vec<Amount>>| {
+            for account in &accounts {
+                amounts.entry(account.clone()).or_default();
+            }
+            amounts
+        };
+        let total_amounts = align(total_amounts);
+        let previous_amounts = align(previous_amounts);
+        let period_amounts = period_amounts.into_iter().map(align).zip(periods.iter().map(|(_, to)| *to)).collect_vec();
+
+        let mut trees = vec![];
+        for account_type in [AccountType::Income, AccountType::Expenses] {
+            let total_tree = self.account_tree(account_type, &total_amounts, to)?;
+            let previous_tree = self.account_tree(account_type, &previous_amounts, previous_to)?;
+            let mut period_trees = vec![];
+            for (amounts, period_to) in &period_amounts {
+                period_trees.push(self.account_tree(account_type, amounts, *period_to)?);
+            }
+            let nodes = total_tree
+                .into_iter()
+                .zip(previous_tree)
+                .enumerate()
+                .map(|(idx, (node, previous_node))| IncomeStatementNode {
+                    amounts: PeriodAmounts::new(
+                        period_trees.iter().map(|tree| tree[idx].total.clone()).collect(),
+                        node.total,
+                        previous_node.total,
+                    ),
+                    account: node.account,
+                    parent: node.parent,
+                    depth: node.depth,
+                    children: node.children,
+                })
+                .collect_vec();
+            trees.push(nodes);
+        }
+        let expenses = trees.pop().unwrap_or_default();
+        let income = trees.pop().unwrap_or_default();
+
+        let flatten = |amounts: &HashMap<Account, Vec<Amount>>| merge_amounts(amounts.values().flatten().cloned().collect());
+        let mut net_income_periods = vec![];
+        for (amounts, period_to) in &period_amounts {
+            net_income_periods.push(flatten(amounts).calculate(*period_to, self)?);
+        }
+        let net_income = PeriodAmounts::new(
+            net_income_periods,
+            flatten(&total_amounts).calculate(to, self)?,
+            flatten(&previous_amounts).calculate(previous_to, self)?,
+        );
+
+        Ok(IncomeStatement {
+            from: from.naive_local(),
+            to: to.naive_local(),
+            previous_from: previous_from.naive_local(),
+            previous_to: previous_to.naive_local(),
+            periods: periods
+                .into_iter()
+                .map(|(from, to)| ReportPeriod {
+                    from: from.naive_local(),
+                    to: to.naive_local(),
+                })
+                .collect(),
+            income,
+            expenses,
+            net_income,
+        })
+    }
+
+    /// the debit and credit totals of every account at the given date, accounts are ordered by name
+    pub fn trial_balance(&mut self, date: DateTime<Tz>) -> ZhangResult<TrialBalance> {
+        let mut debits: BTreeMap<String, Vec<Amount>> = BTreeMap::new();
+        let mut credits: BTreeMap<String, Vec<Amount>> = BTreeMap::new();
+        {
+            let store = self.read();
+            for posting in store.dated_postings(None, Some(date)) {
+                let (side, other_side) = if posting.inferred_amount.number >= BigDecimal::zero() {
+                    (&mut debits, &mut credits)
+                } else {
+                    (&mut credits, &mut debits)
+                };
+                side.entry(posting.account.content.clone()).or_default().push(posting.inferred_amount.clone());
+                other_side.entry(posting.account.content.clone()).or_default();
+            }
+        }
+
+        let total_debit = merge_amounts(debits.values().flatten().cloned().collect());
+        let total_credit = merge_amounts(credits.values().flatten().cloned().collect());
+        let mut rows = Vec::with_capacity(debits.len());
+        for ((account, debit), (_, credit)) in debits.into_iter().zip(credits) {
+            let balance = merge_amounts(debit.iter().chain(credit.iter()).cloned().collect());
+            rows.push(TrialBalanceRow {
+                account,
+                debit: merge_amounts(debit).calculate(date, self)?,
+                credit: merge_amounts(credit).calculate(date, self)?,
+                balance: balance.calculate(date, self)?,
+            });
+        }
+
+        Ok(TrialBalance {
+            date: date.naive_local(),
+            rows,
+            total_debit: total_debit.calculate(date, self)?,
+            total_credit: total_credit.calculate(date, self)?,
+        })
+    }
+
+    /// generate the directives closing the books at the given date, like the `CLEAR` operation of beancount.
+    /// a transaction dated at the end of the given day transfers the income and expenses balances to [`EARNINGS_ACCOUNT`],
+    /// and the earnings account is opened ahead if it does not exist.
+    pub fn close_books(&mut self, date: NaiveDate) -> ZhangResult<Vec<Directive>> {
+        let end_of_day = date.and_hms_opt(23, 59, 59).expect("invalid end of day");
+        let datetime = end_of_day.and_local_timezone(self.timezone).earliest().ok_or(ZhangError::InvalidDate)?;
+        let amounts = {
+            let store = self.read();
+            sum_postings(
+                store
+                    .dated_postings(None, Some(datetime))
+                    .filter(|posting| posting.account.is_income_statement_account()),
+            )
+        };
+
+        let mut postings = vec![];
+        let mut earnings = vec![];
+        for (account, account_amounts) in amounts.into_iter().sorted_by(|a, b| a.0.content.cmp(&b.0.content)) {
+            for amount in account_amounts.into_iter().filter(|amount| !amount.number.is_zero()) {
+                earnings.push(amount.clone());
+                postings.push(closing_posting(account.clone(), -amount));
+            }
+        }
+        if postings.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let earnings_account = Account::from_str(EARNINGS_ACCOUNT).map_err(|_| ZhangError::InvalidAccount)?;
+        postings.extend(
+            merge_amounts(earnings)
+                .into_iter()
+                .map(|amount| closing_posting(earnings_account.clone(), amount)),
+        );
+
+        let mut directives = vec![];
+        if !self.exist_account(EARNINGS_ACCOUNT)? {
+            directives.push(Directive::Open(Open {
+                date: Date::Date(date),
+                account: earnings_account,
+                commodities: vec![],
+                meta: Meta::default(),
+            }));
+        }
+        directives.push(Directive::Transaction(Transaction {
+            // dated at the instant the balances are summed up to, so that postings later on the day stay in the books
+            date: Date::Datetime(end_of_day),
+            flag: Some(Flag::Okay),
+            payee: None,
+            narration: Some(ZhangString::quote(CLOSE_BOOKS_NARRATION)),
+            tags: IndexSet::from([CLOSE_BOOKS_TAG.to_owned()]),
+            links: IndexSet::new(),
+            postings,
+            meta: Meta::default(),
+        }));
+        Ok(directives)
+    }
+
+    /// the latest date the books have been closed at, which is told by the transactions tagged [`CLOSE_BOOKS_TAG`].
+    /// closing books on or before that date would transfer the balances summed up already once more.
+    pub fn last_closed_date(&self) -> Option<NaiveDate> {
+        self.read()
+            .transactions
+            .values()
+            .filter(|it| is_closing_books(it))
+            .map(|it| it.datetime.date_naive())
+            .max()
+    }
+
+    /// build the tree of given account type from the amounts held by each account, the converted totals are calculated at the given date.
+    /// nodes are ordered depth-first, and the root node of account type is always present.
+    pub fn account_tree(
+        &mut self, account_type: AccountType, amounts: &HashMap<Account, Vec<Amount>>, date: DateTime<Tz>,
+    ) -> ZhangResult<Vec<AccountTreeNode>> {
+        let root = Account {
+            account_type,
+            content: account_type.to_string(),
+            components: vec![],
+        };
+        // keyed by components to keep the depth-first order
+        let mut accounts: BTreeMap<Vec<String>, (Account, Vec<Amount>)> = BTreeMap::new();
+        let mut totals: HashMap<String, Vec<Amount>> = HashMap::new();
+        accounts.insert(vec![], (root, vec![]));
+
+        for (account, account_amounts) in amounts.iter().filter(|(account, _)| account.account_type == account_type) {
+            accounts
+                .entry(account.components.clone())
+                .or_insert_with(|| (account.clone(), vec![]))
+                .1
+                .extend(account_amounts.iter().cloned());
+
+            let mut ancestor = account.clone();
+            loop {
+                totals.entry(ancestor.content.clone()).or_default().extend(account_amounts.iter().cloned());
+                if ancestor.components.is_empty() {
+                    break;
+                }
+                ancestor = ancestor.parent();
+                accounts.entry(ancestor.components.clone()).or_insert_with(|| (ancestor.clone(), vec![]));
+            }
+        }
+
+        let mut children: HashMap<String, Vec<String>> = HashMap::new();
+        for (account, _) in accounts.values().filter(|(account, _)| !account.components.is_empty()) {
+            children.entry(account.parent().content).or_default().push(account.content.clone());
+        }
+
+        let mut nodes = Vec::with_capacity(accounts.len());
+        for (account, account_amounts) in accounts.into_values() {
+            let total = merge_amounts(totals.remove(&account.content).unwrap_or_default());
+            nodes.push(AccountTreeNode {
+                parent: (!account.components.is_empty()).then(|| account.parent().content),
+                depth: account.components.len(),
+                children: children.remove(&account.content).unwrap_or_default(),
+                balance: merge_amounts(account_amounts).calculate(date, self)?,
+                total: total.calculate(date, self)?,
+                account: account.content,
+            });
+        }
+        Ok(nodes)
+    }
+}
+
+impl Store {
+    /// postings of income and expenses accounts within the inclusive range.
+    /// postings of closing books are left out, since they transfer the balances to equity instead of earning or spending.
+    pub fn income_statement_postings(&self, from: Option<DateTime<Tz>>, to: Option<DateTime<Tz>>) -> impl Iterator<Item = &PostingDomain> {
+        self.dated_postings(from, to)
+            .filter(|posting| posting.account.is_income_statement_account() && !self.transactions.get(&posting.trx_id).is_some_and(is_closing_books))
+    }
+}
+
+/// whether the transaction is generated by closing books, which is told by the tag [`CLOSE_BOOKS_TAG`]
+fn is_closing_books(transaction: &TransactionDomain) -> bool {
+    transaction.tags.iter().any(|tag| tag == CLOSE_BOOKS_TAG)
+}
+
+/// sum up the inferred amounts of postings per account and commodity
+pub(crate) fn sum_postings<'a>(postings: impl Iterator<Item = &'a PostingDomain>) -> HashMap<Account, Vec<Amount>> {
+    let mut ret: HashMap<Account, Vec<Amount>> = HashMap::new();
+    for posting in postings {
+        ret.entry(posting.account.clone()).or_default().push(posting.inferred_amount.clone());
+    }
+    ret.into_iter().map(|(account, amounts)| (account, merge_amounts(amounts))).collect()
+}
+
+/// merge amounts with the same commodity, commodities are ordered by name
+pub(crate) fn merge_amounts(amounts: Vec<Amount>) -> Vec<Amount> {
+    let mut merged: BTreeMap<String, BigDecimal> = BTreeMap::new();
+    for amount in amounts {
+        merged.entry(amount.commodity).or_insert_with(BigDecimal::zero).add_assign(amount.number);
+    }
+    merged.into_iter().map(|(commodity, number)| Amount::new(number, commodity)).collect_vec()
+}
+
+fn closing_posting(account: Account, amount: Amount) -> Posting {
+    Posting {
+        flag: None,
+        account,
+        units: Some(amount),
+        cost: None,
+        price: None,
+        comment: None,
+    }
+}
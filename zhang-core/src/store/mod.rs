@@ -1,7 +1,8 @@
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
 
 use bigdecimal::BigDecimal;
-use chrono::{DateTime, NaiveDate};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
 use chrono_tz::Tz;
 #[cfg(feature = "openapi")]
 use gotcha_core::Schematic;
@@ -12,18 +13,23 @@ use zhang_ast::{Account, Flag, SpanInfo};
 
 use crate::domains::schemas::{AccountDomain, CommodityDomain, ErrorDomain, MetaDomain, PriceDomain};
 
-#[derive(Default, serde::Serialize)]
+#[derive(Default, serde::Serialize, serde::Deserialize)]
 pub struct Store {
     pub options: HashMap<String, String>,
     pub accounts: HashMap<String, AccountDomain>,
     pub commodities: IndexMap<String, CommodityDomain>,
     pub transactions: HashMap<Uuid, TransactionDomain>,
-    pub postings: Vec<PostingDomain>,
+    /// postings in inserting order, which is only changed via [Store::insert_posting] to keep the index updated
+    postings: Vec<PostingDomain>,
+    #[serde(skip)]
+    posting_index: PostingIndex,
 
     pub prices: Vec<PriceDomain>,
 
     pub budgets: HashMap<String, BudgetDomain>,
 
+    pub queries: IndexMap<String, QueryDomain>,
+
     // by account
     pub commodity_lots: HashMap<String, Vec<CommodityLotRecord>>,
 
@@ -34,10 +40,99 @@ pub struct Store {
     pub errors: Vec<ErrorDomain>,
 }
 
-#[derive(Clone, serde::Serialize, Debug)]
+/// the transaction datetime and the position of posting in [Store::postings]
+type PostingKey = (DateTime<Tz>, usize);
+
+/// indices of postings ordered by transaction datetime, postings of the same datetime keep the inserting order
+#[derive(Default)]
+struct PostingIndex {
+    by_account: HashMap<Account, BTreeSet<PostingKey>>,
+    by_datetime: BTreeSet<PostingKey>,
+}
+
+/// convert inclusive datetime bounds into the bounds of posting keys
+fn posting_key_range(from: Option<DateTime<Tz>>, to: Option<DateTime<Tz>>) -> (Bound<PostingKey>, Bound<PostingKey>) {
+    (
+        from.map(|it| Bound::Included((it, usize::MIN))).unwrap_or(Bound::Unbounded),
+        to.map(|it| Bound::Included((it, usize::MAX))).unwrap_or(Bound::Unbounded),
+    )
+}
+
+impl Store {
+    /// postings in inserting order
+    pub fn postings(&self) -> &[PostingDomain] {
+        &self.postings
+    }
+
+    pub fn insert_posting(&mut self, posting: PostingDomain) {
+        let key = (posting.trx_datetime, self.postings.len());
+        self.posting_index.by_account.entry(posting.account.clone()).or_default().insert(key);
+        self.posting_index.by_datetime.insert(key);
+        self.postings.push(posting);
+    }
+
+    /// rebuild the posting index, which is not serialized, from [Store::postings]
+    pub(crate) fn rebuild_posting_index(&mut self) {
+        self.posting_index = PostingIndex::default();
+        for (idx, posting) in self.postings.iter().enumerate() {
+            let key = (posting.trx_datetime, idx);
+            self.posting_index.by_account.entry(posting.account.clone()).or_default().insert(key);
+            self.posting_index.by_datetime.insert(key);
+        }
+    }
+
+    /// attach the timezone to the datetimes restored from cache, which are held in UTC.
+    /// datetime fields cached via [`crate::cache::deserialize_instant`] must be converted here as well.
+    pub(crate) fn attach_timezone(&mut self, timezone: Tz) {
+        let attach = |datetime: &mut DateTime<Tz>| *datetime = datetime.with_timezone(&timezone);
+        for transaction in self.transactions.values_mut() {
+            attach(&mut transaction.datetime);
+            for posting in transaction.postings.iter_mut() {
+                attach(&mut posting.trx_datetime);
+            }
+        }
+        for posting in self.postings.iter_mut() {
+            attach(&mut posting.trx_datetime);
+        }
+        for document in self.documents.iter_mut() {
+            attach(&mut document.datetime);
+        }
+        for event in self
+            .budgets
+            .values_mut()
+            .flat_map(|budget| budget.detail.values_mut())
+            .flat_map(|detail| detail.events.iter_mut())
+        {
+            attach(&mut event.datetime);
+        }
+    }
+
+    /// postings dated within the inclusive range, ordered by transaction datetime
+    pub fn dated_postings(&self, from: Option<DateTime<Tz>>, to: Option<DateTime<Tz>>) -> impl DoubleEndedIterator<Item = &PostingDomain> {
+        self.posting_index
+            .by_datetime
+            .range(posting_key_range(from, to))
+            .map(|(_, idx)| &self.postings[*idx])
+    }
+
+    /// postings of the account dated within the inclusive range, ordered by transaction datetime
+    pub fn account_dated_postings(
+        &self, account: &Account, from: Option<DateTime<Tz>>, to: Option<DateTime<Tz>>,
+    ) -> impl DoubleEndedIterator<Item = &PostingDomain> {
+        self.posting_index
+            .by_account
+            .get(account)
+            .into_iter()
+            .flat_map(move |keys| keys.range(posting_key_range(from, to)))
+            .map(|(_, idx)| &self.postings[*idx])
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize, Debug)]
 pub struct TransactionDomain {
     pub id: Uuid,
     pub sequence: i32,
+    #[serde(deserialize_with = "crate::cache::deserialize_instant")]
     pub datetime: DateTime<Tz>,
     pub flag: Flag,
     pub payee: Option<String>,
@@ -105,11 +200,12 @@ impl TransactionDomain {
     }
 }
 
-#[derive(Clone, serde::Serialize, Debug)]
+#[derive(Clone, serde::Serialize, serde::Deserialize, Debug)]
 pub struct PostingDomain {
     pub id: Uuid,
     pub trx_id: Uuid,
     pub trx_sequence: i32,
+    #[serde(deserialize_with = "crate::cache::deserialize_instant")]
     pub trx_datetime: DateTime<Tz>,
     pub account: Account,
     pub unit: Option<Amount>,
@@ -119,7 +215,7 @@ pub struct PostingDomain {
     pub after_amount: Amount,
 }
 
-#[derive(Clone, serde::Serialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub enum DocumentType {
     Trx(Uuid),
     Account(Account),
@@ -146,15 +242,16 @@ impl DocumentType {
     }
 }
 
-#[derive(Clone, serde::Serialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct DocumentDomain {
+    #[serde(deserialize_with = "crate::cache::deserialize_instant")]
     pub datetime: DateTime<Tz>,
     pub document_type: DocumentType,
     pub filename: Option<String>,
     pub path: String,
 }
 
-#[derive(Default, Clone, Debug, serde::Serialize, PartialEq)]
+#[derive(Default, Clone, Debug, serde::Serialize, serde::Deserialize, PartialEq)]
 pub struct CommodityLotRecord {
     pub commodity: String,
     pub amount: BigDecimal,
@@ -165,7 +262,16 @@ pub struct CommodityLotRecord {
     pub acquisition_date: Option<NaiveDate>,
 }
 
-#[derive(Clone, Debug, serde::Serialize)]
+/// saved query defined by `query` directive
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "openapi", derive(Schematic))]
+pub struct QueryDomain {
+    pub name: String,
+    pub date: NaiveDateTime,
+    pub query_string: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct BudgetDomain {
     pub name: String,
     pub alias: Option<String>,
@@ -175,7 +281,7 @@ pub struct BudgetDomain {
     pub commodity: String,
 }
 
-#[derive(Clone, Debug, serde::Serialize)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct BudgetIntervalDetail {
     /// year and month pair, calculated as `year*100+month`, E.G. `202312`
     pub date: u32,
@@ -185,15 +291,16 @@ pub struct BudgetIntervalDetail {
     pub activity_amount: Amount,
 }
 
-#[derive(Clone, Debug, serde::Serialize)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct BudgetEvent {
+    #[serde(deserialize_with = "crate::cache::deserialize_instant")]
     pub datetime: DateTime<Tz>,
     pub timestamp: i64,
     pub amount: Amount,
     pub event_type: BudgetEventType,
 }
 
-#[derive(Clone, Debug, serde::Serialize)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "openapi", derive(Schematic))]
 pub enum BudgetEventType {
     AddAssignedAmount,
@@ -204,10 +311,92 @@ pub enum BudgetEventType {
 mod test {
     use std::str::FromStr;
 
-    use uuid::uuid;
+    use bigdecimal::BigDecimal;
+    use chrono::{DateTime, NaiveDate, TimeZone};
+    use chrono_tz::Tz;
+    use itertools::Itertools;
+    use uuid::{uuid, Uuid};
+    use zhang_ast::amount::Amount;
     use zhang_ast::Account;
 
-    use crate::store::DocumentType;
+    use crate::store::{DocumentDomain, DocumentType, PostingDomain, Store};
+
+    fn datetime(day: u32) -> DateTime<Tz> {
+        Tz::UTC
+            .from_local_datetime(&NaiveDate::from_ymd_opt(2024, 1, day).unwrap().and_hms_opt(0, 0, 0).unwrap())
+            .unwrap()
+    }
+
+    fn posting(account: &str, day: u32, number: i32) -> PostingDomain {
+        let amount = Amount::new(BigDecimal::from(number), "CNY");
+        PostingDomain {
+            id: Uuid::nil(),
+            trx_id: Uuid::nil(),
+            trx_sequence: 0,
+            trx_datetime: datetime(day),
+            account: Account::from_str(account).unwrap(),
+            unit: Some(amount.clone()),
+            cost: None,
+            inferred_amount: amount.clone(),
+            previous_amount: amount.clone(),
+            after_amount: amount,
+        }
+    }
+
+    #[test]
+    fn should_query_postings_by_index() {
+        let mut store = Store::default();
+        store.insert_posting(posting("Assets:Bank", 3, 3));
+        store.insert_posting(posting("Expenses:Food", 1, 1));
+        store.insert_posting(posting("Assets:Bank", 1, 10));
+        store.insert_posting(posting("Assets:Bank", 2, 2));
+        store.insert_posting(posting("Assets:Bank", 1, 11));
+
+        let numbers = |postings: Vec<&PostingDomain>| postings.into_iter().map(|it| it.after_amount.number.to_string()).collect_vec();
+        let bank = Account::from_str("Assets:Bank").unwrap();
+        assert_eq!(
+            vec!["10", "11", "2", "3"],
+            numbers(store.account_dated_postings(&bank, None, None).collect_vec())
+        );
+        assert_eq!(
+            vec!["10", "11", "2"],
+            numbers(store.account_dated_postings(&bank, None, Some(datetime(2))).collect_vec())
+        );
+        assert_eq!(
+            vec!["2", "3"],
+            numbers(store.account_dated_postings(&bank, Some(datetime(2)), None).collect_vec())
+        );
+        assert!(store
+            .account_dated_postings(&Account::from_str("Assets:Cash").unwrap(), None, None)
+            .next()
+            .is_none());
+
+        assert_eq!(
+            vec!["1", "10", "11"],
+            numbers(store.dated_postings(Some(datetime(1)), Some(datetime(1))).collect_vec())
+        );
+        assert_eq!(5, store.dated_postings(None, None).count());
+    }
+
+    #[test]
+    fn should_restore_datetime_of_ambiguous_local_time_from_cache() {
+        let timezone: Tz = "America/New_York".parse().unwrap();
+        // 01:30 happens twice on the day daylight saving time ends, and this is the second one
+        let local = NaiveDate::from_ymd_opt(2023, 11, 5).unwrap().and_hms_opt(1, 30, 0).unwrap();
+        let datetime = timezone.from_local_datetime(&local).latest().unwrap();
+        let mut store = Store::default();
+        store.documents.push(DocumentDomain {
+            datetime,
+            document_type: DocumentType::Account(Account::from_str("Assets:A").unwrap()),
+            filename: None,
+            path: "receipt.pdf".to_owned(),
+        });
+
+        let mut restored: Store = serde_json::from_str(&serde_json::to_string(&store).unwrap()).unwrap();
+        restored.attach_timezone(timezone);
+
+        assert_eq!(datetime.to_rfc3339(), restored.documents[0].datetime.to_rfc3339());
+    }
 
     #[test]
     fn should_match_document_type() {
@@ -18,8 +18,15 @@ impl Calculable for Vec<Amount> {
     fn calculate(&self, date: DateTime<Tz>, operations: &mut Operations) -> ZhangResult<CalculatedAmount> {
         let operating_currency: String = operations.option(KEY_OPERATING_CURRENCY)?.expect("cannot find operating currency");
 
+        let price_grip = if self.iter().any(|amount| amount.commodity.ne(&operating_currency)) {
+            Some(operations.price_grip(date.naive_local())?)
+        } else {
+            None
+        };
+
         let mut total = BigDecimal::zero();
         let mut detail = HashMap::new();
+        let mut unconverted = HashMap::new();
 
         for amount in self.iter() {
             let number = amount.number.clone();
@@ -27,8 +34,11 @@ impl Calculable for Vec<Amount> {
 
             if currency.eq(&operating_currency) {
                 total.add_assign(&number);
-            } else if let Some(price) = operations.get_price(date.naive_local(), &currency, &operating_currency)? {
-                total.add_assign((&number).mul(price.amount));
+            } else if let Some(rate) = price_grip.and_then(|grip| grip.rate(&currency, &operating_currency)) {
+                total.add_assign((&number).mul(rate));
+            } else if !number.is_zero() {
+                let unconverted_amount = unconverted.entry(currency.clone()).or_insert_with(BigDecimal::zero);
+                unconverted_amount.add_assign(&number);
             }
 
             let currency_amount = detail.entry(currency).or_insert_with(BigDecimal::zero);
@@ -38,6 +48,7 @@ impl Calculable for Vec<Amount> {
         Ok(CalculatedAmount {
             calculated: Amount::new(total, operating_currency),
             detail,
+            unconverted,
         })
     }
 }
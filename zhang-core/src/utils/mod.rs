@@ -8,6 +8,11 @@ pub mod logging;
 pub mod price_grip;
 pub mod string_;
 
+/// the sha256 hash of content, used to detect whether the content is changed
+pub fn content_hash(content: impl AsRef<[u8]>) -> String {
+    sha256::digest(content.as_ref())
+}
+
 pub fn has_path_visited<'a>(visited: impl IntoIterator<Item = &'a PathBuf>, path: &PathBuf) -> bool {
     visited.into_iter().any(|pathbuf| pathbuf.eq(path))
 }
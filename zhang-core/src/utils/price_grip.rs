@@ -1,11 +1,14 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 
-use bigdecimal::BigDecimal;
+use bigdecimal::{BigDecimal, One, Zero};
 use zhang_ast::Currency;
 
+/// `PriceGrip` is the graph of commodity prices, in which each price links two commodities in both directions.
+///
+/// commodities are kept in order so that the traversal of the graph, and hence the chosen chain, is deterministic.
 #[derive(Debug, Clone, Default)]
 pub struct PriceGrip {
-    inner: HashMap<Currency, HashMap<Currency, BigDecimal>>,
+    inner: BTreeMap<Currency, BTreeMap<Currency, BigDecimal>>,
 }
 
 impl PriceGrip {
@@ -16,6 +19,44 @@ impl PriceGrip {
     pub fn get(&self, from: &Currency, to: &Currency) -> Option<BigDecimal> {
         self.inner.get(from).and_then(|from_map| from_map.get(to)).cloned()
     }
+
+    /// the rate converting `from` into `to`, which uses inverse prices and chains through intermediate commodities if needed.
+    /// the chain with the fewest conversions is chosen, and the direct price is preferred over the inverse one.
+    /// among chains of the same length, the one through the alphabetically first commodities wins.
+    pub fn rate(&self, from: &Currency, to: &Currency) -> Option<BigDecimal> {
+        if from.eq(to) {
+            return Some(BigDecimal::one());
+        }
+        let mut rates: HashMap<&Currency, BigDecimal> = HashMap::from([(from, BigDecimal::one())]);
+        let mut queue = VecDeque::from([from]);
+        while let Some(current) = queue.pop_front() {
+            let current_rate = rates[current].clone();
+            for (next, rate) in self.neighbors(current) {
+                if rates.contains_key(next) {
+                    continue;
+                }
+                let next_rate = &current_rate * rate;
+                if next.eq(to) {
+                    return Some(next_rate);
+                }
+                rates.insert(next, next_rate);
+                queue.push_back(next);
+            }
+        }
+        None
+    }
+
+    /// the commodities which the commodity can be converted into by a single price, direct prices come first
+    fn neighbors<'a>(&'a self, commodity: &'a Currency) -> impl Iterator<Item = (&'a Currency, BigDecimal)> + 'a {
+        let direct = self.inner.get(commodity).into_iter().flatten().map(|(to, amount)| (to, amount.clone()));
+        let inverse = self.inner.iter().filter_map(move |(from, to_map)| {
+            to_map
+                .get(commodity)
+                .filter(|amount| !amount.is_zero())
+                .map(|amount| (from, BigDecimal::one() / amount))
+        });
+        direct.chain(inverse)
+    }
 }
 
 #[cfg(test)]
@@ -43,5 +84,56 @@ mod test {
             assert_eq!(grip.get(&"USD".to_string(), &"CCY".to_string()), None);
             assert_eq!(grip.get(&"CNY".to_string(), &"USD".to_string()), None);
         }
+
+        #[test]
+        fn should_get_rate_via_inverse_and_intermediate_prices() {
+            let currency = |it: &str| it.to_string();
+            let mut grip = PriceGrip::default();
+            grip.insert(currency("USD"), currency("CNY"), BigDecimal::from(7i32));
+            grip.insert(currency("VOO"), currency("USD"), BigDecimal::from(400i32));
+            grip.insert(currency("CNY"), currency("JPY"), BigDecimal::from(20i32));
+
+            assert_eq!(grip.rate(&currency("CNY"), &currency("CNY")), Some(BigDecimal::from(1i32)));
+            assert_eq!(grip.rate(&currency("USD"), &currency("CNY")), Some(BigDecimal::from(7i32)));
+            assert_eq!(
+                grip.rate(&currency("JPY"), &currency("CNY")),
+                Some(BigDecimal::from(1i32) / BigDecimal::from(20i32))
+            );
+            assert_eq!(grip.rate(&currency("VOO"), &currency("CNY")), Some(BigDecimal::from(2800i32)));
+            assert_eq!(grip.rate(&currency("VOO"), &currency("JPY")), Some(BigDecimal::from(56000i32)));
+            assert_eq!(grip.rate(&currency("VOO"), &currency("EUR")), None);
+        }
+
+        #[test]
+        fn should_prefer_direct_price_over_inverse_one() {
+            let currency = |it: &str| it.to_string();
+            let mut grip = PriceGrip::default();
+            grip.insert(currency("USD"), currency("CNY"), BigDecimal::from(7i32));
+            grip.insert(currency("CNY"), currency("USD"), BigDecimal::from(1i32) / BigDecimal::from(8i32));
+
+            assert_eq!(grip.rate(&currency("USD"), &currency("CNY")), Some(BigDecimal::from(7i32)));
+            assert_eq!(
+                grip.rate(&currency("CNY"), &currency("USD")),
+                Some(BigDecimal::from(1i32) / BigDecimal::from(8i32))
+            );
+        }
+
+        #[test]
+        fn should_choose_same_chain_among_equally_short_ones() {
+            let currency = |it: &str| it.to_string();
+            for _ in 0..20 {
+                let mut grip = PriceGrip::default();
+                grip.insert(currency("USD"), currency("JPY"), BigDecimal::from(150i32));
+                grip.insert(currency("JPY"), currency("CNY"), BigDecimal::from(1i32) / BigDecimal::from(20i32));
+                grip.insert(currency("USD"), currency("EUR"), BigDecimal::from(1i32));
+                grip.insert(currency("EUR"), currency("CNY"), BigDecimal::from(8i32));
+
+                assert_eq!(grip.rate(&currency("USD"), &currency("CNY")), Some(BigDecimal::from(8i32)));
+                assert_eq!(
+                    grip.rate(&currency("CNY"), &currency("USD")),
+                    Some(BigDecimal::from(1i32) / BigDecimal::from(8i32))
+                );
+            }
+        }
     }
 }
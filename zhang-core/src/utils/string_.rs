@@ -10,6 +10,9 @@ pub trait StringExt {
     fn into_unquote(self) -> ZhangString;
 
     fn replace_by_span(&mut self, span: &SpanInfo, content: &str);
+
+    /// remove the content of span with its line ending, and the blank line after it if it is surrounded by blank lines
+    fn remove_by_span(&mut self, span: &SpanInfo);
 }
 
 impl StringExt for String {
@@ -33,6 +36,25 @@ impl StringExt for String {
         self.replace_range(span.start..span.end, "");
         self.insert_str(span.start, content);
     }
+
+    fn remove_by_span(&mut self, span: &SpanInfo) {
+        let line_ending = |content: &str| {
+            if content.starts_with("\r\n") {
+                2
+            } else if content.starts_with('\n') {
+                1
+            } else {
+                0
+            }
+        };
+        let mut end = span.end;
+        end += line_ending(&self[end..]);
+        let leading_blank = span.start == 0 || self[..span.start].ends_with("\n\n") || self[..span.start].ends_with("\n\r\n");
+        if leading_blank {
+            end += line_ending(&self[end..]);
+        }
+        self.replace_range(span.start..end, "");
+    }
 }
 
 pub fn escape_with_quote(s: &str) -> Cow<'_, str> {
@@ -106,4 +128,19 @@ mod test {
         origin.replace_by_span(&info, "new");
         assert_eq!(origin, "hnewoworld");
     }
+
+    #[test]
+    fn test_remove_by_span() {
+        let mut origin = "a\n\nbc\n\nd".to_string();
+        origin.remove_by_span(&SpanInfo::simple(3, 5));
+        assert_eq!(origin, "a\n\nd");
+
+        let mut origin = "a\nbc\nd".to_string();
+        origin.remove_by_span(&SpanInfo::simple(2, 4));
+        assert_eq!(origin, "a\nd");
+
+        let mut origin = "a\n\nbc".to_string();
+        origin.remove_by_span(&SpanInfo::simple(3, 5));
+        assert_eq!(origin, "a\n\n");
+    }
 }
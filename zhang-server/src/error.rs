@@ -1,10 +1,13 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
+use chrono::NaiveDate;
 use serde_json::json;
 use thiserror::Error;
 use zhang_ast::account::InvalidAccountError;
+use zhang_core::importer::duplicate::DuplicateCandidate;
 use zhang_core::ZhangError;
+use zhang_sql::SqlError;
 
 #[derive(Error, Debug)]
 pub enum ServerError {
@@ -28,6 +31,37 @@ pub enum ServerError {
 
     #[error("sql error: {0}")]
     SqlError(String),
+
+    /// the task running on the blocking pool panicked or was cancelled
+    #[error("task error: {0}")]
+    TaskError(#[from] tokio::task::JoinError),
+
+    #[error("sql is rejected: {0}")]
+    SqlRejected(String),
+
+    #[error("statement cannot be imported: {0}")]
+    InvalidStatement(String),
+
+    /// the content is changed since the client read it, the current content is carried
+    #[error("content has been changed by others")]
+    Conflict(String),
+
+    /// the transaction to be created may be the same one as the existing candidates
+    #[error("transaction may be a duplicate of existing ones")]
+    DuplicateTransaction(Vec<DuplicateCandidate>),
+
+    /// the books are closed at the carried date already, closing them again on or before it would transfer the balances twice
+    #[error("books are already closed on {0}")]
+    BooksClosed(NaiveDate),
+}
+
+impl From<SqlError> for ServerError {
+    fn from(value: SqlError) -> Self {
+        match value {
+            SqlError::ReadOnly(reason) => Self::SqlRejected(reason),
+            SqlError::Execution(e) => Self::SqlError(e.to_string()),
+        }
+    }
 }
 
 impl From<InvalidAccountError> for ServerError {
@@ -38,14 +72,24 @@ impl From<InvalidAccountError> for ServerError {
 
 impl IntoResponse for ServerError {
     fn into_response(self) -> Response {
-        let payload = json!({
+        let mut payload = json!({
             "message": format!("{}", self),
             "origin": "with_rejection"
         });
+        if let ServerError::Conflict(content) | ServerError::CoreError(ZhangError::ConflictedContent { content, .. }) = &self {
+            payload["content"] = json!(content);
+        }
+        if let ServerError::DuplicateTransaction(candidates) = &self {
+            payload["candidates"] = json!(candidates);
+        }
 
         let status = match self {
             ServerError::NotFound => StatusCode::NOT_FOUND,
-            ServerError::BadRequest => StatusCode::BAD_REQUEST,
+            ServerError::BadRequest | ServerError::SqlRejected(_) | ServerError::InvalidStatement(_) => StatusCode::BAD_REQUEST,
+            ServerError::Conflict(_)
+            | ServerError::DuplicateTransaction(_)
+            | ServerError::BooksClosed(_)
+            | ServerError::CoreError(ZhangError::ConflictedContent { .. }) => StatusCode::CONFLICT,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
@@ -0,0 +1,490 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::sync::Arc;
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use gotcha::Schematic;
+use itertools::Itertools;
+use log::{error, info};
+use serde::Serialize;
+use zhang_ast::Directive;
+use zhang_core::data_source::{DataSource, LoadResult};
+use zhang_core::ledger::Ledger;
+use zhang_core::{ZhangError, ZhangResult};
+
+/// the identity used to commit if the user does not configure one for git
+const FALLBACK_USER_NAME: &str = "zhang";
+const FALLBACK_USER_EMAIL: &str = "zhang@localhost";
+
+/// separator of fields in the formatted output of `git log`
+const FIELD_SEPARATOR: char = '\u{1f}';
+const LOG_FORMAT: &str = "--format=%H%x1f%an%x1f%aI%x1f%s";
+
+#[derive(Debug, Clone, Serialize, Schematic)]
+pub struct GitCommit {
+    pub id: String,
+    pub author: String,
+    pub datetime: DateTime<Utc>,
+    pub summary: String,
+}
+
+/// a local git working tree holding the ledger files
+pub struct GitRepository {
+    root: PathBuf,
+    has_identity: bool,
+}
+
+impl GitRepository {
+    /// open the working tree containing the given path, a new repository is initialized if the path is not in any working tree
+    pub fn open(root: impl AsRef<Path>) -> ZhangResult<Self> {
+        let root = root.as_ref().to_path_buf();
+        let mut repository = Self { root, has_identity: true };
+        if repository.run(&["rev-parse", "--is-inside-work-tree"]).is_err() {
+            info!("initializing git repository at {}", repository.root.display());
+            repository.run(&["init"])?;
+        }
+        repository.has_identity = repository.run(&["config", "user.email"]).is_ok();
+        Ok(repository)
+    }
+
+    /// commit the changes of given paths, nothing happens if none of them is changed
+    pub fn commit(&self, paths: &[String], message: &str) -> ZhangResult<()> {
+        let paths = paths.iter().unique().map(|it| it.as_str()).collect_vec();
+        if paths.is_empty() {
+            return Ok(());
+        }
+        self.run(&[&["add", "--"], paths.as_slice()].concat())?;
+        // `git diff --quiet` exits with 1 if there are differences
+        if self.run(&[&["diff", "--cached", "--quiet", "--"], paths.as_slice()].concat()).is_ok() {
+            return Ok(());
+        }
+        self.run(&[&["commit", "--quiet", "--message", message, "--"], paths.as_slice()].concat())?;
+        Ok(())
+    }
+
+    /// the latest commits, the latest comes first
+    pub fn commits(&self, limit: usize) -> ZhangResult<Vec<GitCommit>> {
+        // a fresh repository has no commit, and `git log` fails on it
+        if self.run(&["rev-parse", "--verify", "--quiet", "HEAD"]).is_err() {
+            return Ok(vec![]);
+        }
+        let output = self.run(&["log", "--max-count", &limit.to_string(), LOG_FORMAT])?;
+        output.lines().filter(|it| !it.is_empty()).map(parse_commit).collect()
+    }
+
+    pub fn commit_info(&self, commit_id: &str) -> ZhangResult<GitCommit> {
+        validate_commit_id(commit_id)?;
+        let output = self.run(&["show", "--no-patch", LOG_FORMAT, commit_id])?;
+        parse_commit(output.trim_end())
+    }
+
+    /// the patch introduced by the commit
+    pub fn diff(&self, commit_id: &str) -> ZhangResult<String> {
+        validate_commit_id(commit_id)?;
+        self.run(&["show", "--format=", "--patch", commit_id])
+    }
+
+    fn run(&self, args: &[&str]) -> ZhangResult<String> {
+        let mut command = std::process::Command::new("git");
+        command.arg("-C").arg(&self.root);
+        if !self.has_identity {
+            command.args([
+                "-c",
+                &format!("user.name={}", FALLBACK_USER_NAME),
+                "-c",
+                &format!("user.email={}", FALLBACK_USER_EMAIL),
+            ]);
+        }
+        let output = command.args(args).output()?;
+        output_to_result(args, output)
+    }
+}
+
+fn output_to_result(args: &[&str], output: Output) -> ZhangResult<String> {
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(ZhangError::CustomError(format!(
+            "fail to run git {}: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// commit id is passed to git as an argument, so only hex digits are accepted to avoid being treated as an option
+pub fn is_valid_commit_id(commit_id: &str) -> bool {
+    !commit_id.is_empty() && commit_id.chars().all(|it| it.is_ascii_hexdigit())
+}
+
+fn validate_commit_id(commit_id: &str) -> ZhangResult<()> {
+    if !is_valid_commit_id(commit_id) {
+        return Err(ZhangError::CustomError(format!("invalid commit id: {}", commit_id)));
+    }
+    Ok(())
+}
+
+fn parse_commit(line: &str) -> ZhangResult<GitCommit> {
+    let invalid = || ZhangError::CustomError(format!("invalid git log line: {}", line));
+    let (id, author, datetime, summary) = line.splitn(4, FIELD_SEPARATOR).collect_tuple().ok_or_else(invalid)?;
+    Ok(GitCommit {
+        id: id.to_owned(),
+        author: author.to_owned(),
+        datetime: DateTime::parse_from_rfc3339(datetime).map_err(|_| invalid())?.with_timezone(&Utc),
+        summary: summary.to_owned(),
+    })
+}
+
+/// the commit message describing the appended directives
+fn append_message(directives: &[Directive]) -> String {
+    let describe = |directive: &Directive| {
+        let directive_type = directive.directive_type().to_string().to_lowercase();
+        match directive.datetime() {
+            Some(datetime) => format!("{} on {}", directive_type, datetime.date()),
+            None => directive_type,
+        }
+    };
+    match directives {
+        [directive] => format!("add {}", describe(directive)),
+        _ => format!(
+            "add {} directives\n\n{}",
+            directives.len(),
+            directives.iter().map(|it| format!("- {}", describe(it))).join("\n")
+        ),
+    }
+}
+
+tokio::task_local! {
+    /// the files saved by the append running in current task. appends write files through `ledger.data_source`,
+    /// so the files are collected here instead of being committed one by one, and saves of other requests are not mixed in.
+    static APPENDED_PATHS: RefCell<Vec<String>>;
+}
+
+/// `GitDataSource` commits every change made through the wrapped data source into the git working tree.
+///
+/// each `save` produces a commit, and all the files touched by an `append` are committed together.
+pub struct GitDataSource {
+    inner: Arc<dyn DataSource>,
+    repository: Arc<GitRepository>,
+}
+
+impl GitDataSource {
+    pub fn new(inner: Arc<dyn DataSource>, repository: Arc<GitRepository>) -> Self {
+        Self { inner, repository }
+    }
+
+    fn saved(&self, path: String) {
+        if !collect(&path) {
+            let message = format!("update {}", path);
+            commit(&self.repository, &[path], &message);
+        }
+    }
+
+    async fn async_saved(&self, path: String) {
+        if !collect(&path) {
+            let message = format!("update {}", path);
+            self.async_commit(vec![path], message).await;
+        }
+    }
+
+    fn removed(&self, path: String) {
+        if !collect(&path) {
+            let message = format!("remove {}", path);
+            commit(&self.repository, &[path], &message);
+        }
+    }
+
+    async fn async_removed(&self, path: String) {
+        if !collect(&path) {
+            let message = format!("remove {}", path);
+            self.async_commit(vec![path], message).await;
+        }
+    }
+
+    fn appended(&self, directives: &[Directive], paths: Vec<String>) {
+        commit(&self.repository, &paths, &append_message(directives));
+    }
+
+    async fn async_appended(&self, directives: &[Directive], paths: Vec<String>) {
+        self.async_commit(paths, append_message(directives)).await;
+    }
+
+    /// git runs as blocking processes, so they are moved off the runtime to not stall other requests on the worker
+    async fn async_commit(&self, paths: Vec<String>, message: String) {
+        let repository = self.repository.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || commit(&repository, &paths, &message)).await {
+            error!("fail to run commit task: {}", e);
+        }
+    }
+}
+
+/// collect the saved path into the append running in current task, false is returned if there is no such append
+fn collect(path: &str) -> bool {
+    APPENDED_PATHS.try_with(|paths| paths.borrow_mut().push(path.to_owned())).is_ok()
+}
+
+/// the change has been written into file, so failing to commit is not reported to caller
+fn commit(repository: &GitRepository, paths: &[String], message: &str) {
+    if let Err(e) = repository.commit(paths, message) {
+        error!("fail to commit changes of {:?}: {}", paths, e);
+    }
+}
+
+#[async_trait]
+impl DataSource for GitDataSource {
+    fn export(&self, directive: Directive) -> ZhangResult<Vec<u8>> {
+        self.inner.export(directive)
+    }
+
+    fn get(&self, path: String) -> ZhangResult<Vec<u8>> {
+        self.inner.get(path)
+    }
+
+    fn load(&self, entry: String, endpoint: String) -> ZhangResult<LoadResult> {
+        self.inner.load(entry, endpoint)
+    }
+
+    fn visited_file_path(&self, entry: &Path, visited_file: &Path) -> String {
+        self.inner.visited_file_path(entry, visited_file)
+    }
+
+    fn save(&self, ledger: &Ledger, path: String, content: &[u8]) -> ZhangResult<()> {
+        self.inner.save(ledger, path.clone(), content)?;
+        self.saved(path);
+        Ok(())
+    }
+
+    fn append(&self, ledger: &Ledger, directives: Vec<Directive>) -> ZhangResult<()> {
+        let (result, paths) = APPENDED_PATHS.sync_scope(RefCell::default(), || {
+            let result = self.inner.append(ledger, directives.clone());
+            (result, APPENDED_PATHS.with(RefCell::take))
+        });
+        self.appended(&directives, paths);
+        result
+    }
+
+    fn remove(&self, ledger: &Ledger, path: String) -> ZhangResult<()> {
+        self.inner.remove(ledger, path.clone())?;
+        self.removed(path);
+        Ok(())
+    }
+
+    async fn async_load(&self, entry: String, endpoint: String) -> ZhangResult<LoadResult> {
+        self.inner.async_load(entry, endpoint).await
+    }
+
+    async fn async_get(&self, path: String) -> ZhangResult<Vec<u8>> {
+        self.inner.async_get(path).await
+    }
+
+    async fn async_append(&self, ledger: &Ledger, directives: Vec<Directive>) -> ZhangResult<()> {
+        let (result, paths) = APPENDED_PATHS
+            .scope(RefCell::default(), async {
+                let result = self.inner.async_append(ledger, directives.clone()).await;
+                (result, APPENDED_PATHS.with(RefCell::take))
+            })
+            .await;
+        self.async_appended(&directives, paths).await;
+        result
+    }
+
+    async fn async_save(&self, ledger: &Ledger, path: String, content: &[u8]) -> ZhangResult<()> {
+        self.inner.async_save(ledger, path.clone(), content).await?;
+        self.async_saved(path).await;
+        Ok(())
+    }
+
+    async fn async_remove(&self, ledger: &Ledger, path: String) -> ZhangResult<()> {
+        self.inner.async_remove(ledger, path.clone()).await?;
+        self.async_removed(path).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    use axum::async_trait;
+    use chrono::NaiveDate;
+    use itertools::Itertools;
+    use tempfile::tempdir;
+    use tokio::sync::oneshot;
+    use zhang_ast::{Account, Date, Directive, Meta, Open};
+    use zhang_core::data_source::{DataSource, LoadResult, LocalFileSystemDataSource};
+    use zhang_core::data_type::text::ZhangDataType;
+    use zhang_core::ledger::Ledger;
+    use zhang_core::ZhangResult;
+
+    use crate::git::{parse_commit, GitDataSource, GitRepository, FIELD_SEPARATOR};
+
+    fn open_directive() -> Directive {
+        Directive::Open(Open {
+            date: Date::Date(NaiveDate::from_ymd_opt(2023, 5, 1).unwrap()),
+            account: Account::from_str("Assets:Bank").unwrap(),
+            commodities: vec![],
+            meta: Meta::default(),
+        })
+    }
+
+    fn summaries(repository: &GitRepository) -> Vec<String> {
+        repository.commits(10).unwrap().into_iter().map(|it| it.summary).collect()
+    }
+
+    fn committed_files(repository: &GitRepository, commit_id: &str) -> Vec<String> {
+        let output = repository.run(&["show", "--name-only", "--format=", commit_id]).unwrap();
+        output.lines().filter(|it| !it.is_empty()).map(|it| it.to_owned()).sorted().collect()
+    }
+
+    fn load(dir: &Path) -> (Arc<GitRepository>, Ledger) {
+        std::fs::write(dir.join("main.zhang"), "option \"title\" \"git\"\n").unwrap();
+        let repository = Arc::new(GitRepository::open(dir).unwrap());
+        let data_source = GitDataSource::new(Arc::new(LocalFileSystemDataSource::new(ZhangDataType {})), repository.clone());
+        let ledger = Ledger::load_with_data_source(dir.to_path_buf(), "main.zhang".to_string(), Arc::new(data_source)).unwrap();
+        (repository, ledger)
+    }
+
+    #[test]
+    fn should_parse_commit() {
+        let line = ["abc123", "zhang", "2024-01-02T03:04:05+08:00", "update main.zhang"].join(&FIELD_SEPARATOR.to_string());
+        let commit = parse_commit(&line).unwrap();
+        assert_eq!("abc123", commit.id);
+        assert_eq!("zhang", commit.author);
+        assert_eq!("2024-01-01T19:04:05+00:00", commit.datetime.to_rfc3339());
+        assert_eq!("update main.zhang", commit.summary);
+
+        assert!(parse_commit("abc123").is_err());
+        let invalid_datetime = ["abc123", "zhang", "yesterday", "update"].join(&FIELD_SEPARATOR.to_string());
+        assert!(parse_commit(&invalid_datetime).is_err());
+    }
+
+    #[test]
+    fn should_commit_changed_paths_and_show_them() {
+        let dir = tempdir().unwrap();
+        let repository = GitRepository::open(dir.path()).unwrap();
+        assert!(repository.commits(10).unwrap().is_empty());
+        let file = dir.path().join("main.zhang").to_string_lossy().to_string();
+
+        std::fs::write(&file, "option \"title\" \"first\"\n").unwrap();
+        repository.commit(std::slice::from_ref(&file), "first").unwrap();
+        std::fs::write(&file, "option \"title\" \"second\"\n").unwrap();
+        repository.commit(&[file.clone(), file.clone()], "second").unwrap();
+        // nothing is committed if the paths are not changed
+        repository.commit(std::slice::from_ref(&file), "third").unwrap();
+        repository.commit(&[], "fourth").unwrap();
+
+        assert_eq!(vec!["second", "first"], summaries(&repository));
+        let commits = repository.commits(1).unwrap();
+        assert_eq!(1, commits.len());
+        assert_eq!("second", repository.commit_info(&commits[0].id).unwrap().summary);
+        let diff = repository.diff(&commits[0].id).unwrap();
+        assert!(diff.contains("-option \"title\" \"first\""));
+        assert!(diff.contains("+option \"title\" \"second\""));
+        assert!(repository.diff("--help").is_err());
+    }
+
+    #[test]
+    fn should_commit_each_save() {
+        let dir = tempdir().unwrap();
+        let (repository, ledger) = load(dir.path());
+        let main_file = ledger.entry.0.join("main.zhang").to_string_lossy().to_string();
+
+        ledger.data_source.save(&ledger, main_file.clone(), b"option \"title\" \"first\"\n").unwrap();
+        ledger.data_source.save(&ledger, main_file.clone(), b"option \"title\" \"second\"\n").unwrap();
+        // saving the same content creates no commit
+        ledger.data_source.save(&ledger, main_file.clone(), b"option \"title\" \"second\"\n").unwrap();
+
+        let update = format!("update {}", main_file);
+        assert_eq!(vec![update.clone(), update], summaries(&repository));
+    }
+
+    #[test]
+    fn should_commit_each_append_with_include_and_target_files() {
+        let dir = tempdir().unwrap();
+        let (repository, ledger) = load(dir.path());
+
+        ledger.data_source.append(&ledger, vec![open_directive()]).unwrap();
+
+        assert_eq!(vec!["add open on 2023-05-01"], summaries(&repository));
+        let commits = repository.commits(10).unwrap();
+        assert_eq!(vec!["data/2023/5.zhang", "main.zhang"], committed_files(&repository, &commits[0].id));
+    }
+
+    /// local file system data source whose append waits to be resumed after files are written
+    struct PausedAppendDataSource {
+        inner: LocalFileSystemDataSource,
+        appended: Mutex<Option<oneshot::Sender<()>>>,
+        resume: Mutex<Option<oneshot::Receiver<()>>>,
+    }
+
+    #[async_trait]
+    impl DataSource for PausedAppendDataSource {
+        fn export(&self, directive: Directive) -> ZhangResult<Vec<u8>> {
+            self.inner.export(directive)
+        }
+
+        fn get(&self, path: String) -> ZhangResult<Vec<u8>> {
+            self.inner.get(path)
+        }
+
+        fn load(&self, entry: String, endpoint: String) -> ZhangResult<LoadResult> {
+            self.inner.load(entry, endpoint)
+        }
+
+        fn save(&self, ledger: &Ledger, path: String, content: &[u8]) -> ZhangResult<()> {
+            self.inner.save(ledger, path, content)
+        }
+
+        async fn async_append(&self, ledger: &Ledger, directives: Vec<Directive>) -> ZhangResult<()> {
+            self.inner.append(ledger, directives)?;
+            let appended = self.appended.lock().unwrap().take().unwrap();
+            appended.send(()).unwrap();
+            let resume = self.resume.lock().unwrap().take().unwrap();
+            resume.await.unwrap();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn should_not_fold_concurrent_save_into_append_commit() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("main.zhang"), "option \"title\" \"git\"\n").unwrap();
+        std::fs::write(dir.path().join("other.zhang"), "").unwrap();
+        let repository = Arc::new(GitRepository::open(dir.path()).unwrap());
+        let (appended_sender, appended_receiver) = oneshot::channel();
+        let (resume_sender, resume_receiver) = oneshot::channel();
+        let inner = PausedAppendDataSource {
+            inner: LocalFileSystemDataSource::new(ZhangDataType {}),
+            appended: Mutex::new(Some(appended_sender)),
+            resume: Mutex::new(Some(resume_receiver)),
+        };
+        let data_source = Arc::new(GitDataSource::new(Arc::new(inner), repository.clone()));
+        let ledger = Arc::new(
+            Ledger::async_load(dir.path().to_path_buf(), "main.zhang".to_string(), data_source)
+                .await
+                .unwrap(),
+        );
+
+        let appending = tokio::spawn({
+            let ledger = ledger.clone();
+            async move { ledger.data_source.async_append(&ledger, vec![open_directive()]).await }
+        });
+        appended_receiver.await.unwrap();
+        let other_file = ledger.entry.0.join("other.zhang").to_string_lossy().to_string();
+        ledger.data_source.async_save(&ledger, other_file.clone(), b"; other\n").await.unwrap();
+        resume_sender.send(()).unwrap();
+        appending.await.unwrap().unwrap();
+
+        assert_eq!(
+            vec!["add open on 2023-05-01".to_string(), format!("update {}", other_file)],
+            summaries(&repository)
+        );
+        let commits = repository.commits(10).unwrap();
+        assert_eq!(vec!["data/2023/5.zhang", "main.zhang"], committed_files(&repository, &commits[0].id));
+        assert_eq!(vec!["other.zhang"], committed_files(&repository, &commits[1].id));
+    }
+}
@@ -0,0 +1,299 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use gotcha::Schematic;
+use serde::Serialize;
+use zhang_ast::Directive;
+use zhang_core::data_source::{DataSource, LoadResult};
+use zhang_core::ledger::Ledger;
+use zhang_core::ZhangResult;
+
+/// how many changes are kept in history
+pub const HISTORY_CAPACITY: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Schematic)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub datetime: DateTime<Utc>,
+    /// the files changed together in the order they are written, like the main file including and the file appended by an append
+    pub files: Vec<HistoryFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Schematic)]
+pub struct HistoryFile {
+    pub path: String,
+    /// the content before the change, `None` if the file was created by the change
+    pub before: Option<String>,
+    /// the content after the change, `None` if the file was removed by the change
+    pub after: Option<String>,
+}
+
+impl HistoryFile {
+    /// `before` and `after` are `None` if the file does not exist, and `None` is returned if the change is not recorded
+    fn new(path: String, before: Option<Vec<u8>>, after: Option<&[u8]>) -> Option<Self> {
+        // only text files are recorded, binary files like uploaded documents are skipped
+        let before = before.map(String::from_utf8).transpose().ok()?;
+        let after = after.map(|it| String::from_utf8(it.to_vec())).transpose().ok()?;
+        (before != after).then_some(Self { path, before, after })
+    }
+}
+
+tokio::task_local! {
+    /// the files changed by the group running in current task, which are recorded as one change once the group is finished
+    static CHANGED_FILES: RefCell<Vec<HistoryFile>>;
+}
+
+/// the latest changes of files made through the server, the oldest one is dropped once the capacity is reached.
+///
+/// history is kept in memory only and is lost when the server restarts, use the git data source for durable history.
+pub struct History {
+    capacity: usize,
+    counter: AtomicU64,
+    entries: Mutex<VecDeque<HistoryEntry>>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counter: AtomicU64::new(1),
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// the files changed by the given future are recorded as one change
+    pub async fn group<T>(&self, change: impl Future<Output = T>) -> T {
+        let (result, files) = CHANGED_FILES
+            .scope(RefCell::default(), async {
+                let result = change.await;
+                (result, CHANGED_FILES.with(RefCell::take))
+            })
+            .await;
+        self.record(files);
+        result
+    }
+
+    /// like [History::group], but for the blocking change
+    pub fn sync_group<T>(&self, change: impl FnOnce() -> T) -> T {
+        let (result, files) = CHANGED_FILES.sync_scope(RefCell::default(), || {
+            let result = change();
+            (result, CHANGED_FILES.with(RefCell::take))
+        });
+        self.record(files);
+        result
+    }
+
+    /// the file is collected into the group running in current task, or recorded as a change on its own if there is no such group
+    fn changed(&self, file: Option<HistoryFile>) {
+        let Some(file) = file else {
+            return;
+        };
+        if CHANGED_FILES.try_with(|files| files.borrow_mut().push(file.clone())).is_err() {
+            self.record(vec![file]);
+        }
+    }
+
+    fn record(&self, files: Vec<HistoryFile>) {
+        if files.is_empty() {
+            return;
+        }
+        let entry = HistoryEntry {
+            id: self.counter.fetch_add(1, Ordering::Relaxed),
+            datetime: Utc::now(),
+            files,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// all the changes, the latest comes first
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        self.entries.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    pub fn entry(&self, id: u64) -> Option<HistoryEntry> {
+        self.entries.lock().unwrap().iter().find(|it| it.id == id).cloned()
+    }
+}
+
+/// `HistoryDataSource` records every file write of the wrapped data source into history,
+/// and all the files written by an `append` are recorded as one change.
+pub struct HistoryDataSource {
+    inner: Arc<dyn DataSource>,
+    history: Arc<History>,
+}
+
+impl HistoryDataSource {
+    pub fn new(inner: Arc<dyn DataSource>, history: Arc<History>) -> Self {
+        Self { inner, history }
+    }
+}
+
+#[async_trait]
+impl DataSource for HistoryDataSource {
+    fn export(&self, directive: Directive) -> ZhangResult<Vec<u8>> {
+        self.inner.export(directive)
+    }
+
+    fn get(&self, path: String) -> ZhangResult<Vec<u8>> {
+        self.inner.get(path)
+    }
+
+    fn load(&self, entry: String, endpoint: String) -> ZhangResult<LoadResult> {
+        self.inner.load(entry, endpoint)
+    }
+
+    fn visited_file_path(&self, entry: &Path, visited_file: &Path) -> String {
+        self.inner.visited_file_path(entry, visited_file)
+    }
+
+    fn save(&self, ledger: &Ledger, path: String, content: &[u8]) -> ZhangResult<()> {
+        let before = self.inner.get(path.clone()).ok();
+        self.inner.save(ledger, path.clone(), content)?;
+        self.history.changed(HistoryFile::new(path, before, Some(content)));
+        Ok(())
+    }
+
+    fn append(&self, ledger: &Ledger, directives: Vec<Directive>) -> ZhangResult<()> {
+        // the files are written through `ledger.data_source`, which is this data source, and collected into the group
+        self.history.sync_group(|| self.inner.append(ledger, directives))
+    }
+
+    fn remove(&self, ledger: &Ledger, path: String) -> ZhangResult<()> {
+        let before = self.inner.get(path.clone()).ok();
+        self.inner.remove(ledger, path.clone())?;
+        self.history.changed(HistoryFile::new(path, before, None));
+        Ok(())
+    }
+
+    async fn async_load(&self, entry: String, endpoint: String) -> ZhangResult<LoadResult> {
+        self.inner.async_load(entry, endpoint).await
+    }
+
+    async fn async_get(&self, path: String) -> ZhangResult<Vec<u8>> {
+        self.inner.async_get(path).await
+    }
+
+    async fn async_append(&self, ledger: &Ledger, directives: Vec<Directive>) -> ZhangResult<()> {
+        self.history.group(self.inner.async_append(ledger, directives)).await
+    }
+
+    async fn async_save(&self, ledger: &Ledger, path: String, content: &[u8]) -> ZhangResult<()> {
+        let before = self.inner.async_get(path.clone()).await.ok();
+        self.inner.async_save(ledger, path.clone(), content).await?;
+        self.history.changed(HistoryFile::new(path, before, Some(content)));
+        Ok(())
+    }
+
+    async fn async_remove(&self, ledger: &Ledger, path: String) -> ZhangResult<()> {
+        let before = self.inner.async_get(path.clone()).await.ok();
+        self.inner.async_remove(ledger, path.clone()).await?;
+        self.history.changed(HistoryFile::new(path, before, None));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use chrono::NaiveDate;
+    use tempfile::tempdir;
+    use zhang_ast::{Account, Date, Directive, Meta, Open};
+    use zhang_core::data_source::LocalFileSystemDataSource;
+    use zhang_core::data_type::text::ZhangDataType;
+    use zhang_core::ledger::Ledger;
+
+    use crate::history::{History, HistoryDataSource, HistoryFile};
+
+    fn load(dir: &Path, history: Arc<History>) -> Ledger {
+        std::fs::write(dir.join("main.zhang"), "option \"title\" \"history\"\n").unwrap();
+        let data_source = HistoryDataSource::new(Arc::new(LocalFileSystemDataSource::new(ZhangDataType {})), history);
+        Ledger::load_with_data_source(dir.to_path_buf(), "main.zhang".to_string(), Arc::new(data_source)).unwrap()
+    }
+
+    #[test]
+    fn should_record_save_and_append_made_through_data_source() {
+        let dir = tempdir().unwrap();
+        let history = Arc::new(History::new(10));
+        let ledger = load(dir.path(), history.clone());
+        let entry = ledger.entry.0.clone();
+        let main_file = entry.join("main.zhang").to_string_lossy().to_string();
+
+        ledger.data_source.save(&ledger, main_file.clone(), b"option \"title\" \"changed\"\n").unwrap();
+        let entries = history.entries();
+        assert_eq!(1, entries.len());
+        assert_eq!(main_file, entries[0].files[0].path);
+        assert_eq!(Some("option \"title\" \"history\"\n".to_string()), entries[0].files[0].before);
+        assert_eq!(Some("option \"title\" \"changed\"\n".to_string()), entries[0].files[0].after);
+
+        ledger
+            .data_source
+            .append(
+                &ledger,
+                vec![Directive::Open(Open {
+                    date: Date::Date(NaiveDate::from_ymd_opt(2023, 5, 1).unwrap()),
+                    account: Account::from_str("Assets:Bank").unwrap(),
+                    commodities: vec![],
+                    meta: Meta::default(),
+                })],
+            )
+            .unwrap();
+        let entries = history.entries();
+        assert_eq!(2, entries.len());
+        // the main file including the target file and the target file are recorded as one change
+        let files = &entries[0].files;
+        assert_eq!(2, files.len());
+        assert_eq!(main_file, files[0].path);
+        assert!(files[0].after.as_ref().unwrap().contains("include \"data/2023/5.zhang\""));
+        assert_eq!(entry.join("data/2023/5.zhang").to_string_lossy(), files[1].path);
+        assert_eq!(None, files[1].before);
+        assert!(files[1].after.as_ref().unwrap().contains("2023-05-01 open Assets:Bank"));
+    }
+
+    #[test]
+    fn should_tell_existing_empty_file_from_created_file() {
+        let dir = tempdir().unwrap();
+        let history = Arc::new(History::new(10));
+        let ledger = load(dir.path(), history.clone());
+        let empty_file = ledger.entry.0.join("empty.zhang").to_string_lossy().to_string();
+        let created_file = ledger.entry.0.join("created.zhang").to_string_lossy().to_string();
+        std::fs::write(&empty_file, "").unwrap();
+
+        ledger.data_source.save(&ledger, empty_file, b"; comment\n").unwrap();
+        ledger.data_source.save(&ledger, created_file, b"; comment\n").unwrap();
+
+        let entries = history.entries();
+        assert_eq!(None, entries[0].files[0].before);
+        assert_eq!(Some(String::new()), entries[1].files[0].before);
+    }
+
+    #[test]
+    fn should_drop_oldest_entry_once_capacity_is_reached() {
+        let history = History::new(2);
+        history.changed(HistoryFile::new("a.zhang".to_string(), None, Some(b"1")));
+        history.changed(HistoryFile::new("b.zhang".to_string(), None, Some(b"2")));
+        history.changed(HistoryFile::new("c.zhang".to_string(), None, Some(b"3")));
+        // a change without any difference is not recorded
+        history.changed(HistoryFile::new("c.zhang".to_string(), Some(b"3".to_vec()), Some(b"3")));
+
+        let entries = history.entries();
+        assert_eq!(
+            vec!["c.zhang", "b.zhang"],
+            entries.iter().map(|it| it.files[0].path.as_str()).collect::<Vec<_>>()
+        );
+        assert!(history.entry(1).is_none());
+        assert_eq!(Some("2".to_string()), history.entry(2).unwrap().files[0].after);
+    }
+}
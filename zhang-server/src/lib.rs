@@ -19,25 +19,31 @@ use routes::statistics::*;
 use routes::transaction::*;
 use self_update::version::bump_is_greater;
 use serde::Serialize;
-use state::{SharedBroadcaster, SharedLedger, SharedReloadSender};
+use state::{SharedBroadcaster, SharedGitRepository, SharedHistory, SharedLedger, SharedReloadSender, SharedRules, SharedSqlExecutor};
 use tokio::sync::mpsc::error::TryRecvError;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::{mpsc, RwLock};
 use tower_http::cors::CorsLayer;
 use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::validate_request::ValidateRequestHeaderLayer;
+use zhang_core::cache::write_cache;
 use zhang_core::data_source::DataSource;
+use zhang_core::importer::rule::{CategorizeRule, Categorizer};
 use zhang_core::ledger::Ledger;
 use zhang_core::utils::has_path_visited;
 use zhang_core::ZhangResult;
 
 use crate::broadcast::{BroadcastEvent, Broadcaster};
 use crate::error::ServerError;
+use crate::git::{GitDataSource, GitRepository};
+use crate::history::{History, HistoryDataSource, HISTORY_CAPACITY};
 use crate::response::ResponseWrapper;
 use crate::state::AppState;
 
 pub mod broadcast;
 pub mod error;
+pub mod git;
+pub mod history;
 pub mod request;
 pub mod response;
 pub mod routes;
@@ -45,17 +51,26 @@ pub mod state;
 pub mod tasks;
 pub mod util;
 
+#[cfg(test)]
+mod test_util;
+
 pub type LedgerState = Arc<RwLock<Ledger>>;
 
 pub type ServerResult<T> = Result<T, ServerError>;
 
 pub type ApiResult<T> = ServerResult<ResponseWrapper<T>>;
 
+/// how long the ledger cache waits for following reloads before it is written
+const CACHE_WRITE_DELAY: Duration = Duration::from_secs(10);
+
 pub struct ServerApp {
     opts: ServeConfig,
     ledger: Arc<RwLock<Ledger>>,
     broadcaster: Arc<Broadcaster>,
     reload_sender: Arc<ReloadSender>,
+    sql_executor: SharedSqlExecutor,
+    history: SharedHistory,
+    rules: SharedRules,
 }
 
 impl GotchaApp for ServerApp {
@@ -95,7 +110,9 @@ impl GotchaApp for ServerApp {
             .get("/api/for-new-transaction", get_info_for_new_transactions)
             .get("/api/journals", get_journals)
             .post("/api/transactions", create_new_transaction)
+            .post("/api/transactions/duplicates", get_duplicate_transactions)
             .put("/api/transactions/:transaction_id", update_single_transaction)
+            .delete("/api/transactions/:transaction_id", delete_single_transaction)
             .post("/api/transactions/:transaction_id/documents", upload_transaction_document)
             .get("/api/accounts", get_account_list)
             .get("/api/accounts/:account_name", get_account_info)
@@ -105,6 +122,7 @@ impl GotchaApp for ServerApp {
             .get("/api/accounts/:account_name/balances", get_account_balance_data)
             .post("/api/accounts/:account_name/balances", create_account_balance)
             .post("/api/accounts/batch-balances", create_batch_account_balances)
+            .post("/api/accounts/:account_name/statements", routes::import::import_ofx_statement)
             .get("/api/documents", get_documents)
             .get("/api/documents/:file_path", download_document)
             .get("/api/commodities", get_all_commodities)
@@ -112,11 +130,22 @@ impl GotchaApp for ServerApp {
             .get("/api/statistic/summary", get_statistic_summary)
             .get("/api/statistic/graph", get_statistic_graph)
             .get("/api/statistic/:account_type", get_statistic_rank_detail_by_account_type)
+            .get("/api/reports/balance-sheet", routes::report::get_balance_sheet)
+            .get("/api/reports/income-statement", routes::report::get_income_statement)
+            .get("/api/reports/trial-balance", routes::report::get_trial_balance)
+            .post("/api/reports/close-books", routes::report::close_books)
             .get("/api/budgets", get_budget_list)
             .get("/api/budgets/:budget_name", get_budget_info)
             .get("/api/budgets/:budget_name/interval/:year/:month", get_budget_interval_detail)
             .get("/api/plugins", routes::plugin::plugin_list)
             .post("/api/sql", routes::sql::execute_sql)
+            .get("/api/queries", routes::sql::get_query_list)
+            .get("/api/queries/:query_name/result", routes::sql::execute_saved_query)
+            .get("/api/history", routes::history::get_history_list)
+            .get("/api/history/:history_id", routes::history::get_history_detail)
+            .post("/api/history/:history_id/revert", routes::history::revert_history)
+            .get("/api/git/commits", routes::git::get_commit_list)
+            .get("/api/git/commits/:commit_id", routes::git::get_commit_detail)
             .layer(CorsLayer::permissive())
             .layer(DefaultBodyLimit::disable())
             .layer(RequestBodyLimitLayer::new(250 * 1024 * 1024 /* 250mb */));
@@ -142,6 +171,10 @@ impl GotchaApp for ServerApp {
             ledger: SharedLedger(self.ledger.clone()),
             broadcaster: SharedBroadcaster(self.broadcaster.clone()),
             reload_sender: SharedReloadSender(self.reload_sender.clone()),
+            sql_executor: self.sql_executor.clone(),
+            history: self.history.clone(),
+            git_repository: SharedGitRepository(self.opts.git_repository.clone()),
+            rules: self.rules.clone(),
         })
     }
 }
@@ -172,12 +205,30 @@ pub struct ServeConfig {
     pub data_source: Arc<dyn DataSource>,
     pub auth_credential: Option<String>,
     pub is_local_fs: bool,
+    /// the git working tree where every change is committed into
+    pub git_repository: Option<Arc<GitRepository>>,
+    /// the cache file of processed ledger, which is updated in background once reloads settle down
+    pub database: Option<PathBuf>,
+    /// categorize rules applied to transactions created or imported through web
+    pub rules: Vec<CategorizeRule>,
+}
+
+/// what the reload listener is notified of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloadEvent {
+    /// files are changed without reloading the ledger, the listener reloads it
+    Changed,
+    /// the ledger is reloaded by the request changing it, the listener only broadcasts the reload and updates the cache
+    Reloaded,
 }
 
-pub struct ReloadSender(pub Sender<i32>);
+/// how many reload events can be queued, events queued together are handled by one reload
+const RELOAD_EVENT_CAPACITY: usize = 16;
+
+pub struct ReloadSender(pub Sender<ReloadEvent>, pub SharedSqlExecutor);
 
 impl Deref for ReloadSender {
-    type Target = Sender<i32>;
+    type Target = Sender<ReloadEvent>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -186,20 +237,52 @@ impl Deref for ReloadSender {
 
 impl ReloadSender {
     fn reload(&self) {
-        self.0.try_send(1).ok();
+        self.0.try_send(ReloadEvent::Changed).ok();
+    }
+
+    /// reload the ledger whose write lock is held by caller, so that the requests waiting for the lock see the changes.
+    /// the sql executor built from the stale ledger is dropped before the lock is released as well.
+    ///
+    /// routes mutating the ledger take the write lock before checking the current state (file hashes, duplicates,
+    /// closed books, ...) and keep it until this reload, so that concurrent requests never act on a stale state.
+    async fn reload_locked(&self, ledger: &mut Ledger) -> ZhangResult<()> {
+        let result = ledger.async_reload().await;
+        self.1.invalidate().await;
+        result?;
+        self.0.try_send(ReloadEvent::Reloaded).ok();
+        Ok(())
     }
 }
 
 pub async fn serve(opts: ServeConfig) -> ZhangResult<()> {
     info!("version: {}, build date: {}", env!("ZHANG_BUILD_VERSION"), env!("ZHANG_BUILD_DATE"));
-    let ledger = Ledger::async_load(opts.path.clone(), opts.endpoint.clone(), opts.data_source.clone()).await?;
+    // reject invalid rules on startup instead of on every request
+    let rules = SharedRules(Arc::new(Categorizer::new(opts.rules.clone())?));
+    let history = Arc::new(History::new(HISTORY_CAPACITY));
+    let data_source = match opts.git_repository.clone() {
+        Some(repository) => Arc::new(GitDataSource::new(opts.data_source.clone(), repository)),
+        None => opts.data_source.clone(),
+    };
+    let data_source = Arc::new(HistoryDataSource::new(data_source, history.clone()));
+    let ledger = match opts.database.as_deref() {
+        Some(database) => Ledger::async_load_with_cache(opts.path.clone(), opts.endpoint.clone(), data_source, database).await?,
+        None => Ledger::async_load(opts.path.clone(), opts.endpoint.clone(), data_source).await?,
+    };
     let ledger_data = Arc::new(RwLock::new(ledger));
     let broadcaster = Broadcaster::create();
-    let (tx, rx) = mpsc::channel::<i32>(1);
-    let reload_sender = Arc::new(ReloadSender(tx));
+    let (tx, rx) = mpsc::channel::<ReloadEvent>(RELOAD_EVENT_CAPACITY);
+    let sql_executor = SharedSqlExecutor::default();
+    let reload_sender = Arc::new(ReloadSender(tx, sql_executor.clone()));
+
+    let cache_sender = opts.database.clone().map(|database| {
+        info!("start ledger cache writer");
+        let (cache_tx, cache_rx) = mpsc::channel::<()>(1);
+        start_cache_writer(ledger_data.clone(), database, cache_rx);
+        cache_tx
+    });
 
     info!("start reload listener");
-    start_reload_listener(ledger_data.clone(), broadcaster.clone(), rx);
+    start_reload_listener(ledger_data.clone(), broadcaster.clone(), sql_executor.clone(), cache_sender, rx);
 
     if opts.is_local_fs {
         info!("start fs event listener");
@@ -212,7 +295,16 @@ pub async fn serve(opts: ServeConfig) -> ZhangResult<()> {
     if !opts.no_report {
         start_report_tasker();
     }
-    start_server(opts, ledger_data, broadcaster.clone(), reload_sender.clone()).await
+    start_server(
+        opts,
+        ledger_data,
+        broadcaster.clone(),
+        reload_sender.clone(),
+        sql_executor,
+        SharedHistory(history),
+        rules,
+    )
+    .await
 }
 
 fn start_report_tasker() {
@@ -287,51 +379,100 @@ fn start_fs_event_lisenter(cloned_ledger: Arc<RwLock<Ledger>>, reload_sender_for
 
             if is_visited_file_updated {
                 debug!("gotcha event, sending reload event...");
-                reload_sender_for_fs.0.try_send(1).ok();
+                reload_sender_for_fs.reload();
             }
         }
     });
 }
 
-fn start_reload_listener(ledger_for_reload: Arc<RwLock<Ledger>>, cloned_broadcaster: Arc<Broadcaster>, mut rx: Receiver<i32>) {
+fn start_reload_listener(
+    ledger_for_reload: Arc<RwLock<Ledger>>, cloned_broadcaster: Arc<Broadcaster>, sql_executor: SharedSqlExecutor, cache_sender: Option<Sender<()>>,
+    mut rx: Receiver<ReloadEvent>,
+) {
     tokio::spawn(async move {
-        while rx.recv().await.is_some() {
-            info!("start reloading...");
-            let start_time = Instant::now();
-            let mut guard = ledger_for_reload.write().await;
-            match guard.async_reload().await {
-                Ok(_) => {
-                    let duration = start_time.elapsed();
-                    info!("ledger is reloaded successfully in {:?}", duration);
-                    // todo: add reload duration to reload event
-                    cloned_broadcaster.broadcast(BroadcastEvent::Reload).await;
+        while let Some(event) = rx.recv().await {
+            let mut changed = event == ReloadEvent::Changed;
+            while let Ok(event) = rx.try_recv() {
+                changed |= event == ReloadEvent::Changed;
+            }
+
+            if changed {
+                info!("start reloading...");
+                let start_time = Instant::now();
+                let mut guard = ledger_for_reload.write().await;
+                let result = guard.async_reload().await;
+                // drop the executor while holding the write lock, so it cannot be rebuilt from the stale ledger
+                sql_executor.invalidate().await;
+                drop(guard);
+                match result {
+                    Ok(_) => info!("ledger is reloaded successfully in {:?}", start_time.elapsed()),
+                    Err(err) => {
+                        error!("error on reload: {}", err);
+                        // todo: broadcast the error
+                        continue;
+                    }
                 }
-                Err(err) => {
-                    error!("error on reload: {}", err);
-                    // todo: broadcast the error
+            }
+
+            // todo: add reload duration to reload event
+            cloned_broadcaster.broadcast(BroadcastEvent::Reload).await;
+            if let Some(cache_sender) = cache_sender.as_ref() {
+                cache_sender.try_send(()).ok();
+            }
+        }
+    });
+}
+
+/// write the ledger cache once no reload happens within [CACHE_WRITE_DELAY], instead of on every reload.
+/// the ledger is only read while it is serialized, and the file is written after the lock is released.
+fn start_cache_writer(ledger: Arc<RwLock<Ledger>>, cache: PathBuf, mut rx: Receiver<()>) {
+    tokio::spawn(async move {
+        while rx.recv().await.is_some() {
+            // keep waiting as long as reloads keep coming
+            while let Ok(Some(_)) = tokio::time::timeout(CACHE_WRITE_DELAY, rx.recv()).await {}
+            let guard = ledger.clone().read_owned().await;
+            let cache = cache.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let content = guard.cache_content();
+                drop(guard);
+                match content? {
+                    Some(content) => write_cache(&cache, content),
+                    None => Ok(()),
                 }
+            })
+            .await;
+            match result {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!("fail to write ledger cache: {}", e),
+                Err(e) => error!("fail to write ledger cache: {}", e),
             }
-            drop(guard);
         }
     });
 }
 
 pub async fn start_server(
-    opts: ServeConfig, ledger_data: Arc<RwLock<Ledger>>, broadcaster: Arc<Broadcaster>, reload_sender: Arc<ReloadSender>,
+    opts: ServeConfig, ledger_data: Arc<RwLock<Ledger>>, broadcaster: Arc<Broadcaster>, reload_sender: Arc<ReloadSender>, sql_executor: SharedSqlExecutor,
+    history: SharedHistory, rules: SharedRules,
 ) -> ZhangResult<()> {
     info!("zhang is listening on http://{}:{}/", opts.addr, opts.port);
 
-    let app = create_server_app(opts, ledger_data, broadcaster, reload_sender);
+    let app = create_server_app(opts, ledger_data, broadcaster, reload_sender, sql_executor, history, rules);
     app.run().await.unwrap();
     Ok(())
 }
 
-pub fn create_server_app(opts: ServeConfig, ledger: Arc<RwLock<Ledger>>, broadcaster: Arc<Broadcaster>, reload_sender: Arc<ReloadSender>) -> ServerApp {
+pub fn create_server_app(
+    opts: ServeConfig, ledger: Arc<RwLock<Ledger>>, broadcaster: Arc<Broadcaster>, reload_sender: Arc<ReloadSender>, sql_executor: SharedSqlExecutor,
+    history: SharedHistory, rules: SharedRules,
+) -> ServerApp {
     ServerApp {
         opts,
         ledger,
         broadcaster,
         reload_sender,
+        sql_executor,
+        history,
+        rules,
     }
 }
 
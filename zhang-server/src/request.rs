@@ -1,11 +1,12 @@
 use std::cmp::max;
 use std::collections::HashSet;
 
-use chrono::{DateTime, Datelike, Local, Utc};
+use chrono::{DateTime, Datelike, Local, NaiveDate, Utc};
 use gotcha::Schematic;
 use serde::Deserialize;
 use zhang_ast::amount::Amount;
 use zhang_ast::Flag;
+use zhang_core::reports::ReportInterval;
 
 #[derive(Schematic, Deserialize)]
 #[serde(tag = "type")]
@@ -24,6 +25,14 @@ pub enum AccountBalanceRequest {
 #[derive(Schematic, Deserialize)]
 pub struct FileUpdateRequest {
     pub content: String,
+    /// the hash of the content being edited, given by `get_file_content`
+    pub hash: String,
+}
+
+#[derive(Schematic, Deserialize)]
+pub struct TransactionMutationRequest {
+    /// the hash of the transaction being mutated, given by journals
+    pub hash: String,
 }
 
 #[derive(Schematic, Deserialize)]
@@ -45,6 +54,35 @@ pub struct StatisticGraphRequest {
     pub interval: StatisticInterval,
 }
 
+#[derive(Schematic, Deserialize)]
+pub struct BalanceSheetRequest {
+    /// the date of balance sheet, default to today
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(Schematic, Deserialize)]
+pub struct TrialBalanceRequest {
+    /// the date of trial balance, default to today
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(Schematic, Deserialize)]
+pub struct CloseBooksRequest {
+    /// the closing date, usually the last day of fiscal year
+    pub date: NaiveDate,
+    /// append the generated directives to ledger, otherwise they are only previewed
+    #[serde(default)]
+    pub write: bool,
+}
+
+#[derive(Schematic, Deserialize)]
+pub struct IncomeStatementRequest {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// the interval of period columns, default to month
+    pub interval: Option<ReportInterval>,
+}
+
 #[derive(Schematic, Deserialize)]
 pub struct ReportRequest {
     pub from: DateTime<Utc>,
@@ -82,6 +120,9 @@ pub struct CreateTransactionRequest {
     pub metas: Vec<MetaRequest>,
     pub tags: Vec<String>,
     pub links: Vec<String>,
+    /// create the transaction even if some existing transactions look like the same one
+    #[serde(default)]
+    pub allow_duplicate: bool,
 }
 
 #[derive(Deserialize)]
@@ -16,6 +16,7 @@ use zhang_core::plugin::PluginType;
 use zhang_core::store::{BudgetEvent, BudgetEventType, PostingDomain};
 
 use crate::error::ServerError;
+use crate::git::GitCommit;
 use crate::ServerResult;
 
 pub struct Created;
@@ -155,6 +156,8 @@ pub struct JournalTransactionItemEntity {
     pub is_balanced: bool,
     pub postings: Vec<JournalTransactionPostingEntity>,
     pub metas: Vec<MetaEntity>,
+    /// the hash of transaction source, which must be given back when mutating the transaction
+    pub hash: String,
 }
 #[derive(Serialize, Schematic)]
 pub struct JournalTransactionPostingEntity {
@@ -194,6 +197,8 @@ pub struct JournalBalanceItemEntity {
 pub struct InfoForNewTransaction {
     pub payee: Vec<String>,
     pub account_name: Vec<String>,
+    /// the accounts used with each payee, the most frequent comes first
+    pub payee_accounts: HashMap<String, Vec<String>>,
 }
 
 #[derive(Serialize, Schematic)]
@@ -234,10 +239,25 @@ pub struct CommodityDetailEntity {
     pub prices: Vec<CommodityPriceEntity>,
 }
 
+#[derive(Serialize, Schematic)]
+pub struct HistoryItemEntity {
+    pub id: u64,
+    pub datetime: DateTime<Utc>,
+    pub paths: Vec<String>,
+}
+
+#[derive(Serialize, Schematic)]
+pub struct GitCommitDetailEntity {
+    pub commit: GitCommit,
+    pub diff: String,
+}
+
 #[derive(Serialize, Schematic)]
 pub struct FileDetailEntity {
     pub path: String,
     pub content: String,
+    /// the hash of content, which must be given back when updating the file
+    pub hash: String,
 }
 
 #[derive(Serialize, Schematic)]
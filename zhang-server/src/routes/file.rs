@@ -2,7 +2,10 @@ use axum::extract::State;
 use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
 use base64::Engine as _;
 use gotcha::api;
+use zhang_core::utils::content_hash;
+use zhang_core::ZhangError;
 
+use crate::error::ServerError;
 use crate::request::FileUpdateRequest;
 use crate::response::{Created, FileDetailEntity, ResponseWrapper};
 use crate::state::{SharedLedger, SharedReloadSender};
@@ -30,8 +33,9 @@ pub async fn get_file_content(ledger: State<SharedLedger>, path: axum::extract::
 
     let content = ledger.data_source.async_get(filename.to_owned()).await?;
     let content = String::from_utf8(content).unwrap();
+    let hash = content_hash(&content);
 
-    ResponseWrapper::json(FileDetailEntity { path: filename, content })
+    ResponseWrapper::json(FileDetailEntity { path: filename, content, hash })
 }
 
 #[api(group = "file")]
@@ -40,12 +44,115 @@ pub async fn update_file_content(
     axum::extract::Json(payload): axum::extract::Json<FileUpdateRequest>,
 ) -> ServerResult<Created> {
     let encoded_file_path = path.0 .0;
-    let filename = String::from_utf8(BASE64_STANDARD.decode(encoded_file_path).unwrap()).unwrap();
-    let ledger = ledger.read().await;
+    let decoded_file_path = BASE64_STANDARD.decode(encoded_file_path).map_err(|_| ServerError::BadRequest)?;
+    let filename = String::from_utf8(decoded_file_path).map_err(|_| ServerError::BadRequest)?;
+    let mut ledger = ledger.write().await;
+
+    let current_content = String::from_utf8(ledger.data_source.async_get(filename.clone()).await?).map_err(ZhangError::from)?;
+    if content_hash(&current_content) != payload.hash {
+        return Err(ServerError::Conflict(current_content));
+    }
 
     // todo(refact) check if the syntax valid
     // if parse_zhang(&payload.content, None).is_ok() {
     ledger.data_source.async_save(&ledger, filename, payload.content.as_bytes()).await?;
-    reload_sender.reload();
+    reload_sender.reload_locked(&mut ledger).await?;
     Ok(Created)
 }
+
+#[cfg(test)]
+mod test {
+
+    use axum::extract::{Json, Path, State};
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+    use base64::Engine as _;
+    use tempfile::tempdir;
+    use zhang_core::utils::content_hash;
+
+    use crate::error::ServerError;
+    use crate::request::FileUpdateRequest;
+    use crate::routes::file::update_file_content;
+    use crate::test_util::load;
+
+    #[tokio::test]
+    async fn should_reload_ledger_before_update_returns() {
+        let dir = tempdir().unwrap();
+        let main_file = dir.path().join("main.zhang");
+        let (ledger, reload_sender) = load(dir.path(), "option \"title\" \"before\"\n").await;
+        let encoded_path = BASE64_STANDARD.encode(main_file.to_string_lossy().as_bytes());
+
+        update_file_content(
+            State(ledger.clone()),
+            State(reload_sender),
+            Path((encoded_path,)),
+            Json(FileUpdateRequest {
+                content: "option \"title\" \"after\"\n".to_string(),
+                hash: content_hash("option \"title\" \"before\"\n"),
+            }),
+        )
+        .await
+        .unwrap();
+
+        // the reload listener has not handled the update yet
+        let title = ledger.read().await.operations().option::<String>("title").unwrap();
+        assert_eq!(Some("after".to_string()), title);
+    }
+
+    #[tokio::test]
+    async fn should_reject_malformed_path() {
+        let dir = tempdir().unwrap();
+        let (ledger, reload_sender) = load(dir.path(), "option \"title\" \"before\"\n").await;
+        let request = || {
+            Json(FileUpdateRequest {
+                content: "".to_string(),
+                hash: content_hash("option \"title\" \"before\"\n"),
+            })
+        };
+
+        let result = update_file_content(
+            State(ledger.clone()),
+            State(reload_sender.clone()),
+            Path(("not base64!".to_string(),)),
+            request(),
+        )
+        .await;
+        assert!(matches!(result, Err(ServerError::BadRequest)));
+
+        let not_utf8 = BASE64_STANDARD.encode([0xff, 0xfe]);
+        let result = update_file_content(State(ledger), State(reload_sender), Path((not_utf8,)), request()).await;
+        assert!(matches!(result, Err(ServerError::BadRequest)));
+    }
+
+    #[tokio::test]
+    async fn should_reject_update_with_stale_hash() {
+        let dir = tempdir().unwrap();
+        let main_file = dir.path().join("main.zhang");
+        let (ledger, reload_sender) = load(dir.path(), "option \"title\" \"before\"\n").await;
+        let encoded_path = BASE64_STANDARD.encode(main_file.to_string_lossy().as_bytes());
+        let stale_hash = content_hash("option \"title\" \"before\"\n");
+        std::fs::write(&main_file, "option \"title\" \"edited elsewhere\"\n").unwrap();
+
+        let result = update_file_content(
+            State(ledger),
+            State(reload_sender),
+            Path((encoded_path,)),
+            Json(FileUpdateRequest {
+                content: "option \"title\" \"after\"\n".to_string(),
+                hash: stale_hash,
+            }),
+        )
+        .await;
+
+        let Err(error @ ServerError::Conflict(_)) = result else {
+            panic!("update should be rejected");
+        };
+        let response = error.into_response();
+        assert_eq!(StatusCode::CONFLICT, response.status());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!("option \"title\" \"edited elsewhere\"\n", payload["content"]);
+        assert_eq!("option \"title\" \"edited elsewhere\"\n", std::fs::read_to_string(&main_file).unwrap());
+    }
+}
@@ -0,0 +1,36 @@
+use axum::extract::{Path, State};
+use gotcha::api;
+
+use crate::error::ServerError;
+use crate::git::{is_valid_commit_id, GitCommit};
+use crate::response::{GitCommitDetailEntity, ResponseWrapper};
+use crate::state::SharedGitRepository;
+use crate::{ApiResult, ServerResult};
+
+/// how many commits are listed
+const COMMIT_LIST_LIMIT: usize = 50;
+
+/// the latest commits of the ledger, only available if the git data source is used
+#[api(group = "git")]
+pub async fn get_commit_list(repository: State<SharedGitRepository>) -> ApiResult<Vec<GitCommit>> {
+    let repository = repository.as_ref().cloned().ok_or(ServerError::NotFound)?;
+    // git runs as a child process, which blocks the thread until it exits
+    let commits = tokio::task::spawn_blocking(move || repository.commits(COMMIT_LIST_LIMIT)).await??;
+    ResponseWrapper::json(commits)
+}
+
+#[api(group = "git")]
+pub async fn get_commit_detail(repository: State<SharedGitRepository>, path: Path<(String,)>) -> ApiResult<GitCommitDetailEntity> {
+    let repository = repository.as_ref().cloned().ok_or(ServerError::NotFound)?;
+    let commit_id = path.0 .0;
+    if !is_valid_commit_id(&commit_id) {
+        return Err(ServerError::BadRequest);
+    }
+    let detail = tokio::task::spawn_blocking(move || -> ServerResult<GitCommitDetailEntity> {
+        let commit = repository.commit_info(&commit_id).map_err(|_| ServerError::NotFound)?;
+        let diff = repository.diff(&commit_id)?;
+        Ok(GitCommitDetailEntity { commit, diff })
+    })
+    .await??;
+    ResponseWrapper::json(detail)
+}
@@ -0,0 +1,193 @@
+use axum::extract::{Path, State};
+use gotcha::api;
+use log::info;
+use zhang_core::ZhangResult;
+
+use crate::error::ServerError;
+use crate::history::HistoryEntry;
+use crate::response::{HistoryItemEntity, ResponseWrapper};
+use crate::state::{SharedHistory, SharedLedger, SharedReloadSender};
+use crate::ApiResult;
+
+/// the latest changes of files made through the server since it started, the latest comes first.
+/// history is kept in memory only, so changes made before a restart are not listed and cannot be reverted.
+#[api(group = "history")]
+pub async fn get_history_list(history: State<SharedHistory>) -> ApiResult<Vec<HistoryItemEntity>> {
+    let ret = history
+        .entries()
+        .into_iter()
+        .map(|it| HistoryItemEntity {
+            id: it.id,
+            datetime: it.datetime,
+            paths: it.files.into_iter().map(|file| file.path).collect(),
+        })
+        .collect();
+    ResponseWrapper::json(ret)
+}
+
+#[api(group = "history")]
+pub async fn get_history_detail(history: State<SharedHistory>, path: Path<(u64,)>) -> ApiResult<HistoryEntry> {
+    let entry = history.entry(path.0 .0).ok_or(ServerError::NotFound)?;
+    ResponseWrapper::json(entry)
+}
+
+/// restore all the files of the change to the content before it, and remove the files created by it.
+/// it is rejected if any of the files has been changed since then, and the revert itself is recorded as a new change.
+#[api(group = "history")]
+pub async fn revert_history(
+    ledger: State<SharedLedger>, history: State<SharedHistory>, reload_sender: State<SharedReloadSender>, path: Path<(u64,)>,
+) -> ApiResult<()> {
+    let entry = history.entry(path.0 .0).ok_or(ServerError::NotFound)?;
+    let mut ledger = ledger.write().await;
+
+    for file in &entry.files {
+        // a file failed to read is taken as a missing one, the same as it is recorded
+        let current_content = ledger
+            .data_source
+            .async_get(file.path.clone())
+            .await
+            .ok()
+            .map(|it| String::from_utf8_lossy(&it).to_string());
+        if current_content != file.after {
+            return Err(ServerError::Conflict(current_content.unwrap_or_default()));
+        }
+    }
+    info!(
+        "reverting change {} of files {:?}",
+        entry.id,
+        entry.files.iter().map(|it| &it.path).collect::<Vec<_>>()
+    );
+    history
+        .group(async {
+            // files are restored in the reverse order they were written
+            for file in entry.files.into_iter().rev() {
+                match file.before {
+                    Some(before) => ledger.data_source.async_save(&ledger, file.path, before.as_bytes()).await?,
+                    None => ledger.data_source.async_remove(&ledger, file.path).await?,
+                }
+            }
+            ZhangResult::Ok(())
+        })
+        .await?;
+    reload_sender.reload_locked(&mut ledger).await?;
+    ResponseWrapper::json(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path as StdPath;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use axum::extract::{Path, State};
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use chrono::NaiveDate;
+    use tempfile::tempdir;
+    use zhang_ast::{Account, Date, Directive, Meta, Open};
+    use zhang_core::data_source::LocalFileSystemDataSource;
+    use zhang_core::data_type::text::ZhangDataType;
+
+    use crate::error::ServerError;
+    use crate::history::{History, HistoryDataSource};
+    use crate::routes::history::revert_history;
+    use crate::state::{SharedHistory, SharedLedger, SharedReloadSender};
+    use crate::test_util::load_with;
+
+    async fn load(dir: &StdPath) -> (SharedLedger, SharedHistory, SharedReloadSender) {
+        let history = Arc::new(History::new(10));
+        let data_source = HistoryDataSource::new(Arc::new(LocalFileSystemDataSource::new(ZhangDataType {})), history.clone());
+        let (ledger, reload_sender) = load_with(dir, data_source, "option \"title\" \"before\"\n").await;
+        (ledger, SharedHistory(history), reload_sender)
+    }
+
+    async fn change(ledger: &SharedLedger, content: &str) -> String {
+        let ledger = ledger.read().await;
+        let main_file = ledger.entry.0.join("main.zhang").to_string_lossy().to_string();
+        ledger.data_source.async_save(&ledger, main_file.clone(), content.as_bytes()).await.unwrap();
+        main_file
+    }
+
+    #[tokio::test]
+    async fn should_restore_content_before_change() {
+        let dir = tempdir().unwrap();
+        let (ledger, history, reload_sender) = load(dir.path()).await;
+        let main_file = change(&ledger, "option \"title\" \"after\"\n").await;
+        let id = history.entries()[0].id;
+
+        revert_history(State(ledger), State(history.clone()), State(reload_sender), Path((id,)))
+            .await
+            .unwrap();
+
+        assert_eq!("option \"title\" \"before\"\n", std::fs::read_to_string(&main_file).unwrap());
+        // the revert is recorded as a new change
+        let latest = &history.entries()[0];
+        assert_ne!(id, latest.id);
+        assert_eq!(Some("option \"title\" \"after\"\n".to_string()), latest.files[0].before);
+    }
+
+    #[tokio::test]
+    async fn should_revert_files_of_append_together_and_remove_created_file() {
+        let dir = tempdir().unwrap();
+        let (ledger, history, reload_sender) = load(dir.path()).await;
+        let (main_file, data_file) = {
+            let ledger = ledger.read().await;
+            let directive = Directive::Open(Open {
+                date: Date::Date(NaiveDate::from_ymd_opt(2023, 5, 1).unwrap()),
+                account: Account::from_str("Assets:Bank").unwrap(),
+                commodities: vec![],
+                meta: Meta::default(),
+            });
+            ledger.data_source.async_append(&ledger, vec![directive]).await.unwrap();
+            (ledger.entry.0.join("main.zhang"), ledger.entry.0.join("data/2023/5.zhang"))
+        };
+        assert!(data_file.exists());
+        let entries = history.entries();
+        assert_eq!(1, entries.len());
+
+        revert_history(State(ledger), State(history.clone()), State(reload_sender), Path((entries[0].id,)))
+            .await
+            .unwrap();
+
+        assert_eq!("option \"title\" \"before\"\n", std::fs::read_to_string(&main_file).unwrap());
+        assert!(!data_file.exists());
+        let latest = &history.entries()[0];
+        assert_eq!(2, latest.files.len());
+        assert_eq!(None, latest.files[0].after);
+    }
+
+    #[tokio::test]
+    async fn should_keep_existing_empty_file_when_reverting() {
+        let dir = tempdir().unwrap();
+        let (ledger, history, reload_sender) = load(dir.path()).await;
+        let empty_file = dir.path().join("empty.zhang");
+        std::fs::write(&empty_file, "").unwrap();
+        {
+            let ledger = ledger.read().await;
+            let path = empty_file.to_string_lossy().to_string();
+            ledger.data_source.async_save(&ledger, path, b"; comment\n").await.unwrap();
+        }
+        let id = history.entries()[0].id;
+
+        revert_history(State(ledger), State(history), State(reload_sender), Path((id,))).await.unwrap();
+
+        assert_eq!("", std::fs::read_to_string(&empty_file).unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_reject_revert_if_file_is_changed_after_the_change() {
+        let dir = tempdir().unwrap();
+        let (ledger, history, reload_sender) = load(dir.path()).await;
+        let main_file = change(&ledger, "option \"title\" \"after\"\n").await;
+        let id = history.entries()[0].id;
+        std::fs::write(&main_file, "option \"title\" \"edited elsewhere\"\n").unwrap();
+
+        let result = revert_history(State(ledger), State(history), State(reload_sender), Path((id,))).await;
+
+        let Err(error @ ServerError::Conflict(_)) = result else {
+            panic!("revert should be rejected");
+        };
+        assert_eq!(StatusCode::CONFLICT, error.into_response().status());
+        assert_eq!("option \"title\" \"edited elsewhere\"\n", std::fs::read_to_string(&main_file).unwrap());
+    }
+}
@@ -0,0 +1,156 @@
+use axum::extract::{Multipart, Path, State};
+use gotcha::api;
+use itertools::Itertools;
+use log::info;
+use zhang_ast::Directive;
+use zhang_core::importer::duplicate::{DuplicateAction, DuplicateRule};
+use zhang_core::importer::ofx::{OfxImporter, OfxMapping};
+use zhang_core::importer::rule::Categorizer;
+use zhang_core::importer::Importer;
+
+use crate::error::ServerError;
+use crate::response::ResponseWrapper;
+use crate::state::{SharedLedger, SharedReloadSender, SharedRules};
+use crate::ApiResult;
+
+/// import the OFX/QFX statement of the account, the multipart form carries the statement in `file` field,
+/// and the account on the other side of transactions in `counter_account` field, which is used when the payee has no history.
+/// transactions are categorized by the rules given on startup and the rules learned from history of the account,
+/// transactions already existing in ledger are skipped, and the imported directives are returned in text form.
+#[api(group = "import")]
+pub async fn import_ofx_statement(
+    ledger: State<SharedLedger>, reload_sender: State<SharedReloadSender>, rules: State<SharedRules>, path: Path<(String,)>, mut multipart: Multipart,
+) -> ApiResult<Vec<String>> {
+    let account_name = path.0 .0;
+    let mut statement = None;
+    let mut counter_account = None;
+    while let Some(field) = multipart.next_field().await.map_err(|_| ServerError::BadRequest)? {
+        let name = field.name().unwrap_or_default().to_owned();
+        let content = field.text().await.map_err(|_| ServerError::BadRequest)?;
+        match name.as_str() {
+            "file" => statement = Some(content),
+            "counter_account" => counter_account = Some(content),
+            _ => {}
+        }
+    }
+    let (Some(statement), Some(counter_account)) = (statement, counter_account) else {
+        return ResponseWrapper::bad_request();
+    };
+
+    let mut ledger = ledger.write().await;
+    let importer = OfxImporter::new(OfxMapping {
+        account: account_name.clone(),
+        counter_account,
+        currency: Some(ledger.options.operating_currency.clone()),
+    });
+    let directives = importer.import(&statement).map_err(|e| ServerError::InvalidStatement(e.to_string()))?;
+    // configured rules come first, and learned rules only apply to transactions matched by none of them
+    let learned = Categorizer::new(ledger.operations().learned_rules(&account_name)?)?;
+    let directives = directives
+        .into_iter()
+        .map(|directive| match directive {
+            Directive::Transaction(mut transaction) => {
+                if !rules.categorize(&mut transaction) {
+                    learned.categorize(&mut transaction);
+                }
+                Directive::Transaction(transaction)
+            }
+            others => others,
+        })
+        .collect_vec();
+    let rule = DuplicateRule {
+        external_id_key: importer.external_id_key().map(|it| it.to_owned()),
+        ..Default::default()
+    };
+    let directives = ledger.operations().handle_duplicates(directives, &rule, DuplicateAction::Skip)?;
+    info!("importing {} directives from statement of account {}", directives.len(), &account_name);
+
+    let mut imported = Vec::with_capacity(directives.len());
+    for directive in directives.iter().cloned() {
+        let content = ledger.data_source.export(directive)?;
+        imported.push(String::from_utf8_lossy(&content).to_string());
+    }
+    if !directives.is_empty() {
+        ledger.data_source.async_append(&ledger, directives).await?;
+        reload_sender.reload_locked(&mut ledger).await?;
+    }
+    ResponseWrapper::json(imported)
+}
+
+#[cfg(test)]
+mod test {
+
+    use axum::body::Body;
+    use axum::extract::{FromRequest, Multipart, Path, Request, State};
+    use axum::http::header::CONTENT_TYPE;
+    use tempfile::tempdir;
+
+    use crate::routes::import::import_ofx_statement;
+    use crate::state::SharedRules;
+    use crate::test_util::load;
+
+    const STATEMENT: &str = indoc::indoc! {r#"
+        <OFX>
+          <BANKMSGSRSV1><STMTTRNRS><STMTRS>
+            <BANKTRANLIST>
+              <STMTTRN>
+                <TRNTYPE>DEBIT</TRNTYPE>
+                <DTPOSTED>20240203</DTPOSTED>
+                <TRNAMT>-30</TRNAMT>
+                <FITID>A1</FITID>
+                <PAYEE><NAME>Grocery</NAME></PAYEE>
+              </STMTTRN>
+            </BANKTRANLIST>
+            <LEDGERBAL><BALAMT>-30</BALAMT><DTASOF>20240203235959</DTASOF></LEDGERBAL>
+          </STMTRS></STMTTRNRS></BANKMSGSRSV1>
+        </OFX>
+    "#};
+
+    /// the multipart form uploading the statement
+    async fn statement_form() -> Multipart {
+        let boundary = "zhang-boundary";
+        let body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"\r\n\r\n{STATEMENT}\r\n--{boundary}\r\nContent-Disposition: form-data; name=\"counter_account\"\r\n\r\nExpenses:Unknown\r\n--{boundary}--\r\n"
+        );
+        let request = Request::builder()
+            .method("POST")
+            .header(CONTENT_TYPE, format!("multipart/form-data; boundary={}", boundary))
+            .body(Body::from(body))
+            .unwrap();
+        Multipart::from_request(request, &()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_not_import_same_statement_twice() {
+        let dir = tempdir().unwrap();
+        let (ledger, reload_sender) = load(
+            dir.path(),
+            indoc::indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Unknown
+            "#},
+        )
+        .await;
+        let import = |form: Multipart| {
+            import_ofx_statement(
+                State(ledger.clone()),
+                State(reload_sender.clone()),
+                State(SharedRules::default()),
+                Path(("Assets:Bank".to_string(),)),
+                form,
+            )
+        };
+
+        let first = import(statement_form().await).await.unwrap();
+        assert_eq!(2, first.data.len());
+        assert!(first.data[1].contains("balance Assets:Bank -30 CNY"), "{:?}", first.data);
+        let content = std::fs::read_to_string(dir.path().join("data/2024/2.zhang")).unwrap();
+
+        // the statement is uploaded again before the reload listener handles the first import
+        let second = import(statement_form().await).await.unwrap();
+        assert!(second.data.is_empty());
+        assert_eq!(content, std::fs::read_to_string(dir.path().join("data/2024/2.zhang")).unwrap());
+        // the balance check is stored as a transaction as well
+        assert_eq!(2, ledger.read().await.operations().read().transactions.len());
+    }
+}
@@ -4,6 +4,10 @@ pub mod commodity;
 pub mod common;
 pub mod document;
 pub mod file;
+pub mod git;
+pub mod history;
+pub mod import;
+pub mod report;
 pub mod statistics;
 pub mod transaction;
 
@@ -0,0 +1,173 @@
+use axum::extract::{Query, State};
+use axum::Json;
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use gotcha::api;
+use zhang_ast::Directive;
+use zhang_core::ledger::Ledger;
+use zhang_core::reports::{BalanceSheet, IncomeStatement, ReportInterval, TrialBalance};
+
+use crate::error::ServerError;
+use crate::request::{BalanceSheetRequest, CloseBooksRequest, IncomeStatementRequest, TrialBalanceRequest};
+use crate::response::ResponseWrapper;
+use crate::state::{SharedLedger, SharedReloadSender};
+use crate::ApiResult;
+
+/// the end of given day, or today if not given
+fn end_of_day(date: Option<NaiveDate>, timezone: Tz) -> Result<DateTime<Tz>, ServerError> {
+    let date = date.unwrap_or_else(|| Utc::now().with_timezone(&timezone).date_naive());
+    date.and_hms_opt(23, 59, 59)
+        .and_then(|it| it.and_local_timezone(timezone).earliest())
+        .ok_or(ServerError::BadRequest)
+}
+
+#[api(group = "report")]
+pub async fn get_balance_sheet(ledger: State<SharedLedger>, params: Query<BalanceSheetRequest>) -> ApiResult<BalanceSheet> {
+    let ledger = ledger.read().await;
+    let timezone = ledger.options.timezone;
+    let mut operations = ledger.operations();
+
+    let datetime = end_of_day(params.0.date, timezone)?;
+    ResponseWrapper::json(operations.balance_sheet(datetime)?)
+}
+
+#[api(group = "report")]
+pub async fn get_trial_balance(ledger: State<SharedLedger>, params: Query<TrialBalanceRequest>) -> ApiResult<TrialBalance> {
+    let ledger = ledger.read().await;
+    let timezone = ledger.options.timezone;
+    let mut operations = ledger.operations();
+
+    let datetime = end_of_day(params.0.date, timezone)?;
+    ResponseWrapper::json(operations.trial_balance(datetime)?)
+}
+
+/// the closing directives of given date, along with their text form.
+/// the date must be after the latest closing, otherwise the balances closed already are summed up again.
+fn closing_directives(ledger: &Ledger, date: NaiveDate) -> Result<(Vec<Directive>, Vec<String>), ServerError> {
+    if let Some(closed) = ledger.operations().last_closed_date().filter(|closed| closed >= &date) {
+        return Err(ServerError::BooksClosed(closed));
+    }
+    let directives = ledger.operations().close_books(date)?;
+    let mut generated = Vec::with_capacity(directives.len());
+    for directive in directives.iter().cloned() {
+        let content = ledger.data_source.export(directive)?;
+        generated.push(String::from_utf8_lossy(&content).to_string());
+    }
+    Ok((directives, generated))
+}
+
+/// generate the closing directives of given date, and append them to ledger if `write` is set.
+/// the generated directives are returned in text form, and closing is rejected if the books are closed on or after the date already.
+#[api(group = "report")]
+pub async fn close_books(
+    ledger: State<SharedLedger>, reload_sender: State<SharedReloadSender>, Json(payload): Json<CloseBooksRequest>,
+) -> ApiResult<Vec<String>> {
+    if !payload.write {
+        let ledger = ledger.read().await;
+        let (_, generated) = closing_directives(&ledger, payload.date)?;
+        return ResponseWrapper::json(generated);
+    }
+
+    let mut ledger = ledger.write().await;
+    let (directives, generated) = closing_directives(&ledger, payload.date)?;
+    if !directives.is_empty() {
+        ledger.data_source.async_append(&ledger, directives).await?;
+        reload_sender.reload_locked(&mut ledger).await?;
+    }
+    ResponseWrapper::json(generated)
+}
+
+#[api(group = "report")]
+pub async fn get_income_statement(ledger: State<SharedLedger>, params: Query<IncomeStatementRequest>) -> ApiResult<IncomeStatement> {
+    let ledger = ledger.read().await;
+    let timezone = ledger.options.timezone;
+    let mut operations = ledger.operations();
+
+    let params = params.0;
+    if params.from > params.to {
+        return ResponseWrapper::bad_request();
+    }
+    let statement = operations.income_statement(
+        params.from.with_timezone(&timezone),
+        params.to.with_timezone(&timezone),
+        params.interval.unwrap_or(ReportInterval::Month),
+    )?;
+    ResponseWrapper::json(statement)
+}
+
+#[cfg(test)]
+mod test {
+
+    use axum::extract::State;
+    use axum::Json;
+    use chrono::NaiveDate;
+    use tempfile::tempdir;
+
+    use crate::error::ServerError;
+    use crate::request::CloseBooksRequest;
+    use crate::routes::report::close_books;
+    use crate::test_util::load;
+
+    const LEDGER: &str = indoc::indoc! {r#"
+        1970-01-01 open Assets:Bank
+        1970-01-01 open Income:Salary
+        2023-01-01 "Company" "Salary"
+          Assets:Bank 1000 CNY
+          Income:Salary -1000 CNY
+    "#};
+
+    #[tokio::test]
+    async fn should_not_close_books_twice() {
+        let dir = tempdir().unwrap();
+        let (ledger, reload_sender) = load(dir.path(), LEDGER).await;
+        let date = NaiveDate::from_ymd_opt(2023, 12, 31).unwrap();
+        let close = || {
+            close_books(
+                State(ledger.clone()),
+                State(reload_sender.clone()),
+                Json(CloseBooksRequest { date, write: true }),
+            )
+        };
+
+        // previewing generates the directives without writing them
+        let preview = close_books(
+            State(ledger.clone()),
+            State(reload_sender.clone()),
+            Json(CloseBooksRequest { date, write: false }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(2, preview.data.len());
+        assert!(!dir.path().join("data/2023/12.zhang").exists());
+
+        let generated = close().await.unwrap();
+        assert_eq!(2, generated.data.len());
+        let content = std::fs::read_to_string(dir.path().join("data/2023/12.zhang")).unwrap();
+
+        // the books are closed again before the reload listener handles the first closing
+        let result = close().await;
+        assert!(matches!(result, Err(ServerError::BooksClosed(closed)) if closed == date));
+        assert_eq!(content, std::fs::read_to_string(dir.path().join("data/2023/12.zhang")).unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_not_close_books_before_latest_closing() {
+        let dir = tempdir().unwrap();
+        let (ledger, reload_sender) = load(dir.path(), LEDGER).await;
+        let close = |date: NaiveDate, write: bool| close_books(State(ledger.clone()), State(reload_sender.clone()), Json(CloseBooksRequest { date, write }));
+        let closed = NaiveDate::from_ymd_opt(2023, 12, 31).unwrap();
+        close(closed, true).await.unwrap();
+        let content = std::fs::read_to_string(dir.path().join("data/2023/12.zhang")).unwrap();
+
+        // the income of first half is transferred by the closing of year end already
+        let earlier = NaiveDate::from_ymd_opt(2023, 6, 30).unwrap();
+        assert!(matches!(close(earlier, false).await, Err(ServerError::BooksClosed(date)) if date == closed));
+        assert!(matches!(close(earlier, true).await, Err(ServerError::BooksClosed(date)) if date == closed));
+        assert!(!dir.path().join("data/2023/6.zhang").exists());
+        assert_eq!(content, std::fs::read_to_string(dir.path().join("data/2023/12.zhang")).unwrap());
+
+        // closing after the latest one only transfers what is left, which is nothing here
+        let later = close(NaiveDate::from_ymd_opt(2024, 6, 30).unwrap(), true).await.unwrap();
+        assert!(later.data.is_empty());
+    }
+}
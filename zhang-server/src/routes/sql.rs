@@ -1,16 +1,41 @@
+use std::sync::Arc;
+
+use axum::extract::Path;
 use gotcha::{api, Json, State};
-use zhang_sql::{AsExecutor, ExecutionResult};
+use zhang_core::store::QueryDomain;
+use zhang_sql::ExecutionResult;
 
 use crate::error::ServerError;
 use crate::request::SqlExecutionRequest;
 use crate::response::ResponseWrapper;
-use crate::state::SharedLedger;
+use crate::state::{SharedLedger, SharedSqlExecutor};
 use crate::ApiResult;
 
 #[api(group = "sql")]
-pub async fn execute_sql(ledger: State<SharedLedger>, sql: Json<SqlExecutionRequest>) -> ApiResult<ExecutionResult> {
+pub async fn execute_sql(ledger: State<SharedLedger>, executor: State<SharedSqlExecutor>, sql: Json<SqlExecutionRequest>) -> ApiResult<ExecutionResult> {
+    let ledger = Arc::clone(&ledger).read_owned().await;
+    let executor = executor.get_or_build(ledger).await?;
+    let sql = sql.0.sql;
+    let ret = tokio::task::spawn_blocking(move || executor.execute_read_only(&sql)).await??;
+    ResponseWrapper::json(ret)
+}
+
+#[api(group = "sql")]
+pub async fn get_query_list(ledger: State<SharedLedger>) -> ApiResult<Vec<QueryDomain>> {
     let ledger = ledger.read().await;
-    let executor = ledger.as_executor();
-    let ret = executor.execute(&sql.sql).map_err(|e| ServerError::SqlError(e.to_string()))?;
+    let operations = ledger.operations();
+    ResponseWrapper::json(operations.queries()?)
+}
+
+/// execute the saved query defined by `query` directive
+#[api(group = "sql")]
+pub async fn execute_saved_query(ledger: State<SharedLedger>, executor: State<SharedSqlExecutor>, params: Path<(String,)>) -> ApiResult<ExecutionResult> {
+    let query_name = params.0 .0;
+    let ledger = Arc::clone(&ledger).read_owned().await;
+    let operations = ledger.operations();
+    let query = operations.query(&query_name)?.ok_or(ServerError::NotFound)?;
+
+    let executor = executor.get_or_build(ledger).await?;
+    let ret = tokio::task::spawn_blocking(move || executor.execute_read_only(&query.query_string)).await??;
     ResponseWrapper::json(ret)
 }
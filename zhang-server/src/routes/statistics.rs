@@ -53,10 +53,7 @@ pub async fn get_statistic_summary(ledger: State<SharedLedger>, params: Query<St
 
     let income_amounts = operations
         .read()
-        .postings
-        .iter()
-        .filter(|posting| posting.trx_datetime.ge(&params.from))
-        .filter(|posting| posting.trx_datetime.le(&params.to))
+        .income_statement_postings(Some(params.from.with_timezone(timezone)), Some(params.to.with_timezone(timezone)))
         .filter(|posting| posting.account.account_type == AccountType::Income)
         .map(|posting| posting.inferred_amount.clone())
         .collect_vec();
@@ -65,10 +62,7 @@ pub async fn get_statistic_summary(ledger: State<SharedLedger>, params: Query<St
 
     let expense_amounts = operations
         .read()
-        .postings
-        .iter()
-        .filter(|posting| posting.trx_datetime.ge(&params.from))
-        .filter(|posting| posting.trx_datetime.le(&params.to))
+        .income_statement_postings(Some(params.from.with_timezone(timezone)), Some(params.to.with_timezone(timezone)))
         .filter(|posting| posting.account.account_type == AccountType::Expenses)
         .map(|posting| posting.inferred_amount.clone())
         .collect_vec();
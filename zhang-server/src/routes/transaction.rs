@@ -1,26 +1,33 @@
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use axum::extract::{Multipart, Path, State};
 use axum::Json;
+use chrono_tz::Tz;
 use gotcha::api;
 use indexmap::IndexSet;
 use itertools::Itertools;
 use log::info;
 use uuid::Uuid;
 use zhang_ast::error::ErrorKind;
-use zhang_ast::{Account, Date, Directive, Flag, Meta, Posting, SpanInfo, Transaction, ZhangString};
+use zhang_ast::{Account, Date, Directive, Flag, Meta, Posting, SpanInfo, Transaction};
 use zhang_core::constants::TXN_ID;
 use zhang_core::domains::schemas::MetaType;
+use zhang_core::importer::duplicate::{DuplicateCandidate, DuplicateRule};
+use zhang_core::ledger::Ledger;
 use zhang_core::store::TransactionDomain;
+use zhang_core::utils::content_hash;
 use zhang_core::utils::string_::{escape_with_quote, StringExt};
+use zhang_core::{ZhangError, ZhangResult};
 
 use super::Query;
-use crate::request::{CreateTransactionRequest, JournalRequest};
+use crate::error::ServerError;
+use crate::request::{CreateTransactionRequest, JournalRequest, TransactionMutationRequest};
 use crate::response::{
     InfoForNewTransaction, JournalBalanceItemEntity, JournalItemEntity, JournalTransactionItemEntity, JournalTransactionPostingEntity, Pageable,
     ResponseWrapper,
 };
-use crate::state::{SharedLedger, SharedReloadSender};
+use crate::state::{SharedHistory, SharedLedger, SharedReloadSender, SharedRules};
 use crate::ApiResult;
 
 #[api(group = "transaction")]
@@ -35,6 +42,7 @@ pub async fn get_info_for_new_transactions(ledger: State<SharedLedger>) -> ApiRe
     ResponseWrapper::json(InfoForNewTransaction {
         payee: operations.all_payees()?,
         account_name: account_names,
+        payee_accounts: operations.payee_accounts()?,
     })
 }
 
@@ -115,6 +123,7 @@ pub async fn get_journals(ledger: State<SharedLedger>, params: Query<JournalRequ
                     is_balanced: !has_unbalanced_error,
                     postings,
                     metas,
+                    hash: content_hash(&journal_item.span.content),
                 })
             }
         };
@@ -127,111 +136,153 @@ pub async fn get_journals(ledger: State<SharedLedger>, params: Query<JournalRequ
 
 #[api(group = "transaction")]
 pub async fn create_new_transaction(
-    ledger: State<SharedLedger>, reload_sender: State<SharedReloadSender>, Json(payload): Json<CreateTransactionRequest>,
+    ledger: State<SharedLedger>, reload_sender: State<SharedReloadSender>, rules: State<SharedRules>, Json(payload): Json<CreateTransactionRequest>,
 ) -> ApiResult<String> {
-    let ledger = ledger.read().await;
+    let mut ledger = ledger.write().await;
+    let allow_duplicate = payload.allow_duplicate;
+    let mut transaction = transaction_from_request(payload, &ledger.options.timezone)?;
+    // the accounts of postings are chosen by user, so only tags, links and metas of the matched rule are applied
+    rules.categorize_keeping_accounts(&mut transaction);
 
-    let mut postings = vec![];
-    for posting in payload.postings.into_iter() {
-        postings.push(Posting {
-            flag: None,
-            account: Account::from_str(&posting.account)?,
-            units: posting.unit,
-            cost: None,
-            price: None,
-            comment: None,
-        });
+    if !allow_duplicate {
+        let rule = DuplicateRule::default();
+        let mut candidates = ledger.operations().duplicate_candidates(&transaction, &rule)?;
+        // only the candidates treated as duplicated by importers block the creation
+        candidates.retain(|it| it.score >= rule.threshold);
+        if !candidates.is_empty() {
+            return Err(ServerError::DuplicateTransaction(candidates));
+        }
     }
 
-    let mut metas = Meta::default();
-    for meta in payload.metas {
-        metas.insert(meta.key, meta.value.to_quote());
-    }
-    let time = payload.datetime.with_timezone(&ledger.options.timezone).naive_local();
-    let trx = Directive::Transaction(Transaction {
-        date: Date::Datetime(time),
-        flag: payload.flag.map(|it| it.into()).or(Some(Flag::Okay)),
-        payee: Some(payload.payee.to_quote()),
-        narration: payload.narration.map(|it| it.to_quote()),
-        tags: IndexSet::from_iter(payload.tags),
-        links: IndexSet::from_iter(payload.links),
-        postings,
-        meta: metas,
-    });
-
-    ledger.data_source.async_append(&ledger, vec![trx]).await?;
-    reload_sender.reload();
+    ledger.data_source.async_append(&ledger, vec![Directive::Transaction(transaction)]).await?;
+    reload_sender.reload_locked(&mut ledger).await?;
     ResponseWrapper::json("Ok".to_string())
 }
 
+/// find the existing transactions which may be the same one as the transaction to be created, the most likely comes first
+#[api(group = "transaction")]
+pub async fn get_duplicate_transactions(ledger: State<SharedLedger>, Json(payload): Json<CreateTransactionRequest>) -> ApiResult<Vec<DuplicateCandidate>> {
+    let ledger = ledger.read().await;
+    let transaction = transaction_from_request(payload, &ledger.options.timezone)?;
+    let mut operations = ledger.operations();
+    let candidates = operations.duplicate_candidates(&transaction, &DuplicateRule::default())?;
+    ResponseWrapper::json(candidates)
+}
+
 // TODO: handle multipart/form-data
 #[api(group = "transaction")]
-// todo(refact): use exporter to update transaction
 pub async fn upload_transaction_document(
-    ledger: State<SharedLedger>, reload_sender: State<SharedReloadSender>, path: Path<(String,)>, mut multipart: Multipart,
+    ledger: State<SharedLedger>, reload_sender: State<SharedReloadSender>, history: State<SharedHistory>, path: Path<(String,)>,
+    params: Query<TransactionMutationRequest>, mut multipart: Multipart,
 ) -> ApiResult<String> {
-    let transaction_id = Uuid::from_str(&path.0 .0).expect("invalid txn id");
-    let ledger = ledger.read().await;
+    let Ok(transaction_id) = Uuid::from_str(&path.0 .0) else {
+        return ResponseWrapper::bad_request();
+    };
+    let mut files = vec![];
+    while let Some(field) = multipart.next_field().await.map_err(|_| ServerError::BadRequest)? {
+        let Some(file_name) = field.file_name().map(|it| it.to_string()) else {
+            return ResponseWrapper::bad_request();
+        };
+        let content = field.bytes().await.map_err(|_| ServerError::BadRequest)?;
+        files.push((file_name, content));
+    }
+
+    let mut ledger = ledger.write().await;
     let mut operations = ledger.operations();
-    let entry = &ledger.entry.0;
-    let mut documents = vec![];
 
     let span_info = operations.transaction_span(&transaction_id)?;
     let Some(span_info) = span_info else {
         return ResponseWrapper::bad_request();
     };
+    verify_hash(&ledger, &span_info, &params.0.hash).await?;
 
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let _name = field.name().unwrap().to_string();
-        let file_name = field.file_name().unwrap().to_string();
-        let _content_type = field.content_type().unwrap().to_string();
-
-        let v4 = Uuid::new_v4();
-        let buf = entry.join("attachments").join(v4.to_string()).join(&file_name);
-        let striped_buf = buf.strip_prefix(entry).unwrap();
-        let striped_path_string = striped_buf.to_string_lossy().to_string();
-        info!("uploading document `{}`(id={}) to transaction {}", file_name, &v4.to_string(), &transaction_id);
-        let content_buf = field.bytes().await.unwrap();
-
-        ledger.data_source.async_save(&ledger, striped_path_string, &content_buf).await?;
-
-        let path = match buf.strip_prefix(entry) {
-            Ok(relative_path) => relative_path.to_str().unwrap(),
-            Err(_) => buf.to_str().unwrap(),
-        };
-
-        documents.push(ZhangString::QuoteString(path.to_string()));
-    }
+    let documents = files
+        .into_iter()
+        .map(|(file_name, content)| {
+            let v4 = Uuid::new_v4();
+            info!("uploading document `{}`(id={}) to transaction {}", file_name, &v4.to_string(), &transaction_id);
+            let path = PathBuf::from("attachments").join(v4.to_string()).join(&file_name);
+            (path.to_string_lossy().to_string(), content)
+        })
+        .collect_vec();
 
+    // only the document metas are appended, so that the comments and layout of the transaction are kept
     let metas_content = documents
-        .into_iter()
-        .map(|document| format!("  document: {}", escape_with_quote(document.as_str())))
-        .join("\n");
-
-    let source_file_path = span_info.source_file.to_string_lossy().to_string();
-    let mut content = String::from_utf8(ledger.data_source.async_get(source_file_path.clone()).await?).unwrap();
-    content.insert(span_info.span_end, '\n');
-    content.insert_str(span_info.span_end + 1, &metas_content);
-    ledger.data_source.async_save(&ledger, source_file_path, content.as_bytes()).await?;
-    reload_sender.reload();
+        .iter()
+        .map(|(document, _)| format!("\n  document: {}", escape_with_quote(document)))
+        .join("");
+    let content = format!("{}{}", span_info.content, metas_content);
+    // the documents are saved only if the transaction is updated, so that no document is left behind by a conflict
+    history
+        .group(async {
+            ledger.data_source.async_replace_content(&ledger, &span_info, Some(content)).await?;
+            for (document, content) in documents {
+                ledger.data_source.async_save(&ledger, document, &content).await?;
+            }
+            ZhangResult::Ok(())
+        })
+        .await?;
+    reload_sender.reload_locked(&mut ledger).await?;
     ResponseWrapper::json("Ok".to_string())
 }
 
 #[api(group = "transaction")]
 pub async fn update_single_transaction(
-    ledger: State<SharedLedger>, reload_sender: State<SharedReloadSender>, path: Path<(String,)>, Json(payload): Json<CreateTransactionRequest>,
+    ledger: State<SharedLedger>, reload_sender: State<SharedReloadSender>, path: Path<(String,)>, params: Query<TransactionMutationRequest>,
+    Json(payload): Json<CreateTransactionRequest>,
 ) -> ApiResult<()> {
     let Ok(transaction_id) = Uuid::from_str(&path.0 .0) else {
         return ResponseWrapper::bad_request();
     };
-    let ledger = ledger.read().await;
+    let mut ledger = ledger.write().await;
     let mut operations = ledger.operations();
 
     let span_info = operations.transaction_span(&transaction_id)?;
     let Some(span_info) = span_info else {
         return ResponseWrapper::bad_request();
     };
+    verify_hash(&ledger, &span_info, &params.0.hash).await?;
+
+    let trx = Directive::Transaction(transaction_from_request(payload, &ledger.options.timezone)?);
+    ledger.data_source.async_replace(&ledger, &span_info, Some(trx)).await?;
+    reload_sender.reload_locked(&mut ledger).await?;
+    ResponseWrapper::json(())
+}
+
+/// remove the transaction with its metas and posting comments from the source file
+#[api(group = "transaction")]
+pub async fn delete_single_transaction(
+    ledger: State<SharedLedger>, reload_sender: State<SharedReloadSender>, path: Path<(String,)>, params: Query<TransactionMutationRequest>,
+) -> ApiResult<()> {
+    let Ok(transaction_id) = Uuid::from_str(&path.0 .0) else {
+        return ResponseWrapper::bad_request();
+    };
+    let mut ledger = ledger.write().await;
+    let mut operations = ledger.operations();
 
+    let Some(span_info) = operations.transaction_span(&transaction_id)? else {
+        return ResponseWrapper::not_found();
+    };
+    verify_hash(&ledger, &span_info, &params.0.hash).await?;
+    info!("deleting transaction {}", &transaction_id);
+
+    ledger.data_source.async_replace(&ledger, &span_info, None).await?;
+    // the spans of following transactions in the file are shifted, so they are refreshed before others mutate them
+    reload_sender.reload_locked(&mut ledger).await?;
+    ResponseWrapper::json(())
+}
+
+/// reject the mutation if the transaction is changed since the client read it, carrying the current content of its file
+async fn verify_hash(ledger: &Ledger, span: &SpanInfo, hash: &str) -> Result<(), ServerError> {
+    if content_hash(&span.content) != hash {
+        let path = span.filename.as_ref().map(|it| it.to_string_lossy().to_string()).unwrap_or_default();
+        let current_content = String::from_utf8(ledger.data_source.async_get(path).await?).map_err(ZhangError::from)?;
+        return Err(ServerError::Conflict(current_content));
+    }
+    Ok(())
+}
+
+fn transaction_from_request(payload: CreateTransactionRequest, timezone: &Tz) -> Result<Transaction, ServerError> {
     let mut postings = vec![];
     for posting in payload.postings.into_iter() {
         postings.push(Posting {
@@ -243,12 +294,13 @@ pub async fn update_single_transaction(
             comment: None,
         });
     }
+
     let mut metas = Meta::default();
     for meta in payload.metas {
         metas.insert(meta.key, meta.value.to_quote());
     }
-    let time = payload.datetime.with_timezone(&ledger.options.timezone).naive_local();
-    let trx = Directive::Transaction(Transaction {
+    let time = payload.datetime.with_timezone(timezone).naive_local();
+    Ok(Transaction {
         date: Date::Datetime(time),
         flag: payload.flag.map(|it| it.into()).or(Some(Flag::Okay)),
         payee: Some(payload.payee.to_quote()),
@@ -257,15 +309,591 @@ pub async fn update_single_transaction(
         links: IndexSet::from_iter(payload.links),
         postings,
         meta: metas,
-    });
-    let txn_content = ledger.data_source.export(trx)?;
-    let trx_content = String::from_utf8_lossy(&txn_content);
-    let source_file_path = span_info.source_file.to_string_lossy().to_string();
+    })
+}
 
-    let mut content = String::from_utf8(ledger.data_source.async_get(source_file_path.clone()).await?).unwrap();
-    content.replace_by_span(&SpanInfo::simple(span_info.span_start, span_info.span_end), &trx_content);
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::Arc;
 
-    ledger.data_source.async_save(&ledger, source_file_path, content.as_bytes()).await?;
-    reload_sender.reload();
-    ResponseWrapper::json(())
+    use axum::body::Body;
+    use axum::extract::{FromRequest, FromRequestParts, Multipart, Path as RoutePath, Request, State};
+    use axum::http::header::CONTENT_TYPE;
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use axum::Json;
+    use indexmap::IndexMap;
+    use serde_json::json;
+    use tempfile::tempdir;
+    use zhang_ast::Directive;
+    use zhang_core::data_source::{DataSource, LoadResult, LocalFileSystemDataSource};
+    use zhang_core::data_type::text::ZhangDataType;
+    use zhang_core::importer::rule::{CategorizeRule, Categorizer};
+    use zhang_core::ledger::Ledger;
+    use zhang_core::utils::content_hash;
+    use zhang_core::{ZhangError, ZhangResult};
+
+    use crate::error::ServerError;
+    use crate::history::{History, HistoryDataSource};
+    use crate::request::TransactionMutationRequest;
+    use crate::routes::history::revert_history;
+    use crate::routes::transaction::{create_new_transaction, delete_single_transaction, update_single_transaction, upload_transaction_document};
+    use crate::routes::Query;
+    use crate::state::{SharedHistory, SharedRules};
+    use crate::test_util::{load, load_with};
+
+    #[tokio::test]
+    async fn should_categorize_created_transaction_by_rules() {
+        let dir = tempdir().unwrap();
+        let (ledger, reload_sender) = load(
+            dir.path(),
+            indoc::indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Food
+                1970-01-01 open Expenses:Unknown
+            "#},
+        )
+        .await;
+        let rules = SharedRules(Arc::new(
+            Categorizer::new(vec![CategorizeRule {
+                payee: Some("kfc".to_string()),
+                counter_account: Some("Expenses:Food".to_string()),
+                tags: vec!["fastfood".to_string()],
+                meta: IndexMap::from([("category".to_string(), "meal".to_string())]),
+                ..Default::default()
+            }])
+            .unwrap(),
+        ));
+        let request = serde_json::from_value(json!({
+            "datetime": "2023-05-02T04:00:00Z",
+            "payee": "KFC",
+            "narration": "lunch",
+            "postings": [{"account": "Assets:Bank", "unit": {"number": "-10", "commodity": "CNY"}}, {"account": "Expenses:Unknown", "unit": null}],
+            "metas": [],
+            "tags": [],
+            "links": []
+        }))
+        .unwrap();
+
+        create_new_transaction(State(ledger), State(reload_sender), State(rules), Json(request))
+            .await
+            .unwrap();
+
+        let content = std::fs::read_to_string(dir.path().join("data/2023/5.zhang")).unwrap();
+        assert!(content.contains("#fastfood"));
+        assert!(content.contains("category: \"meal\""));
+        // the account chosen by user is kept
+        assert!(content.contains("Expenses:Unknown"));
+        assert!(!content.contains("Expenses:Food"));
+    }
+
+    #[tokio::test]
+    async fn should_reject_duplicate_transaction_unless_allowed() {
+        let dir = tempdir().unwrap();
+        let (ledger, reload_sender) = load(
+            dir.path(),
+            indoc::indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Food
+                2023-05-01 "KFC" "lunch"
+                  Assets:Bank -10 CNY
+                  Expenses:Food
+            "#},
+        )
+        .await;
+        let request = |allow_duplicate: bool| {
+            serde_json::from_value(json!({
+                "datetime": "2023-05-02T04:00:00Z",
+                "payee": "KFC",
+                "narration": "lunch",
+                "postings": [{"account": "Assets:Bank", "unit": {"number": "-10", "commodity": "CNY"}}, {"account": "Expenses:Food", "unit": null}],
+                "metas": [],
+                "tags": [],
+                "links": [],
+                "allow_duplicate": allow_duplicate
+            }))
+            .unwrap()
+        };
+
+        let result = create_new_transaction(
+            State(ledger.clone()),
+            State(reload_sender.clone()),
+            State(SharedRules::default()),
+            Json(request(false)),
+        )
+        .await;
+        let Err(ServerError::DuplicateTransaction(candidates)) = result else {
+            panic!("duplicate transaction should be rejected");
+        };
+        assert_eq!(1, candidates.len());
+        assert_eq!(Some("KFC".to_string()), candidates[0].payee);
+        assert!(!dir.path().join("data/2023/5.zhang").exists());
+
+        create_new_transaction(
+            State(ledger.clone()),
+            State(reload_sender.clone()),
+            State(SharedRules::default()),
+            Json(request(true)),
+        )
+        .await
+        .unwrap();
+        let content = std::fs::read_to_string(dir.path().join("data/2023/5.zhang")).unwrap();
+        assert!(content.contains("2023-05-02"));
+    }
+
+    #[tokio::test]
+    async fn should_reject_same_transaction_submitted_twice() {
+        let dir = tempdir().unwrap();
+        let (ledger, reload_sender) = load(
+            dir.path(),
+            indoc::indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Food
+            "#},
+        )
+        .await;
+        let request = || {
+            serde_json::from_value(json!({
+                "datetime": "2023-05-02T04:00:00Z",
+                "payee": "KFC",
+                "narration": "lunch",
+                "postings": [{"account": "Assets:Bank", "unit": {"number": "-10", "commodity": "CNY"}}, {"account": "Expenses:Food", "unit": null}],
+                "metas": [],
+                "tags": [],
+                "links": []
+            }))
+            .unwrap()
+        };
+        let create = || {
+            create_new_transaction(
+                State(ledger.clone()),
+                State(reload_sender.clone()),
+                State(SharedRules::default()),
+                Json(request()),
+            )
+        };
+
+        create().await.unwrap();
+        let content = std::fs::read_to_string(dir.path().join("data/2023/5.zhang")).unwrap();
+
+        // the transaction is submitted again before the reload listener handles the first one
+        let result = create().await;
+        assert!(matches!(result, Err(ServerError::DuplicateTransaction(_))));
+        assert_eq!(content, std::fs::read_to_string(dir.path().join("data/2023/5.zhang")).unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_create_transaction_whose_candidates_are_below_threshold() {
+        let dir = tempdir().unwrap();
+        let (ledger, reload_sender) = load(
+            dir.path(),
+            indoc::indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Food
+                2023-05-01 "KFC" "lunch"
+                  Assets:Bank -10 CNY
+                  Expenses:Food
+            "#},
+        )
+        .await;
+        // the same amount three days later by another payee is scored below the threshold
+        let request = serde_json::from_value(json!({
+            "datetime": "2023-05-04T04:00:00Z",
+            "payee": "McDonald",
+            "narration": "lunch",
+            "postings": [{"account": "Assets:Bank", "unit": {"number": "-10", "commodity": "CNY"}}, {"account": "Expenses:Food", "unit": null}],
+            "metas": [],
+            "tags": [],
+            "links": []
+        }))
+        .unwrap();
+
+        create_new_transaction(State(ledger), State(reload_sender), State(SharedRules::default()), Json(request))
+            .await
+            .unwrap();
+        let content = std::fs::read_to_string(dir.path().join("data/2023/5.zhang")).unwrap();
+        assert!(content.contains("2023-05-04"));
+    }
+
+    /// local file system data source resolving relative paths against the ledger root, like the opendal data source does
+    struct RootedDataSource {
+        root: PathBuf,
+        inner: LocalFileSystemDataSource,
+    }
+
+    impl DataSource for RootedDataSource {
+        fn export(&self, directive: Directive) -> ZhangResult<Vec<u8>> {
+            self.inner.export(directive)
+        }
+
+        fn get(&self, path: String) -> ZhangResult<Vec<u8>> {
+            self.inner.get(self.root.join(path).to_string_lossy().to_string())
+        }
+
+        fn load(&self, entry: String, endpoint: String) -> ZhangResult<LoadResult> {
+            self.inner.load(entry, endpoint)
+        }
+
+        fn save(&self, ledger: &Ledger, path: String, content: &[u8]) -> ZhangResult<()> {
+            let path = self.root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            self.inner.save(ledger, path.to_string_lossy().to_string(), content)
+        }
+
+        fn remove(&self, ledger: &Ledger, path: String) -> ZhangResult<()> {
+            self.inner.remove(ledger, self.root.join(path).to_string_lossy().to_string())
+        }
+    }
+
+    fn history() -> SharedHistory {
+        SharedHistory(Arc::new(History::new(10)))
+    }
+
+    /// the multipart form uploading the file as the document
+    async fn document_form(file_name: &str, content: &str) -> Multipart {
+        let boundary = "zhang-boundary";
+        let body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{file_name}\"\r\nContent-Type: text/plain\r\n\r\n{content}\r\n--{boundary}--\r\n"
+        );
+        let request = Request::builder()
+            .method("POST")
+            .header(CONTENT_TYPE, format!("multipart/form-data; boundary={}", boundary))
+            .body(Body::from(body))
+            .unwrap();
+        Multipart::from_request(request, &()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_keep_comments_of_transaction_when_uploading_document() {
+        let dir = tempdir().unwrap();
+        let (ledger, reload_sender) = load_with(
+            dir.path(),
+            RootedDataSource {
+                root: dir.path().canonicalize().unwrap(),
+                inner: LocalFileSystemDataSource::new(ZhangDataType {}),
+            },
+            indoc::indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Food
+
+                2023-05-01 "KFC" "lunch" ; header comment
+                  source: "card" ; meta comment
+                  Assets:Bank   -10 CNY ; posting comment
+                  Expenses:Food
+
+                2023-05-02 "KFC" "dinner"
+                  Assets:Bank -20 CNY
+                  Expenses:Food
+            "#},
+        )
+        .await;
+        let (id, hash) = {
+            let ledger = ledger.read().await;
+            let store = ledger.operations().read().transactions.clone();
+            let transaction = store.values().find(|it| it.narration.as_deref() == Some("lunch")).unwrap();
+            (transaction.id, content_hash(&transaction.span.content))
+        };
+
+        upload_transaction_document(
+            State(ledger.clone()),
+            State(reload_sender),
+            State(history()),
+            RoutePath((id.to_string(),)),
+            Query(TransactionMutationRequest { hash }),
+            document_form("receipt.txt", "receipt").await,
+        )
+        .await
+        .unwrap();
+
+        let content = std::fs::read_to_string(dir.path().join("main.zhang")).unwrap();
+        let document = std::fs::read_dir(dir.path().join("attachments")).unwrap().next().unwrap().unwrap().file_name();
+        let expected = format!(
+            indoc::indoc! {r#"
+                2023-05-01 "KFC" "lunch" ; header comment
+                  source: "card" ; meta comment
+                  Assets:Bank   -10 CNY ; posting comment
+                  Expenses:Food
+                  document: "attachments/{}/receipt.txt"
+
+                2023-05-02 "KFC" "dinner"
+            "#},
+            document.to_string_lossy()
+        );
+        assert!(content.contains(&expected), "{}", content);
+
+        let reloaded = Ledger::async_load(
+            dir.path().to_path_buf(),
+            "main.zhang".to_string(),
+            Arc::new(LocalFileSystemDataSource::new(ZhangDataType {})),
+        )
+        .await
+        .unwrap();
+        assert_eq!(1, reloaded.operations().read().documents.len());
+    }
+
+    #[tokio::test]
+    async fn should_revert_uploaded_document_in_one_step() {
+        let dir = tempdir().unwrap();
+        let history = Arc::new(History::new(10));
+        let data_source = RootedDataSource {
+            root: dir.path().canonicalize().unwrap(),
+            inner: LocalFileSystemDataSource::new(ZhangDataType {}),
+        };
+        let content = indoc::indoc! {r#"
+            1970-01-01 open Assets:Bank
+            1970-01-01 open Expenses:Food
+            2023-05-01 "KFC" "lunch"
+              Assets:Bank -10 CNY
+              Expenses:Food
+        "#};
+        let (ledger, reload_sender) = load_with(dir.path(), HistoryDataSource::new(Arc::new(data_source), history.clone()), content).await;
+        let (id, hash) = {
+            let ledger = ledger.read().await;
+            let store = ledger.operations().read().transactions.clone();
+            let transaction = store.values().next().unwrap();
+            (transaction.id.to_string(), content_hash(&transaction.span.content))
+        };
+
+        upload_transaction_document(
+            State(ledger.clone()),
+            State(reload_sender.clone()),
+            State(SharedHistory(history.clone())),
+            RoutePath((id,)),
+            Query(TransactionMutationRequest { hash }),
+            document_form("receipt.txt", "receipt").await,
+        )
+        .await
+        .unwrap();
+
+        let entries = history.entries();
+        assert_eq!(1, entries.len());
+        assert_eq!(2, entries[0].files.len());
+
+        revert_history(State(ledger), State(SharedHistory(history)), State(reload_sender), RoutePath((entries[0].id,)))
+            .await
+            .unwrap();
+        assert_eq!(content, std::fs::read_to_string(dir.path().join("main.zhang")).unwrap());
+        let attachments = std::fs::read_dir(dir.path().join("attachments")).unwrap();
+        assert!(attachments.flat_map(|it| std::fs::read_dir(it.unwrap().path()).unwrap()).next().is_none());
+    }
+
+    #[tokio::test]
+    async fn should_not_save_document_if_transaction_is_conflicted() {
+        let dir = tempdir().unwrap();
+        let (ledger, reload_sender) = load_with(
+            dir.path(),
+            RootedDataSource {
+                root: dir.path().canonicalize().unwrap(),
+                inner: LocalFileSystemDataSource::new(ZhangDataType {}),
+            },
+            indoc::indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Food
+                2023-05-01 "KFC" "lunch"
+                  Assets:Bank -10 CNY
+                  Expenses:Food
+            "#},
+        )
+        .await;
+        let (id, hash) = {
+            let ledger = ledger.read().await;
+            let store = ledger.operations().read().transactions.clone();
+            let transaction = store.values().next().unwrap();
+            (transaction.id.to_string(), content_hash(&transaction.span.content))
+        };
+        std::fs::write(dir.path().join("main.zhang"), "; edited elsewhere\n").unwrap();
+
+        let result = upload_transaction_document(
+            State(ledger),
+            State(reload_sender),
+            State(history()),
+            RoutePath((id,)),
+            Query(TransactionMutationRequest { hash }),
+            document_form("receipt.txt", "receipt").await,
+        )
+        .await;
+
+        assert!(matches!(result, Err(ServerError::CoreError(ZhangError::ConflictedContent { .. }))));
+        assert!(!dir.path().join("attachments").exists());
+    }
+
+    #[tokio::test]
+    async fn should_reject_document_of_invalid_transaction_id() {
+        let dir = tempdir().unwrap();
+        let (ledger, reload_sender) = load(dir.path(), "1970-01-01 open Assets:Bank\n").await;
+
+        let result = upload_transaction_document(
+            State(ledger),
+            State(reload_sender),
+            State(history()),
+            RoutePath(("invalid".to_string(),)),
+            Query(TransactionMutationRequest { hash: "hash".to_string() }),
+            document_form("receipt.txt", "receipt").await,
+        )
+        .await;
+
+        assert!(matches!(result, Err(ServerError::BadRequest)));
+    }
+
+    /// the body of the conflict response, which carries the current content of the file
+    async fn conflict_content(error: ServerError) -> String {
+        let response = error.into_response();
+        assert_eq!(StatusCode::CONFLICT, response.status());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        payload["content"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn should_reject_mutations_of_transaction_with_stale_hash() {
+        let dir = tempdir().unwrap();
+        let content = indoc::indoc! {r#"
+            1970-01-01 open Assets:Bank
+            1970-01-01 open Expenses:Food
+            2023-05-01 "KFC" "lunch"
+              Assets:Bank -10 CNY
+              Expenses:Food
+        "#};
+        let (ledger, reload_sender) = load(dir.path(), content).await;
+        let id = {
+            let ledger = ledger.read().await;
+            let store = ledger.operations().read().transactions.clone();
+            store.values().next().unwrap().id.to_string()
+        };
+        let stale = || Query(TransactionMutationRequest { hash: "stale".to_string() });
+        let request = serde_json::from_value(json!({
+            "datetime": "2023-05-02T04:00:00Z",
+            "payee": "KFC",
+            "narration": "dinner",
+            "postings": [{"account": "Assets:Bank", "unit": {"number": "-20", "commodity": "CNY"}}, {"account": "Expenses:Food", "unit": null}],
+            "metas": [],
+            "tags": [],
+            "links": []
+        }))
+        .unwrap();
+
+        let updated = update_single_transaction(
+            State(ledger.clone()),
+            State(reload_sender.clone()),
+            RoutePath((id.clone(),)),
+            stale(),
+            Json(request),
+        )
+        .await;
+        let deleted = delete_single_transaction(State(ledger.clone()), State(reload_sender.clone()), RoutePath((id.clone(),)), stale()).await;
+        let uploaded = upload_transaction_document(
+            State(ledger),
+            State(reload_sender),
+            State(history()),
+            RoutePath((id,)),
+            stale(),
+            document_form("receipt.txt", "receipt").await,
+        )
+        .await;
+
+        for error in [updated.err(), deleted.err(), uploaded.err()] {
+            let Some(error @ ServerError::Conflict(_)) = error else {
+                panic!("mutation with stale hash should be rejected");
+            };
+            assert_eq!(content, conflict_content(error).await);
+        }
+        assert_eq!(content, std::fs::read_to_string(dir.path().join("main.zhang")).unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_carry_current_content_if_file_is_changed_after_loaded() {
+        let dir = tempdir().unwrap();
+        let (ledger, reload_sender) = load(
+            dir.path(),
+            indoc::indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Food
+                2023-05-01 "KFC" "lunch"
+                  Assets:Bank -10 CNY
+                  Expenses:Food
+            "#},
+        )
+        .await;
+        let (id, hash) = {
+            let ledger = ledger.read().await;
+            let store = ledger.operations().read().transactions.clone();
+            let transaction = store.values().next().unwrap();
+            (transaction.id.to_string(), content_hash(&transaction.span.content))
+        };
+        let edited = "; edited elsewhere\n";
+        std::fs::write(dir.path().join("main.zhang"), edited).unwrap();
+
+        let result = delete_single_transaction(
+            State(ledger),
+            State(reload_sender),
+            RoutePath((id,)),
+            Query(TransactionMutationRequest { hash }),
+        )
+        .await;
+
+        let Err(error @ ServerError::CoreError(ZhangError::ConflictedContent { .. })) = result else {
+            panic!("deletion should be rejected");
+        };
+        assert_eq!(edited, conflict_content(error).await);
+        assert_eq!(edited, std::fs::read_to_string(dir.path().join("main.zhang")).unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_delete_transactions_of_same_file_back_to_back() {
+        let dir = tempdir().unwrap();
+        let (ledger, reload_sender) = load(
+            dir.path(),
+            indoc::indoc! {r#"
+                1970-01-01 open Assets:Bank
+                1970-01-01 open Expenses:Food
+                2023-05-01 "KFC" "lunch"
+                  Assets:Bank -10 CNY
+                  Expenses:Food
+                2023-05-02 "KFC" "dinner"
+                  Assets:Bank -20 CNY
+                  Expenses:Food
+            "#},
+        )
+        .await;
+        // the id and hash are taken from the store as the client does after every change
+        let transaction = |narration: &'static str| {
+            let ledger = ledger.clone();
+            async move {
+                let ledger = ledger.read().await;
+                let store = ledger.operations().read().transactions.clone();
+                let transaction = store.values().find(|it| it.narration.as_deref() == Some(narration)).unwrap();
+                (transaction.id.to_string(), content_hash(&transaction.span.content))
+            }
+        };
+        let delete = |(id, hash): (String, String)| {
+            delete_single_transaction(
+                State(ledger.clone()),
+                State(reload_sender.clone()),
+                RoutePath((id,)),
+                Query(TransactionMutationRequest { hash }),
+            )
+        };
+
+        delete(transaction("lunch").await).await.unwrap();
+        reload_sender.1.get_or_build(ledger.0.clone().read_owned().await).await.unwrap();
+        // the reload listener has not handled the first deletion yet
+        delete(transaction("dinner").await).await.unwrap();
+        // the sql executor built before the deletion is dropped before the lock is released
+        assert!(reload_sender.1.lock().await.is_none());
+
+        let content = std::fs::read_to_string(dir.path().join("main.zhang")).unwrap();
+        assert!(!content.contains("lunch"), "{}", content);
+        assert!(!content.contains("dinner"), "{}", content);
+        assert!(ledger.read().await.operations().read().transactions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_reject_mutation_without_hash() {
+        let (mut parts, _) = Request::builder().uri("/api/transactions/some-id").body(()).unwrap().into_parts();
+
+        let result = Query::<TransactionMutationRequest>::from_request_parts(&mut parts, &()).await;
+
+        assert_eq!(Some(StatusCode::BAD_REQUEST), result.err());
+    }
 }
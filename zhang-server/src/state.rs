@@ -3,11 +3,15 @@ use std::sync::Arc;
 
 use axum::extract::FromRef;
 use gotcha::GotchaContext;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, OwnedRwLockReadGuard, RwLock};
+use zhang_core::importer::rule::Categorizer;
 use zhang_core::ledger::Ledger;
+use zhang_sql::{AsExecutor, Executor, SqlError};
 
 use crate::broadcast::Broadcaster;
-use crate::ReloadSender;
+use crate::git::GitRepository;
+use crate::history::History;
+use crate::{ReloadSender, ServerResult};
 
 #[derive(Clone)]
 pub struct SharedLedger(pub Arc<RwLock<Ledger>>);
@@ -42,11 +46,83 @@ impl Deref for SharedReloadSender {
     }
 }
 
+#[derive(Clone)]
+pub struct SharedHistory(pub Arc<History>);
+
+impl Deref for SharedHistory {
+    type Target = Arc<History>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// the git working tree of ledger, only available if the git data source is used
+#[derive(Clone)]
+pub struct SharedGitRepository(pub Option<Arc<GitRepository>>);
+
+impl Deref for SharedGitRepository {
+    type Target = Option<Arc<GitRepository>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// categorizer compiled from the rules given on startup, applied to transactions created or imported through web
+#[derive(Clone, Default)]
+pub struct SharedRules(pub Arc<Categorizer>);
+
+impl Deref for SharedRules {
+    type Target = Arc<Categorizer>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// sql executor built from the loaded ledger, it is built on first use and dropped on every reload
+#[derive(Clone, Default)]
+pub struct SharedSqlExecutor(pub Arc<Mutex<Option<Executor>>>);
+
+impl Deref for SharedSqlExecutor {
+    type Target = Arc<Mutex<Option<Executor>>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl SharedSqlExecutor {
+    /// get a connection of the cached executor, or build the executor from given ledger on the blocking pool.
+    /// every caller owns its connection, so that queries run without holding the lock.
+    ///
+    /// the ledger must be locked before the executor, which is the order the reload listener takes them.
+    pub async fn get_or_build(&self, ledger: OwnedRwLockReadGuard<Ledger>) -> ServerResult<Executor> {
+        let mut cached = self.0.lock().await;
+        if cached.is_none() {
+            // building loads the whole store into tables, which would stall the runtime if it runs on async workers
+            let executor = tokio::task::spawn_blocking(move || ledger.as_executor()).await??;
+            *cached = Some(executor);
+        }
+        let executor = cached.as_ref().expect("sql executor is built above");
+        Ok(executor.try_clone().map_err(SqlError::from)?)
+    }
+
+    pub async fn invalidate(&self) {
+        self.0.lock().await.take();
+    }
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub ledger: SharedLedger,
     pub broadcaster: SharedBroadcaster,
     pub reload_sender: SharedReloadSender,
+    pub sql_executor: SharedSqlExecutor,
+    pub history: SharedHistory,
+    pub git_repository: SharedGitRepository,
+    pub rules: SharedRules,
 }
 
 impl FromRef<GotchaContext<AppState, ()>> for SharedLedger {
@@ -65,3 +141,23 @@ impl FromRef<GotchaContext<AppState, ()>> for SharedReloadSender {
         input.state.reload_sender.clone()
     }
 }
+impl FromRef<GotchaContext<AppState, ()>> for SharedSqlExecutor {
+    fn from_ref(input: &GotchaContext<AppState, ()>) -> Self {
+        input.state.sql_executor.clone()
+    }
+}
+impl FromRef<GotchaContext<AppState, ()>> for SharedHistory {
+    fn from_ref(input: &GotchaContext<AppState, ()>) -> Self {
+        input.state.history.clone()
+    }
+}
+impl FromRef<GotchaContext<AppState, ()>> for SharedGitRepository {
+    fn from_ref(input: &GotchaContext<AppState, ()>) -> Self {
+        input.state.git_repository.clone()
+    }
+}
+impl FromRef<GotchaContext<AppState, ()>> for SharedRules {
+    fn from_ref(input: &GotchaContext<AppState, ()>) -> Self {
+        input.state.rules.clone()
+    }
+}
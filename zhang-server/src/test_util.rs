@@ -0,0 +1,29 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, RwLock};
+use zhang_core::data_source::{DataSource, LocalFileSystemDataSource};
+use zhang_core::data_type::text::ZhangDataType;
+use zhang_core::ledger::Ledger;
+
+use crate::state::{SharedLedger, SharedReloadSender, SharedSqlExecutor};
+use crate::ReloadSender;
+
+/// write the content as `main.zhang` into the folder and load the ledger from it through local file system
+pub(crate) async fn load(dir: &Path, content: &str) -> (SharedLedger, SharedReloadSender) {
+    load_with(dir, LocalFileSystemDataSource::new(ZhangDataType {}), content).await
+}
+
+/// like [load], but the ledger is loaded through the given data source.
+/// the receiver of reload sender is dropped, so the reload listener is not involved in tests.
+pub(crate) async fn load_with(dir: &Path, data_source: impl DataSource + 'static, content: &str) -> (SharedLedger, SharedReloadSender) {
+    std::fs::write(dir.join("main.zhang"), content).unwrap();
+    let ledger = Ledger::async_load(dir.to_path_buf(), "main.zhang".to_string(), Arc::new(data_source))
+        .await
+        .unwrap();
+    let (tx, _rx) = mpsc::channel(1);
+    (
+        SharedLedger(Arc::new(RwLock::new(ledger))),
+        SharedReloadSender(Arc::new(ReloadSender(tx, SharedSqlExecutor::default()))),
+    )
+}
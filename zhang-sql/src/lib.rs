@@ -1,34 +1,56 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use duckdb::types::{TimeUnit, ValueRef};
-use zhang_core::domains::schemas::{AccountDomain, CommodityDomain, MetaDomain, PriceDomain};
+use itertools::Itertools;
+use zhang_core::domains::schemas::{AccountDomain, CommodityDomain, ErrorDomain, MetaDomain, PriceDomain};
 use zhang_core::ledger::Ledger;
 use zhang_core::options::InMemoryOptions;
-use zhang_core::store::{DocumentDomain, PostingDomain, TransactionDomain};
+use zhang_core::store::{BudgetDomain, DocumentDomain, PostingDomain, TransactionDomain};
 
+mod read_only;
 mod table_definition;
 use duckdb::{Connection, Result};
 use gotcha::Schematic;
 use serde::Serialize;
-use table_definition::{AsTableDefinition, CommodityLotRow, TableDefinition, TrxLink, TrxTag};
+use table_definition::{AsTableDefinition, BudgetEventRow, BudgetIntervalRow, CommodityLotRow, DAILY_BALANCES_VIEW, TableDefinition, TrxLink, TrxTag};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SqlError {
+    #[error("statement is rejected: {0}")]
+    ReadOnly(String),
+
+    #[error("{0}")]
+    Execution(#[from] duckdb::Error),
+}
 
 #[derive(Debug, Serialize, Schematic)]
 pub struct Column {
-    name: String,
-    value: serde_json::Value,
+    pub name: String,
+    pub value: serde_json::Value,
 }
 
 #[derive(Debug, Serialize, Schematic)]
 pub struct Row {
-    columns: Vec<Column>,
+    pub columns: Vec<Column>,
 }
 
+/// days from 0001-01-01 to 1970-01-01, duckdb dates are counted from the unix epoch
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
 fn value_ref_to_value(value: ValueRef<'_>) -> serde_json::Value {
     match value {
         ValueRef::Text(s) => serde_json::Value::String(String::from_utf8_lossy(s).to_string()),
         ValueRef::Int(i) => serde_json::Value::Number(serde_json::Number::from(i)),
+        ValueRef::BigInt(i) => serde_json::Value::Number(serde_json::Number::from(i)),
         ValueRef::Float(f) => serde_json::Value::Number(serde_json::Number::from_f64(f as f64).unwrap()),
+        ValueRef::Double(f) => serde_json::Number::from_f64(f).map(serde_json::Value::Number).unwrap_or_default(),
+        // decimals are kept as string to preserve the precision, like amounts in other apis
+        ValueRef::Decimal(d) => serde_json::Value::String(d.to_string()),
         ValueRef::Boolean(b) => serde_json::Value::Bool(b),
         ValueRef::Timestamp(TimeUnit::Microsecond, i) => serde_json::Value::String(DateTime::<Utc>::from_timestamp_micros(i).unwrap().to_string()),
+        ValueRef::Date32(days) => NaiveDate::from_num_days_from_ce_opt(days + UNIX_EPOCH_DAYS_FROM_CE)
+            .map(|date| serde_json::Value::String(date.to_string()))
+            .unwrap_or_default(),
         _ => serde_json::Value::Null,
     }
 }
@@ -40,34 +62,53 @@ pub struct Executor {
 
 #[derive(Debug, Serialize, Schematic)]
 pub struct ExecutionResult {
-    columns: Vec<String>,
-    rows: Vec<Row>,
+    pub columns: Vec<String>,
+    pub rows: Vec<Row>,
 }
 
 impl Executor {
-    pub fn new(table_definitions: Vec<TableDefinition>) -> Self {
-        let conn = Connection::open_in_memory().unwrap();
+    pub fn new(table_definitions: Vec<TableDefinition>) -> Result<Self> {
+        let conn = Connection::open_in_memory()?;
 
         // create tables
         for table_definition in &table_definitions {
-            conn.execute(table_definition.as_sql().as_str(), []).unwrap();
+            conn.execute(table_definition.as_sql().as_str(), [])?;
+        }
+        Ok(Self { table_definitions, conn })
+    }
+
+    /// open another connection to the same database, so that queries can run at the same time without sharing a connection
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            table_definitions: self.table_definitions.clone(),
+            conn: self.conn.try_clone()?,
+        })
+    }
+
+    /// bulk load the rows of given table via duckdb's appender
+    fn append<'a, T: AsTableDefinition + 'a>(&self, rows: impl IntoIterator<Item = &'a T>) -> Result<()> {
+        let mut appender = self.conn.appender(T::as_table_definition().name())?;
+        for row in rows {
+            row.append_data(&mut appender)?;
         }
-        Self { table_definitions, conn }
+        appender.flush()
+    }
+
+    /// execute the query only if it is a single read-only statement,
+    /// which keeps the loaded data intact when the executor is shared across requests.
+    pub fn execute_read_only(&self, query: &str) -> Result<ExecutionResult, SqlError> {
+        read_only::check_read_only(query).map_err(SqlError::ReadOnly)?;
+        Ok(self.execute(query)?)
     }
 
     pub fn execute(&self, query: &str) -> Result<ExecutionResult, duckdb::Error> {
         let mut stmt = self.conn.prepare(query)?;
         let mut rows = stmt.query([])?;
 
+        // column names are taken from the executed statement, so that empty results still have their columns
+        let column_names = rows.as_ref().map(|stmt| stmt.column_names()).unwrap_or_default();
         let mut ret = vec![];
-        let mut is_first = true;
-        let mut column_names = vec![];
-        while let Some(row) = rows.next().unwrap() {
-            if is_first {
-                column_names = row.as_ref().column_names();
-                is_first = false;
-            }
-
+        while let Some(row) = rows.next()? {
             let mut columns = Vec::with_capacity(column_names.len());
             for (i, col_name) in column_names.iter().enumerate() {
                 let value: ValueRef = row.get_ref(i)?;
@@ -88,11 +129,11 @@ impl Executor {
 }
 
 pub trait AsExecutor {
-    fn as_executor(&self) -> Executor;
+    fn as_executor(&self) -> Result<Executor, SqlError>;
 }
 
 impl AsExecutor for Ledger {
-    fn as_executor(&self) -> Executor {
+    fn as_executor(&self) -> Result<Executor, SqlError> {
         let table_definitions = vec![
             InMemoryOptions::as_table_definition(),
             AccountDomain::as_table_definition(),
@@ -105,55 +146,83 @@ impl AsExecutor for Ledger {
             PriceDomain::as_table_definition(),
             DocumentDomain::as_table_definition(),
             MetaDomain::as_table_definition(),
+            BudgetDomain::as_table_definition(),
+            BudgetIntervalRow::as_table_definition(),
+            BudgetEventRow::as_table_definition(),
+            ErrorDomain::as_table_definition(),
         ];
-        let executor = Executor::new(table_definitions);
-        self.options.insert_data(&executor.conn);
+        let executor = Executor::new(table_definitions)?;
         let store = self.store.read().unwrap();
-        for account in store.accounts.values() {
-            account.insert_data(&executor.conn);
-        }
-        for trx in store.transactions.values() {
-            trx.insert_data(&executor.conn);
-            for tag in &trx.tags {
-                TrxTag {
+
+        let tags = store
+            .transactions
+            .values()
+            .flat_map(|trx| {
+                trx.tags.iter().map(|tag| TrxTag {
                     trx_id: trx.id,
                     tag: tag.clone(),
-                }
-                .insert_data(&executor.conn);
-            }
-            for link in &trx.links {
-                TrxLink {
+                })
+            })
+            .collect_vec();
+        let links = store
+            .transactions
+            .values()
+            .flat_map(|trx| {
+                trx.links.iter().map(|link| TrxLink {
                     trx_id: trx.id,
                     link: link.clone(),
-                }
-                .insert_data(&executor.conn);
-            }
-        }
-        for posting in store.postings.iter() {
-            posting.insert_data(&executor.conn);
-        }
-        for commodity in store.commodities.values() {
-            commodity.insert_data(&executor.conn);
-        }
-        for price in store.prices.iter() {
-            price.insert_data(&executor.conn);
-        }
-        for (account, commodity_lots) in store.commodity_lots.iter() {
-            for commodity_lot in commodity_lots.iter() {
-                CommodityLotRow {
+                })
+            })
+            .collect_vec();
+        let commodity_lots = store
+            .commodity_lots
+            .iter()
+            .flat_map(|(account, lots)| {
+                lots.iter().map(|lot| CommodityLotRow {
                     account: account.clone(),
-                    lot: commodity_lot.clone(),
-                }
-                .insert_data(&executor.conn);
-            }
-        }
-        for document in store.documents.iter() {
-            document.insert_data(&executor.conn);
-        }
-        for meta in store.metas.iter() {
-            meta.insert_data(&executor.conn);
-        }
-        executor
+                    lot: lot.clone(),
+                })
+            })
+            .collect_vec();
+        let budget_intervals = store
+            .budgets
+            .values()
+            .flat_map(|budget| budget.detail.values().map(|detail| BudgetIntervalRow { budget: &budget.name, detail }))
+            .collect_vec();
+        let budget_events = store
+            .budgets
+            .values()
+            .flat_map(|budget| {
+                budget.detail.values().flat_map(|detail| {
+                    detail.events.iter().map(|event| BudgetEventRow {
+                        budget: &budget.name,
+                        interval_date: detail.date,
+                        event,
+                    })
+                })
+            })
+            .collect_vec();
+
+        executor.append([&self.options])?;
+        executor.append(store.accounts.values())?;
+        executor.append(store.transactions.values())?;
+        executor.append(&tags)?;
+        executor.append(&links)?;
+        executor.append(store.postings())?;
+        executor.append(store.commodities.values())?;
+        executor.append(&store.prices)?;
+        executor.append(&commodity_lots)?;
+        executor.append(&store.documents)?;
+        executor.append(&store.metas)?;
+        executor.append(store.budgets.values())?;
+        executor.append(&budget_intervals)?;
+        executor.append(&budget_events)?;
+        executor.append(&store.errors)?;
+        executor.conn.execute_batch(DAILY_BALANCES_VIEW)?;
+
+        // the executor is only used to query the loaded data, files and extensions are not reachable from sql
+        executor.conn.execute_batch("SET enable_external_access = false")?;
+        Ok(executor)
     }
 }
 
@@ -182,18 +251,119 @@ mod tests {
         let ledger = load_from_temp_str(indoc! {r#"
             option "title" "Accounting"
         "#});
-        let executor = ledger.as_executor();
+        let executor = ledger.as_executor().unwrap();
         let result = executor.execute("select * from options").unwrap();
         println!("{:?}", result);
         assert_eq!(result.rows.len(), 6);
     }
 
+    #[test]
+    fn should_execute_saved_query() {
+        let ledger = load_from_temp_str(indoc! {r#"
+            1970-01-01 open Assets:Cash
+            1970-01-01 open Assets:Bank
+            1970-01-01 query "assets" "select name from accounts order by name"
+        "#});
+        let operations = ledger.operations();
+        let query = operations.query("assets").unwrap().unwrap();
+        let executor = ledger.as_executor().unwrap();
+        let result = executor.execute(&query.query_string).unwrap();
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows[0].columns[0].value, serde_json::json!("Assets:Bank"));
+    }
+
+    #[test]
+    fn should_query_loaded_data_via_cloned_executor() {
+        let ledger = load_from_temp_str(indoc! {r#"
+            1970-01-01 open Assets:Cash
+            1970-01-01 open Assets:Bank
+        "#});
+        let executor = ledger.as_executor().unwrap().try_clone().unwrap();
+        let result = executor.execute_read_only("select name from accounts order by name").unwrap();
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows[0].columns[0].value, serde_json::json!("Assets:Bank"));
+    }
+
+    #[test]
+    fn should_fail_to_build_executor_given_amount_out_of_decimal_range() {
+        let ledger = load_from_temp_str(indoc! {r#"
+            1970-01-01 open Assets:Cash
+            1970-01-02 "KFC" "Lunch"
+              Assets:Cash -100000000000000000000000000000000000000000 CNY
+              Expenses:Food 100000000000000000000000000000000000000000 CNY
+        "#});
+        assert!(matches!(ledger.as_executor(), Err(SqlError::Execution(_))));
+    }
+
+    #[test]
+    fn should_reject_statements_modifying_data() {
+        let ledger = load_from_temp_str(indoc! {r#"
+            1970-01-01 open Assets:Cash
+            1970-01-02 "KFC" "Lunch" #food
+              Assets:Cash -50 CNY
+              Expenses:Food 50 CNY
+        "#});
+        let executor = ledger.as_executor().unwrap();
+        assert!(matches!(executor.execute_read_only("delete from accounts"), Err(SqlError::ReadOnly(_))));
+        assert!(matches!(
+            executor.execute_read_only("select * from read_csv('/etc/hosts')"),
+            Err(SqlError::Execution(_))
+        ));
+
+        let result = executor.execute_read_only("select tag from transaction_tags").unwrap();
+        assert_eq!(result.rows[0].columns[0].value, serde_json::json!("food"));
+        let result = executor.execute_read_only("select unit, trx_datetime from postings order by unit").unwrap();
+        assert_eq!(result.rows.len(), 2);
+    }
+
+    #[test]
+    fn should_query_budgets_errors_and_daily_balances() {
+        let ledger = load_from_temp_str(indoc! {r#"
+            1970-01-01 open Assets:Cash
+            1970-01-01 open Expenses:Food
+              budget: "Food"
+            2023-01-01 budget Food CNY
+            2023-01-01 budget-add Food 100 CNY
+            2023-01-02 "KFC" "Lunch"
+              Assets:Cash -50 CNY
+              Expenses:Food 50 CNY
+            2023-01-02 "KFC" "Dinner"
+              Assets:Cash -20.5 CNY
+              Expenses:Food 20.5 CNY
+            2023-01-03 balance Assets:Cash 10 CNY
+        "#});
+        let executor = ledger.as_executor().unwrap();
+
+        let result = executor
+            .execute("select assigned_amount, activity_amount, interval_date from budget_intervals where budget = 'Food'")
+            .unwrap();
+        assert_eq!(result.rows[0].columns[0].value, serde_json::json!("100.000"));
+        assert_eq!(result.rows[0].columns[1].value, serde_json::json!("70.500"));
+        assert_eq!(result.rows[0].columns[2].value, serde_json::json!(202301));
+        let result = executor.execute("select event_type, interval_date from budget_events").unwrap();
+        assert_eq!(result.rows[0].columns[0].value, serde_json::json!("AddAssignedAmount"));
+        assert_eq!(result.rows[0].columns[1].value, serde_json::json!(202301));
+
+        let result = executor.execute("select error_type, span_start is not null from errors").unwrap();
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0].columns[0].value, serde_json::json!("AccountBalanceCheckError"));
+        assert_eq!(result.rows[0].columns[1].value, serde_json::json!(true));
+
+        let result = executor
+            .execute("select date, balance from daily_balances where account = 'Assets:Cash' order by date")
+            .unwrap();
+        // balance check is recorded as a posting as well
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows[0].columns[0].value, serde_json::json!("2023-01-02"));
+        assert_eq!(result.rows[0].columns[1].value, serde_json::json!("-70.500"));
+    }
+
     #[test]
     fn it_works2() {
         let ledger = load_from_temp_str(indoc! {r#"
             1970-01-01 open Assets:Cash
         "#});
-        let executor = ledger.as_executor();
+        let executor = ledger.as_executor().unwrap();
         let result = executor.execute("select * from accounts").unwrap();
         println!("{:?}", result);
         assert_eq!(result.rows.len(), 1);
@@ -0,0 +1,165 @@
+/// keywords a read-only statement can start with
+const READ_ONLY_STATEMENTS: &[&str] = &["SELECT", "FROM", "VALUES", "TABLE", "DESCRIBE", "SHOW", "SUMMARIZE", "EXPLAIN"];
+
+enum Token {
+    Word(String),
+    Open,
+    Close,
+    Other,
+}
+
+/// check whether the sql is a single statement which only reads data, return the reason if it is rejected.
+///
+/// only the keyword deciding what the statement does is checked, and it is matched outside of string literals,
+/// quoted identifiers and comments, so `SELECT 'delete me' AS set` is still a read-only query.
+/// functions reaching files or network are disabled on the connection instead.
+pub(crate) fn check_read_only(sql: &str) -> Result<(), String> {
+    let mut tokens = vec![];
+    let mut statements = 0;
+    let mut has_content = false;
+
+    let mut chars = sql.chars().peekable();
+    let mut word = String::new();
+    while let Some(c) = chars.next() {
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+            continue;
+        }
+        if !word.is_empty() {
+            tokens.push(Token::Word(std::mem::take(&mut word).to_uppercase()));
+            has_content = true;
+        }
+        match c {
+            '\'' | '"' => {
+                // doubled quote is an escaped quote, which is consumed as two adjacent literals
+                for next in chars.by_ref() {
+                    if next == c {
+                        break;
+                    }
+                }
+                tokens.push(Token::Other);
+                has_content = true;
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                for next in chars.by_ref() {
+                    if next == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = None;
+                for next in chars.by_ref() {
+                    if previous == Some('*') && next == '/' {
+                        break;
+                    }
+                    previous = Some(next);
+                }
+            }
+            ';' => {
+                if has_content {
+                    statements += 1;
+                }
+                has_content = false;
+            }
+            c if c.is_whitespace() => {}
+            '(' => {
+                tokens.push(Token::Open);
+                has_content = true;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                has_content = true;
+            }
+            _ => {
+                tokens.push(Token::Other);
+                has_content = true;
+            }
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(Token::Word(word.to_uppercase()));
+        has_content = true;
+    }
+    if has_content {
+        statements += 1;
+    }
+
+    if statements != 1 {
+        return Err("only one statement can be executed at a time".to_owned());
+    }
+    match statement_keyword(&tokens) {
+        Some(keyword) if READ_ONLY_STATEMENTS.contains(&keyword) => Ok(()),
+        _ => Err("only read-only statements are allowed".to_owned()),
+    }
+}
+
+/// the keyword deciding what the statement does, like `INSERT` of `WITH a AS (SELECT 1) INSERT INTO ...`
+fn statement_keyword(tokens: &[Token]) -> Option<&str> {
+    let mut rest = tokens;
+    // `EXPLAIN ANALYZE` runs the explained statement, so the explained one decides instead
+    loop {
+        match rest.first()? {
+            Token::Word(word) if word == "EXPLAIN" || word == "ANALYZE" => rest = &rest[1..],
+            Token::Open => rest = &rest[1..],
+            _ => break,
+        }
+    }
+    let Token::Word(first) = rest.first()? else {
+        return None;
+    };
+    if first != "WITH" {
+        return Some(first);
+    }
+    // the main statement follows the closing parenthesis of the last common table expression
+    let mut depth = 0;
+    let mut after_close = false;
+    for token in &rest[1..] {
+        match token {
+            Token::Open => depth += 1,
+            Token::Close => {
+                depth -= 1;
+                after_close = depth == 0;
+                continue;
+            }
+            Token::Word(word) if depth == 0 && after_close && word != "AS" => return Some(word),
+            _ => {}
+        }
+        after_close = false;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_read_only;
+
+    #[test]
+    fn should_accept_read_only_statements() {
+        assert!(check_read_only("select * from accounts").is_ok());
+        assert!(check_read_only("  SELECT name FROM accounts;  ").is_ok());
+        assert!(check_read_only("with a as (select 1) select * from a").is_ok());
+        assert!(check_read_only("select 'delete; drop table accounts' as \"update\" -- insert\n from accounts").is_ok());
+        assert!(check_read_only("/* create */ describe postings").is_ok());
+        assert!(check_read_only("SELECT count(*) AS set FROM postings").is_ok());
+        assert!(check_read_only("select 1 as load, 2 as commit, 3 as use").is_ok());
+        assert!(check_read_only("with recursive a(n) as not materialized (select 1), b as (select 2) select * from a, b").is_ok());
+        assert!(check_read_only("(select 1) union (select 2)").is_ok());
+    }
+
+    #[test]
+    fn should_reject_modifying_statements() {
+        assert!(check_read_only("delete from accounts").is_err());
+        assert!(check_read_only("drop table accounts").is_err());
+        assert!(check_read_only("select 1; delete from accounts").is_err());
+        assert!(check_read_only("with a as (select 1) insert into accounts select * from a").is_err());
+        assert!(check_read_only("copy accounts to 'accounts.csv'").is_err());
+        assert!(check_read_only("attach 'other.db'").is_err());
+        assert!(check_read_only("with a as (select 1), b as (select 2) delete from accounts").is_err());
+        assert!(check_read_only("explain analyze delete from accounts").is_err());
+        assert!(check_read_only("set enable_external_access = true").is_err());
+        assert!(check_read_only("").is_err());
+        assert!(check_read_only(" ; ").is_err());
+    }
+}
@@ -1,25 +1,30 @@
-use duckdb::{Connection, ToSql};
+use duckdb::{Appender, ToSql};
 use itertools::Itertools;
 use uuid::Uuid;
 use zhang_core::constants::*;
-use zhang_core::domains::schemas::{AccountDomain, CommodityDomain, MetaDomain, PriceDomain};
+use zhang_core::domains::schemas::{AccountDomain, CommodityDomain, ErrorDomain, MetaDomain, PriceDomain};
 use zhang_core::options::InMemoryOptions;
-use zhang_core::store::{CommodityLotRecord, DocumentDomain, DocumentType, PostingDomain, TransactionDomain};
+use zhang_core::store::{
+    BudgetDomain, BudgetEvent, BudgetEventType, BudgetIntervalDetail, CommodityLotRecord, DocumentDomain, DocumentType, PostingDomain, TransactionDomain,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ColumnDefinition {
     name: String,
     data_type: ColumnType,
     nullable: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ColumnType {
     Uuid,
     String,
     Int,
     Decimal,
     Date,
+    /// calendar date without time
+    Day,
+    Boolean,
 }
 
 impl ColumnDefinition {
@@ -44,11 +49,13 @@ impl ColumnDefinition {
             ColumnType::Int => "INTEGER".to_string(),
             ColumnType::Date => "TIMESTAMPTZ".to_string(),
             ColumnType::Decimal => "DECIMAL".to_string(),
+            ColumnType::Day => "DATE".to_string(),
+            ColumnType::Boolean => "BOOLEAN".to_string(),
         };
         if self.nullable { format!("{} NULL", ret) } else { ret }
     }
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TableDefinition {
     name: &'static str,
     columns: Vec<ColumnDefinition>,
@@ -62,19 +69,15 @@ impl TableDefinition {
             self.columns.iter().map(|c| format!("{} {}", c.name, c.as_sql_type())).join(", ")
         )
     }
-    pub fn as_insert_sql(&self) -> String {
-        format!(
-            "INSERT INTO {} ({}) VALUES ({})",
-            self.name,
-            self.columns.iter().map(|c| c.name.clone()).join(", "),
-            self.columns.iter().map(|_| "?".to_string()).join(", ")
-        )
+    pub fn name(&self) -> &'static str {
+        self.name
     }
 }
 
 pub trait AsTableDefinition {
     fn as_table_definition() -> TableDefinition;
-    fn insert_data(&self, conn: &Connection);
+    /// append the row(s) of this entity via duckdb's appender, which is much faster than inserting row by row
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()>;
 }
 
 impl AsTableDefinition for InMemoryOptions {
@@ -87,39 +90,32 @@ impl AsTableDefinition for InMemoryOptions {
             ],
         }
     }
-    fn insert_data(&self, conn: &Connection) {
-        let table_definition = Self::as_table_definition();
-        let mut stmt = conn.prepare(table_definition.as_insert_sql().as_str()).unwrap();
-        stmt.execute([
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
+        appender.append_row([
             serde_json::to_string(KEY_OPERATING_CURRENCY).unwrap(),
             serde_json::to_string(&self.operating_currency).unwrap(),
-        ])
-        .unwrap();
-        stmt.execute([
+        ])?;
+        appender.append_row([
             serde_json::to_string(KEY_DEFAULT_ROUNDING).unwrap(),
             serde_json::to_string(&self.default_rounding).unwrap(),
-        ])
-        .unwrap();
-        stmt.execute([
+        ])?;
+        appender.append_row([
             serde_json::to_string(KEY_DEFAULT_BALANCE_TOLERANCE_PRECISION).unwrap(),
             serde_json::to_string(&self.default_balance_tolerance_precision).unwrap(),
-        ])
-        .unwrap();
-        stmt.execute([
+        ])?;
+        appender.append_row([
             serde_json::to_string(KEY_DEFAULT_BOOKING_METHOD).unwrap(),
             serde_json::to_string(&self.default_booking_method).unwrap(),
-        ])
-        .unwrap();
-        stmt.execute([
+        ])?;
+        appender.append_row([
             serde_json::to_string(KEY_TIMEZONE).unwrap(),
             serde_json::to_string(&self.timezone.to_string()).unwrap(),
-        ])
-        .unwrap();
-        stmt.execute([
+        ])?;
+        appender.append_row([
             serde_json::to_string(KEY_DIRECTIVE_OUTPUT_PATH).unwrap(),
             serde_json::to_string(&self.directive_output_path).unwrap(),
-        ])
-        .unwrap();
+        ])?;
+        Ok(())
     }
 }
 
@@ -136,11 +132,9 @@ impl AsTableDefinition for AccountDomain {
             ],
         }
     }
-    fn insert_data(&self, conn: &Connection) {
-        let table_definition = Self::as_table_definition();
-        let mut stmt = conn.prepare(table_definition.as_insert_sql().as_str()).unwrap();
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
         let params: &[&dyn ToSql] = &[&self.date, &self.r#type, &self.name, &self.status.as_ref(), &self.alias.clone()];
-        stmt.execute(params).unwrap();
+        appender.append_row(params)
     }
 }
 
@@ -158,9 +152,7 @@ impl AsTableDefinition for TransactionDomain {
             ],
         }
     }
-    fn insert_data(&self, conn: &Connection) {
-        let table_definition = Self::as_table_definition();
-        let mut stmt = conn.prepare(table_definition.as_insert_sql().as_str()).unwrap();
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
         let params: &[&dyn ToSql] = &[
             &self.id,
             &self.sequence,
@@ -169,7 +161,7 @@ impl AsTableDefinition for TransactionDomain {
             &self.payee.clone(),
             &self.narration.clone(),
         ];
-        stmt.execute(params).unwrap();
+        appender.append_row(params)
     }
 }
 
@@ -182,6 +174,7 @@ impl AsTableDefinition for PostingDomain {
                 ColumnDefinition::new("trx_id", ColumnType::Uuid),
                 ColumnDefinition::new("trx_sequence", ColumnType::Int),
                 ColumnDefinition::new("trx_datetime", ColumnType::Date),
+                ColumnDefinition::new("trx_date", ColumnType::Day),
                 ColumnDefinition::new("account", ColumnType::String),
                 ColumnDefinition::new("unit", ColumnType::Decimal).into_nullable(),
                 ColumnDefinition::new("unit_commodity", ColumnType::String).into_nullable(),
@@ -196,14 +189,14 @@ impl AsTableDefinition for PostingDomain {
             ],
         }
     }
-    fn insert_data(&self, conn: &Connection) {
-        let table_definition = Self::as_table_definition();
-        let mut stmt = conn.prepare(table_definition.as_insert_sql().as_str()).unwrap();
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
         let params: &[&dyn ToSql] = &[
             &self.id,
             &self.trx_id,
             &self.trx_sequence,
             &self.trx_datetime,
+            // the date in ledger's timezone, which duckdb cannot derive from the timestamp
+            &self.trx_datetime.date_naive(),
             &self.account.name(),
             &self.unit.as_ref().map(|u| u.number.to_string()),
             &self.unit.as_ref().map(|u| u.commodity.clone()),
@@ -216,7 +209,7 @@ impl AsTableDefinition for PostingDomain {
             &self.after_amount.number.to_string(),
             &self.after_amount.commodity,
         ];
-        stmt.execute(params).unwrap();
+        appender.append_row(params)
     }
 }
 
@@ -235,11 +228,9 @@ impl AsTableDefinition for TrxTag {
             ],
         }
     }
-    fn insert_data(&self, conn: &Connection) {
-        let table_definition = Self::as_table_definition();
-        let mut stmt = conn.prepare(table_definition.as_insert_sql().as_str()).unwrap();
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
         let params: &[&dyn ToSql] = &[&self.trx_id, &self.tag];
-        stmt.execute(params).unwrap();
+        appender.append_row(params)
     }
 }
 
@@ -258,11 +249,9 @@ impl AsTableDefinition for TrxLink {
             ],
         }
     }
-    fn insert_data(&self, conn: &Connection) {
-        let table_definition = Self::as_table_definition();
-        let mut stmt = conn.prepare(table_definition.as_insert_sql().as_str()).unwrap();
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
         let params: &[&dyn ToSql] = &[&self.trx_id, &self.link];
-        stmt.execute(params).unwrap();
+        appender.append_row(params)
     }
 }
 
@@ -279,9 +268,7 @@ impl AsTableDefinition for CommodityDomain {
             ],
         }
     }
-    fn insert_data(&self, conn: &Connection) {
-        let table_definition = Self::as_table_definition();
-        let mut stmt = conn.prepare(table_definition.as_insert_sql().as_str()).unwrap();
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
         let params: &[&dyn ToSql] = &[
             &self.name,
             &self.precision,
@@ -289,7 +276,7 @@ impl AsTableDefinition for CommodityDomain {
             &self.suffix.clone(),
             &self.rounding.to_string(),
         ];
-        stmt.execute(params).unwrap();
+        appender.append_row(params)
     }
 }
 
@@ -305,11 +292,9 @@ impl AsTableDefinition for PriceDomain {
             ],
         }
     }
-    fn insert_data(&self, conn: &Connection) {
-        let table_definition = Self::as_table_definition();
-        let mut stmt = conn.prepare(table_definition.as_insert_sql().as_str()).unwrap();
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
         let params: &[&dyn ToSql] = &[&self.datetime, &self.commodity, &self.amount.to_string(), &self.target_commodity];
-        stmt.execute(params).unwrap();
+        appender.append_row(params)
     }
 }
 
@@ -332,9 +317,7 @@ impl AsTableDefinition for CommodityLotRow {
             ],
         }
     }
-    fn insert_data(&self, conn: &Connection) {
-        let table_definition = Self::as_table_definition();
-        let mut stmt = conn.prepare(table_definition.as_insert_sql().as_str()).unwrap();
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
         let params: &[&dyn ToSql] = &[
             &self.account,
             &self.lot.commodity,
@@ -343,7 +326,7 @@ impl AsTableDefinition for CommodityLotRow {
             &self.lot.cost.as_ref().map(|c| c.commodity.clone()),
             &self.lot.acquisition_date,
         ];
-        stmt.execute(params).unwrap();
+        appender.append_row(params)
     }
 }
 
@@ -360,9 +343,7 @@ impl AsTableDefinition for DocumentDomain {
             ],
         }
     }
-    fn insert_data(&self, conn: &Connection) {
-        let table_definition = Self::as_table_definition();
-        let mut stmt = conn.prepare(table_definition.as_insert_sql().as_str()).unwrap();
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
         let document_type = match &self.document_type {
             DocumentType::Trx(_) => "trx",
             DocumentType::Account(_) => "account",
@@ -372,7 +353,7 @@ impl AsTableDefinition for DocumentDomain {
             DocumentType::Account(account) => account.name().to_string(),
         };
         let params: &[&dyn ToSql] = &[&self.datetime, &document_type, &document_id, &self.filename.clone(), &self.path];
-        stmt.execute(params).unwrap();
+        appender.append_row(params)
     }
 }
 
@@ -388,10 +369,141 @@ impl AsTableDefinition for MetaDomain {
             ],
         }
     }
-    fn insert_data(&self, conn: &Connection) {
-        let table_definition = Self::as_table_definition();
-        let mut stmt = conn.prepare(table_definition.as_insert_sql().as_str()).unwrap();
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
         let params: &[&dyn ToSql] = &[&self.meta_type, &self.type_identifier, &self.key, &self.value];
-        stmt.execute(params).unwrap();
+        appender.append_row(params)
+    }
+}
+
+impl AsTableDefinition for BudgetDomain {
+    fn as_table_definition() -> TableDefinition {
+        TableDefinition {
+            name: "budgets",
+            columns: vec![
+                ColumnDefinition::new("name", ColumnType::String),
+                ColumnDefinition::new("alias", ColumnType::String).into_nullable(),
+                ColumnDefinition::new("category", ColumnType::String).into_nullable(),
+                ColumnDefinition::new("commodity", ColumnType::String),
+                ColumnDefinition::new("closed", ColumnType::Boolean),
+            ],
+        }
+    }
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
+        let params: &[&dyn ToSql] = &[&self.name, &self.alias, &self.category, &self.commodity, &self.closed];
+        appender.append_row(params)
+    }
+}
+
+pub struct BudgetIntervalRow<'a> {
+    pub budget: &'a str,
+    pub detail: &'a BudgetIntervalDetail,
+}
+
+impl AsTableDefinition for BudgetIntervalRow<'_> {
+    fn as_table_definition() -> TableDefinition {
+        TableDefinition {
+            name: "budget_intervals",
+            columns: vec![
+                ColumnDefinition::new("budget", ColumnType::String),
+                ColumnDefinition::new("interval_date", ColumnType::Int),
+                ColumnDefinition::new("assigned_amount", ColumnType::Decimal),
+                ColumnDefinition::new("activity_amount", ColumnType::Decimal),
+                ColumnDefinition::new("commodity", ColumnType::String),
+            ],
+        }
+    }
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
+        let params: &[&dyn ToSql] = &[
+            &self.budget,
+            &self.detail.date,
+            &self.detail.assigned_amount.number.to_string(),
+            &self.detail.activity_amount.number.to_string(),
+            &self.detail.assigned_amount.commodity,
+        ];
+        appender.append_row(params)
+    }
+}
+
+pub struct BudgetEventRow<'a> {
+    pub budget: &'a str,
+    /// year and month pair of the interval, E.G. `202312`
+    pub interval_date: u32,
+    pub event: &'a BudgetEvent,
+}
+
+impl AsTableDefinition for BudgetEventRow<'_> {
+    fn as_table_definition() -> TableDefinition {
+        TableDefinition {
+            name: "budget_events",
+            columns: vec![
+                ColumnDefinition::new("budget", ColumnType::String),
+                ColumnDefinition::new("interval_date", ColumnType::Int),
+                ColumnDefinition::new("datetime", ColumnType::Date),
+                ColumnDefinition::new("event_type", ColumnType::String),
+                ColumnDefinition::new("amount", ColumnType::Decimal),
+                ColumnDefinition::new("commodity", ColumnType::String),
+            ],
+        }
+    }
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
+        let event_type = match self.event.event_type {
+            BudgetEventType::AddAssignedAmount => "AddAssignedAmount",
+            BudgetEventType::Transfer => "Transfer",
+        };
+        let params: &[&dyn ToSql] = &[
+            &self.budget,
+            &self.interval_date,
+            &self.event.datetime,
+            &event_type,
+            &self.event.amount.number.to_string(),
+            &self.event.amount.commodity,
+        ];
+        appender.append_row(params)
     }
 }
+
+impl AsTableDefinition for ErrorDomain {
+    fn as_table_definition() -> TableDefinition {
+        TableDefinition {
+            name: "errors",
+            columns: vec![
+                ColumnDefinition::new("id", ColumnType::String),
+                ColumnDefinition::new("error_type", ColumnType::String),
+                ColumnDefinition::new("filename", ColumnType::String).into_nullable(),
+                ColumnDefinition::new("span_start", ColumnType::Int).into_nullable(),
+                ColumnDefinition::new("span_end", ColumnType::Int).into_nullable(),
+                ColumnDefinition::new("content", ColumnType::String).into_nullable(),
+                ColumnDefinition::new("metas", ColumnType::String),
+            ],
+        }
+    }
+    fn append_data(&self, appender: &mut Appender) -> duckdb::Result<()> {
+        let filename = self
+            .span
+            .as_ref()
+            .and_then(|span| span.filename.as_ref())
+            .map(|filename| filename.to_string_lossy().to_string());
+        let params: &[&dyn ToSql] = &[
+            &self.id,
+            &self.error_type.to_string(),
+            &filename,
+            &self.span.as_ref().map(|span| span.start as u64),
+            &self.span.as_ref().map(|span| span.end as u64),
+            &self.span.as_ref().map(|span| span.content.clone()),
+            &serde_json::to_string(&self.metas).unwrap(),
+        ];
+        appender.append_row(params)
+    }
+}
+
+/// the balance of each account and commodity at the end of every day it changes,
+/// picked from the last processed posting of the day
+pub const DAILY_BALANCES_VIEW: &str = r#"
+CREATE VIEW daily_balances AS
+SELECT trx_date AS date, account, after_amount_commodity AS commodity, after_amount AS balance
+FROM (
+    SELECT *, row_number() OVER (PARTITION BY account, after_amount_commodity, trx_date ORDER BY rowid DESC) AS seq
+    FROM postings
+)
+WHERE seq = 1
+"#;